@@ -8,7 +8,7 @@
 //! ```
 
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use shardmap::ShardMapBuilder;
+use shardmap::{ShardMap, ShardMapBuilder};
 use std::sync::Arc;
 use std::thread;
 
@@ -69,6 +69,71 @@ fn bench_get(c: &mut Criterion) {
     group.finish();
 }
 
+/// Integer values stored behind an `Arc` (ShardMap) vs inline (CopyShardMap).
+fn bench_inline_values(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inline_values");
+
+    let arc_map = ShardMapBuilder::new().build::<usize, u64>().unwrap();
+    let inline_map = ShardMapBuilder::new()
+        .inline_values()
+        .build::<usize, u64>()
+        .unwrap();
+
+    group.bench_function("insert/arc", |b| {
+        b.iter(|| {
+            for i in 0..1000 {
+                arc_map.insert(black_box(i), i as u64);
+            }
+        });
+    });
+    group.bench_function("insert/inline", |b| {
+        b.iter(|| {
+            for i in 0..1000 {
+                inline_map.insert(black_box(i), i as u64);
+            }
+        });
+    });
+    group.bench_function("get/arc", |b| {
+        b.iter(|| {
+            for i in 0..1000 {
+                black_box(arc_map.get(&i).map(|v| *v));
+            }
+        });
+    });
+    group.bench_function("get/inline", |b| {
+        b.iter(|| {
+            for i in 0..1000 {
+                black_box(inline_map.get(&i));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+/// Loading a known dataset: presized from_pairs vs building empty then overwrite_many.
+fn bench_bulk_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bulk_load");
+
+    let pairs: Vec<(usize, usize)> = (0..100_000).map(|i| (i, i)).collect();
+    group.bench_function("from_pairs", |b| {
+        b.iter(|| black_box(ShardMap::from_pairs(pairs.clone(), 16).unwrap()));
+    });
+    group.bench_function("build_then_overwrite_many", |b| {
+        b.iter(|| {
+            let map = ShardMapBuilder::new()
+                .shard_count(16)
+                .unwrap()
+                .build::<usize, usize>()
+                .unwrap();
+            map.overwrite_many(pairs.clone());
+            black_box(map)
+        });
+    });
+
+    group.finish();
+}
+
 /// get_by_hash vs get: when caller already has a hash, get_by_hash skips shard hashing.
 fn bench_get_by_hash(c: &mut Criterion) {
     let mut group = c.benchmark_group("get_by_hash");
@@ -104,6 +169,37 @@ fn bench_get_by_hash(c: &mut Criterion) {
     group.finish();
 }
 
+/// route_u32_batch vs per-key shard_for_key for small integer keys.
+fn bench_route_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("route_batch");
+
+    let map = ShardMapBuilder::new()
+        .shard_count(16)
+        .unwrap()
+        .build::<u32, u32>()
+        .unwrap();
+    let keys: Vec<u32> = (0..4096).collect();
+    let mut out = vec![0usize; keys.len()];
+
+    group.bench_function("shard_for_key", |b| {
+        b.iter(|| {
+            for (key, slot) in keys.iter().zip(out.iter_mut()) {
+                *slot = map.shard_for_key(key);
+            }
+            black_box(&out);
+        });
+    });
+
+    group.bench_function("route_u32_batch", |b| {
+        b.iter(|| {
+            map.route_u32_batch(black_box(&keys), &mut out);
+            black_box(&out);
+        });
+    });
+
+    group.finish();
+}
+
 fn bench_concurrent_insert(c: &mut Criterion) {
     let mut group = c.benchmark_group("concurrent_insert");
 
@@ -208,7 +304,10 @@ criterion_group!(
     benches,
     bench_insert,
     bench_get,
+    bench_inline_values,
+    bench_bulk_load,
     bench_get_by_hash,
+    bench_route_batch,
     bench_concurrent_insert,
     bench_mixed_workload
 );
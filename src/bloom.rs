@@ -0,0 +1,104 @@
+//! Bloom filter over a map's keys, for skipping definite misses without a shard lock.
+
+use crate::hash::ShardHasher;
+use crate::shardmap::KeyNormalizer;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// A point-in-time membership filter over a map's keys, from
+/// [`ShardMap::build_bloom`](crate::ShardMap::build_bloom).
+///
+/// `might_contain` never returns `false` for a key that was in the map when the filter was
+/// built, and returns `true` for other keys at roughly the requested false-positive rate.
+/// The filter is not updated afterwards: keys inserted later are reported as absent, and
+/// removed keys keep testing positive. Rebuild it when the key set changes.
+///
+/// Keys are hashed with the map's own (shared) hasher, so a fixed-seed map builds the same
+/// filter on every run. The filter keeps the map's key normalizer, if it has one, and runs
+/// it on every key passed to `might_contain`, so `"Foo"` tests positive wherever the map
+/// would find it.
+pub struct BloomFilter<K> {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    hasher: Arc<ShardHasher>,
+    normalizer: Option<KeyNormalizer<K>>,
+    _key: PhantomData<fn(&K)>,
+}
+
+impl<K: Hash> BloomFilter<K> {
+    /// An empty filter sized for `expected` keys at `false_positive_rate`, for a map with
+    /// `normalizer`.
+    pub(crate) fn with_rate(
+        expected: usize,
+        false_positive_rate: f64,
+        hasher: Arc<ShardHasher>,
+        normalizer: Option<KeyNormalizer<K>>,
+    ) -> Self {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "build_bloom: false_positive_rate must be in (0, 1)"
+        );
+        let n = expected.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 32.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            hasher,
+            normalizer,
+            _key: PhantomData,
+        }
+    }
+
+    /// The `i`-th bit position for `key` is `(hash + i * step) % num_bits` (double hashing
+    /// over the map's 64-bit key hash, with an odd step derived from the same hash).
+    fn probe(hash: u64) -> (u64, u64) {
+        (hash, splitmix64(hash) | 1)
+    }
+
+    /// Add a key as stored in the map, i.e. already normalized.
+    pub(crate) fn insert(&mut self, key: &K) {
+        let (hash, step) = Self::probe(self.hasher.hash_key(key));
+        for i in 0..u64::from(self.num_hashes) {
+            let bit = hash.wrapping_add(i.wrapping_mul(step)) % self.num_bits;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// `false` if `key` was definitely not in the map when the filter was built; `true` if
+    /// it may have been.
+    pub fn might_contain(&self, key: &K) -> bool {
+        let hash = match &self.normalizer {
+            Some(normalize) => self.hasher.hash_key(&normalize(key)),
+            None => self.hasher.hash_key(key),
+        };
+        let (hash, step) = Self::probe(hash);
+        (0..u64::from(self.num_hashes)).all(|i| {
+            let bit = hash.wrapping_add(i.wrapping_mul(step)) % self.num_bits;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// Size of the bit array.
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// Number of bits set (and checked) per key.
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
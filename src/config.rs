@@ -1,5 +1,12 @@
 use crate::error::Error;
-use crate::hash::ShardHasher;
+use crate::hash::{seeded_ahash, ShardHasher};
+use crate::shardmap::KeyNormalizer;
+use crate::write_through::{WriteThrough, WriteThroughHook, WriteThroughMode};
+use std::marker::PhantomData;
+
+/// Seed for every hasher of a map built with [`ShardMapBuilder::deterministic`].
+#[cfg(any(test, feature = "deterministic"))]
+const DETERMINISTIC_SEED: u128 = 0x5eed_5a4d_3a90;
 
 /// Which hash function to use for shard assignment.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -16,6 +23,23 @@ pub enum HashFunction {
 pub trait ShardRouter: Send + Sync {
     /// Return the shard index in `[0, shard_count)` for the given key hash.
     fn route(&self, key_hash: u64, shard_count: usize) -> usize;
+
+    /// Expose this router's load-feedback hook, if it has one.
+    ///
+    /// Routers that also implement [`Rebalancer`] override this to return `Some(self)` so
+    /// [`ShardMap::feed_loads_to_router`](crate::ShardMap::feed_loads_to_router) can reach them.
+    fn as_rebalancer(&self) -> Option<&dyn Rebalancer> {
+        None
+    }
+}
+
+/// Load feedback for stateful routers (e.g. power-of-two-choices).
+///
+/// The map never changes routing on its own; it only reports observed per-shard entry counts
+/// when asked, and the router may adjust its internal weights for future keys.
+pub trait Rebalancer: Send + Sync {
+    /// Receive the current per-shard entry counts, indexed by shard.
+    fn advise(&self, loads: &[usize]);
 }
 
 /// Default routing: `(hash as usize) & (shard_count - 1)`.
@@ -39,6 +63,52 @@ pub enum RoutingConfig {
     Custom(Box<dyn ShardRouter>),
 }
 
+impl RoutingConfig {
+    /// Route with a plain closure `(key_hash, shard_count) -> shard index` instead of a
+    /// hand-written [`ShardRouter`] impl. The closure must return an index in
+    /// `[0, shard_count)`.
+    pub fn from_fn<F>(f: F) -> Self
+    where
+        F: Fn(u64, usize) -> usize + Send + Sync + 'static,
+    {
+        RoutingConfig::Custom(Box::new(FnRouter(f)))
+    }
+}
+
+/// Adapts a closure to [`ShardRouter`] for [`RoutingConfig::from_fn`].
+struct FnRouter<F>(F);
+
+impl<F> ShardRouter for FnRouter<F>
+where
+    F: Fn(u64, usize) -> usize + Send + Sync,
+{
+    #[inline]
+    fn route(&self, key_hash: u64, shard_count: usize) -> usize {
+        (self.0)(key_hash, shard_count)
+    }
+}
+
+/// Routes one half of a map split by [`ShardMap::split_shards`](crate::ShardMap::split_shards).
+///
+/// Keys keep the shard the unsplit map routed them to, shifted down by `start`. Keys the
+/// unsplit map routed to the other half fall back to `index % shard_count`.
+pub(crate) struct SplitRouter {
+    pub(crate) inner: Router,
+    pub(crate) total: usize,
+    pub(crate) start: usize,
+}
+
+impl ShardRouter for SplitRouter {
+    #[inline]
+    fn route(&self, key_hash: u64, shard_count: usize) -> usize {
+        let index = self.inner.route(key_hash, self.total);
+        match index.checked_sub(self.start) {
+            Some(local) if local < shard_count => local,
+            _ => index % shard_count,
+        }
+    }
+}
+
 impl std::fmt::Debug for RoutingConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -48,6 +118,148 @@ impl std::fmt::Debug for RoutingConfig {
     }
 }
 
+/// Which routing stage a map applies after hashing; see [`RoutingDescriptor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingKind {
+    /// The default router: `(hash as usize) & (shard_count - 1)`.
+    Mask,
+    /// A user-provided [`ShardRouter`]; its decisions can't be reproduced from a descriptor.
+    Custom,
+}
+
+/// Everything needed to reproduce a map's key→shard routing outside the process, e.g. for a
+/// coordinator forwarding requests to the node that owns a shard.
+///
+/// For [`RoutingKind::Mask`], routing a key is: hash it with `hash_function`, then mask the
+/// hash with `shard_count - 1`. With a seed, the `AHash` hash is
+/// `ahash::RandomState::with_seeds(lo, hi, !lo, !hi)`, where `lo` and `hi` are the low and
+/// high 64 bits of the seed. Without one, ahash keys are generated per process, so only maps
+/// built with [`ShardMapBuilder::fixed_seed`] (or with `FxHash`) can be reproduced elsewhere.
+/// The same stability caveats as `fixed_seed` apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutingDescriptor {
+    /// Number of shards.
+    pub shard_count: usize,
+    /// Hash function applied to keys.
+    pub hash_function: HashFunction,
+    /// Fixed ahash seed, if one was configured.
+    pub seed: Option<u128>,
+    /// Router applied to the hash.
+    pub routing_kind: RoutingKind,
+}
+
+impl RoutingDescriptor {
+    /// Whether routing can be reproduced in another process from this descriptor: false for
+    /// custom routers and for `AHash` without a fixed seed.
+    pub fn is_reproducible(&self) -> bool {
+        self.routing_kind == RoutingKind::Mask
+            && (self.seed.is_some() || self.hash_function != HashFunction::AHash)
+    }
+}
+
+/// What a bounded map does when inserting a new key into a full shard.
+///
+/// See [`ShardMapBuilder::max_entries_per_shard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnFull {
+    /// Refuse the insert in the `Result`-returning inserts: `checked_insert`,
+    /// `try_insert_all`, `rename` and `migrate_key` fail with `Error::ShardFull`. Every other
+    /// inserting method evicts like `EvictOldest`, so none of them panic.
+    Reject,
+    /// Remove the shard's oldest entry (by insertion order) to make room. Overwriting an
+    /// existing key does not change its position; reads don't either, so this is
+    /// insertion-order eviction, not LRU. The default.
+    #[default]
+    EvictOldest,
+    /// Remove the shard's least recently used entry to make room. `get` and `insert` (and
+    /// `update`, `get_or_insert*` hits) mark an entry as used. Requires the `lru` feature.
+    ///
+    /// Trade-off: `get` still takes only the shard read lock, but a hit also takes a
+    /// per-shard mutex to bump recency, so concurrent reads on one shard briefly serialize.
+    #[cfg(feature = "lru")]
+    EvictLeastRecent,
+}
+
+/// Per-shard entry bound, resolved from `Config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ShardBound {
+    pub(crate) max_entries: usize,
+    pub(crate) on_full: OnFull,
+}
+
+/// Resolved routing held by a map. Custom routers are shared so sibling maps
+/// (e.g. from `partition`) can reuse them.
+#[derive(Clone, Default)]
+pub(crate) enum Router {
+    #[default]
+    Default,
+    Custom(std::sync::Arc<dyn ShardRouter>),
+}
+
+impl Router {
+    #[inline]
+    pub(crate) fn route(&self, key_hash: u64, shard_count: usize) -> usize {
+        match self {
+            Router::Default => DefaultRouter.route(key_hash, shard_count),
+            Router::Custom(router) => router.route(key_hash, shard_count),
+        }
+    }
+
+    /// Whether both routers are known to make the same decisions (both default, or the same
+    /// shared custom router).
+    pub(crate) fn same_as(&self, other: &Router) -> bool {
+        match (self, other) {
+            (Router::Default, Router::Default) => true,
+            (Router::Custom(a), Router::Custom(b)) => std::sync::Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn kind(&self) -> RoutingKind {
+        match self {
+            Router::Default => RoutingKind::Mask,
+            Router::Custom(_) => RoutingKind::Custom,
+        }
+    }
+
+    pub(crate) fn as_rebalancer(&self) -> Option<&dyn Rebalancer> {
+        match self {
+            Router::Default => None,
+            Router::Custom(router) => router.as_rebalancer(),
+        }
+    }
+}
+
+impl From<RoutingConfig> for Router {
+    fn from(routing: RoutingConfig) -> Self {
+        match routing {
+            RoutingConfig::Default => Router::Default,
+            RoutingConfig::Custom(router) => Router::Custom(router.into()),
+        }
+    }
+}
+
+/// Whether `n` is accepted by [`Config::shard_count`]: a power of two greater than 0.
+pub fn valid_shard_count(n: usize) -> bool {
+    n.is_power_of_two()
+}
+
+/// Round `n` up to the nearest valid shard count (the next power of two, at least 1), to
+/// sanitize a dynamically derived value before calling `shard_count`. Values above the
+/// largest power of two a `usize` holds round down to it.
+///
+/// ```rust
+/// use shardmap::config::round_shard_count;
+///
+/// assert_eq!(round_shard_count(0), 1);
+/// assert_eq!(round_shard_count(12), 16);
+/// ```
+pub fn round_shard_count(n: usize) -> usize {
+    n.max(1)
+        .checked_next_power_of_two()
+        .unwrap_or(1 << (usize::BITS - 1))
+}
+
 /// Configuration for a ShardMap instance.
 #[derive(Debug)]
 pub struct Config {
@@ -55,6 +267,11 @@ pub struct Config {
     pub(crate) hash_function: HashFunction,
     pub(crate) capacity_per_shard: Option<usize>,
     pub(crate) routing: RoutingConfig,
+    pub(crate) seed: Option<u128>,
+    pub(crate) max_entries_per_shard: Option<usize>,
+    pub(crate) on_full: OnFull,
+    pub(crate) table_seed: Option<u128>,
+    pub(crate) shared_hasher: Option<std::sync::Arc<ShardHasher>>,
 }
 
 impl Config {
@@ -65,7 +282,7 @@ impl Config {
 
     /// Set the number of shards. Must be a power of two and greater than 0.
     pub fn shard_count(mut self, count: usize) -> Result<Self, Error> {
-        if count == 0 || !count.is_power_of_two() {
+        if !valid_shard_count(count) {
             return Err(Error::InvalidShardCount);
         }
         self.shard_count = count;
@@ -84,6 +301,49 @@ impl Config {
         self.capacity_per_shard = Some(capacity);
         self
     }
+
+    /// Pin the hasher keys to `seed` so `hash_for_key` is reproducible across runs.
+    /// Only affects `HashFunction::AHash` (FxHash is unseeded).
+    pub fn fixed_seed(mut self, seed: u128) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Fix every hasher, for routing and inside shards, so iteration order is reproducible.
+    /// See [`ShardMapBuilder::deterministic`]; not for production use.
+    #[cfg(any(test, feature = "deterministic"))]
+    pub fn deterministic(mut self) -> Self {
+        self.seed = Some(DETERMINISTIC_SEED);
+        self.table_seed = Some(DETERMINISTIC_SEED);
+        self
+    }
+
+    /// Hash with `hasher`, shared with other maps, instead of building one from
+    /// `hash_function` and `fixed_seed`.
+    pub fn shared_hasher(mut self, hasher: std::sync::Arc<ShardHasher>) -> Self {
+        self.shared_hasher = Some(hasher);
+        self
+    }
+
+    /// Bound each shard to at most `max` entries. Must be greater than 0 (checked at build).
+    pub fn max_entries_per_shard(mut self, max: usize) -> Self {
+        self.max_entries_per_shard = Some(max);
+        self
+    }
+
+    /// Set what happens when inserting a new key into a full shard (bounded mode only).
+    pub fn on_full(mut self, on_full: OnFull) -> Self {
+        self.on_full = on_full;
+        self
+    }
+
+    /// The resolved per-shard bound, if bounded mode is enabled.
+    pub(crate) fn shard_bound(&self) -> Option<ShardBound> {
+        self.max_entries_per_shard.map(|max_entries| ShardBound {
+            max_entries,
+            on_full: self.on_full,
+        })
+    }
 }
 
 impl Default for Config {
@@ -93,6 +353,11 @@ impl Default for Config {
             hash_function: HashFunction::AHash,
             capacity_per_shard: None,
             routing: RoutingConfig::Default,
+            seed: None,
+            max_entries_per_shard: None,
+            on_full: OnFull::EvictOldest,
+            table_seed: None,
+            shared_hasher: None,
         }
     }
 }
@@ -128,12 +393,86 @@ impl ShardMapBuilder {
         self
     }
 
+    /// Pin the ahash keys to values derived from `seed`.
+    ///
+    /// With the same seed, `hash_for_key` (and therefore shard routing) is identical across
+    /// process restarts and machines, e.g. to resume a sharded on-disk layout. This trades
+    /// away resistance to crafted-key flooding, which a random per-process seed would give.
+    ///
+    /// Stability holds for the same `shardmap`/`ahash` versions, the same pointer width, and
+    /// builds without the `aes` target feature (ahash switches algorithm when it's enabled).
+    /// Only affects `HashFunction::AHash`.
+    pub fn fixed_seed(mut self, seed: u128) -> Self {
+        self.config = self.config.fixed_seed(seed);
+        self
+    }
+
+    /// Hash keys with `hasher`, shared with other maps, e.g. from
+    /// [`ShardMap::shared_hasher`](crate::ShardMap::shared_hasher). Maps built with the same
+    /// `Arc` provably hash every key identically, so a family of maps (say a value map and an
+    /// index map) routes a key consistently. Overrides
+    /// [`hash_function`](Self::hash_function) and [`fixed_seed`](Self::fixed_seed).
+    ///
+    /// ```rust
+    /// use shardmap::ShardMapBuilder;
+    ///
+    /// let values = ShardMapBuilder::new().fixed_seed(7).build::<u64, String>()?;
+    /// let index = ShardMapBuilder::new()
+    ///     .shared_hasher(values.shared_hasher())
+    ///     .build::<u64, u64>()?;
+    /// assert_eq!(values.hash_for_key(&42), index.hash_for_key(&42));
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn shared_hasher(mut self, hasher: std::sync::Arc<ShardHasher>) -> Self {
+        self.config = self.config.shared_hasher(hasher);
+        self
+    }
+
+    /// Make the map fully reproducible for tests: routing uses a fixed seed (like
+    /// [`fixed_seed`](Self::fixed_seed)) and every shard's table uses fixed hasher keys, so
+    /// the same sequence of operations gives the same `iter_snapshot` order run to run.
+    ///
+    /// Only for tests that depend on snapshot order. Predictable hashing lets anyone who
+    /// controls the keys force collisions (HashDoS), so never use it on untrusted input.
+    /// Available in the crate's own tests and with the `deterministic` feature; the same
+    /// stability caveats as `fixed_seed` apply.
+    #[cfg(any(test, feature = "deterministic"))]
+    pub fn deterministic(mut self) -> Self {
+        self.config = self.config.deterministic();
+        self
+    }
+
+    /// Bound each shard to at most `max` entries (bounded mode).
+    ///
+    /// Since keys are routed by hash, the total capacity is about `max * shard_count`, but a
+    /// skewed key set can fill one shard early. What happens on a full shard is set with
+    /// [`on_full`](Self::on_full); the default is [`OnFull::EvictOldest`]. `build` fails with
+    /// `Error::InvalidCapacity` if `max` is 0.
+    pub fn max_entries_per_shard(mut self, max: usize) -> Self {
+        self.config = self.config.max_entries_per_shard(max);
+        self
+    }
+
+    /// Set the overflow policy for bounded mode. No effect without `max_entries_per_shard`.
+    pub fn on_full(mut self, on_full: OnFull) -> Self {
+        self.config = self.config.on_full(on_full);
+        self
+    }
+
     /// Use a custom shard router (e.g. for stateful or custom distribution).
     pub fn routing(mut self, routing: RoutingConfig) -> Self {
         self.config.routing = routing;
         self
     }
 
+    /// Route with a closure; shorthand for `.routing(RoutingConfig::from_fn(f))`.
+    pub fn routing_fn<F>(self, f: F) -> Self
+    where
+        F: Fn(u64, usize) -> usize + Send + Sync + 'static,
+    {
+        self.routing(RoutingConfig::from_fn(f))
+    }
+
     /// Build a ShardMap with the configured settings.
     pub fn build<K, V>(self) -> Result<crate::ShardMap<K, V>, Error>
     where
@@ -142,6 +481,118 @@ impl ShardMapBuilder {
     {
         crate::ShardMap::with_config(self.config)
     }
+
+    /// Normalize every key before routing and storage, e.g. lowercasing or trimming, so
+    /// `"Foo"` and `"foo"` address the same entry. The normalizer fixes the key type, so this
+    /// returns a [`TypedShardMapBuilder`] carrying the config set so far.
+    ///
+    /// It runs on every operation that takes keys as `K` or `&K`: inserts, lookups,
+    /// removals, updates, `entry`, `rename`, `watch_key`, `routing_value` and the batch
+    /// methods (`overwrite_many`, `merge_from`, `apply_diff`, `insert_presharded`,
+    /// `try_insert_all`, ...), so keep it cheap. Methods that take a borrowed form of the key
+    /// or a precomputed hash can't apply it, and panic on a normalized map: `shard_for_key`,
+    /// `canonical_shard`, `get_map` and the `*_by_hash` methods. Sibling maps
+    /// (`spawn_sibling`, `partition`) inherit it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMapBuilder;
+    /// use std::sync::Arc;
+    ///
+    /// let map = ShardMapBuilder::new()
+    ///     .shard_count(4)?
+    ///     .key_normalizer(Arc::new(|k: &String| k.to_lowercase()))
+    ///     .build()?;
+    /// map.insert("Foo".to_string(), 1);
+    /// assert_eq!(*map.get(&"FOO".to_string()).unwrap(), 1);
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn key_normalizer<K, V>(self, normalizer: KeyNormalizer<K>) -> TypedShardMapBuilder<K, V> {
+        TypedShardMapBuilder {
+            config: self.config,
+            normalizer: Some(normalizer),
+            write_through: None,
+            _value: PhantomData,
+        }
+    }
+
+    /// Mirror every write to a backing store, e.g. when the map is a front cache for a
+    /// database. The store fixes the key and value types, so this returns a
+    /// [`TypedShardMapBuilder`] carrying the config set so far.
+    ///
+    /// Every method that adds, replaces, changes or removes an entry reports it: inserts of
+    /// all kinds (`get_or_insert*`, `try_insert`, `insert_full`, `entry`, the batch methods
+    /// and `apply_diff`), updates (`update*`, `with_many_mut`, `for_each_mut`, values
+    /// handed out by `OccupiedEntry::get_mut` or `rebuild`), `rename`, and removals of all
+    /// kinds (`remove`, `retain*`, `drain_filter`, `clear`, `swap_contents`, evictions from
+    /// a bounded shard). Inserts and changes call [`on_insert`](WriteThrough::on_insert) with
+    /// the new value, and removals [`on_remove`](WriteThrough::on_remove). Methods that pass
+    /// values mutably to a closure (`retain`, `for_each_mut`, ...) report every value they
+    /// handed out, since they can't tell which ones changed. Moving entries between shards
+    /// (`migrate_key`, [`set_routing`](crate::ShardMap::set_routing), `compact`) isn't
+    /// reported, nor are values cached by [`get_through`](crate::ShardMap::get_through),
+    /// which came from the backing side.
+    ///
+    /// The in-memory map is always updated first, then the store is called: after the
+    /// shard lock is released with [`WriteThroughMode::AfterLock`], or while still holding
+    /// it with [`WriteThroughMode::InLock`] (strict per-shard ordering at the cost of
+    /// blocking the shard). Split halves keep the store; sibling maps (`spawn_sibling`,
+    /// `partition`) don't.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{ShardMapBuilder, WriteThrough, WriteThroughMode};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// #[derive(Default)]
+    /// struct Log(Mutex<Vec<String>>);
+    /// impl WriteThrough<&'static str, i32> for Log {
+    ///     fn on_insert(&self, key: &&'static str, value: &i32) {
+    ///         self.0.lock().unwrap().push(format!("put {key}={value}"));
+    ///     }
+    ///     fn on_remove(&self, key: &&'static str) {
+    ///         self.0.lock().unwrap().push(format!("del {key}"));
+    ///     }
+    /// }
+    ///
+    /// let log = Arc::new(Log::default());
+    /// let map = ShardMapBuilder::new()
+    ///     .write_through(log.clone(), WriteThroughMode::AfterLock)
+    ///     .build()?;
+    /// map.insert("a", 1);
+    /// map.get_or_insert("b", 2);
+    /// map.remove(&"a");
+    /// assert_eq!(*log.0.lock().unwrap(), ["put a=1", "put b=2", "del a"]);
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn write_through<K, V>(
+        self,
+        store: std::sync::Arc<dyn WriteThrough<K, V>>,
+        mode: WriteThroughMode,
+    ) -> TypedShardMapBuilder<K, V>
+    where
+        K: Clone,
+    {
+        TypedShardMapBuilder {
+            config: self.config,
+            normalizer: None,
+            write_through: Some(WriteThroughHook {
+                store,
+                mode,
+                clone_key: K::clone,
+            }),
+            _value: PhantomData,
+        }
+    }
+
+    /// Switch to building a [`CopyShardMap`](crate::CopyShardMap), which stores `Copy` values
+    /// inline rather than behind an `Arc` and returns them by copy. Shard count, hashing,
+    /// seeds, routing and capacity carry over; an entry bound is not supported.
+    pub fn inline_values(self) -> crate::CopyShardMapBuilder {
+        crate::CopyShardMapBuilder::new(self.config)
+    }
 }
 
 impl Default for ShardMapBuilder {
@@ -150,10 +601,67 @@ impl Default for ShardMapBuilder {
     }
 }
 
+/// Builder for a [`ShardMap`](crate::ShardMap) with options tied to its key type, from
+/// [`ShardMapBuilder::key_normalizer`] or [`ShardMapBuilder::write_through`]. Carries the
+/// config set so far.
+pub struct TypedShardMapBuilder<K, V> {
+    config: Config,
+    normalizer: Option<KeyNormalizer<K>>,
+    write_through: Option<WriteThroughHook<K, V>>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<K, V> TypedShardMapBuilder<K, V>
+where
+    K: std::hash::Hash + Eq + Send + Sync,
+    V: Send + Sync,
+{
+    /// Replace the key normalizer; see [`ShardMapBuilder::key_normalizer`].
+    pub fn key_normalizer(mut self, normalizer: KeyNormalizer<K>) -> Self {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    /// Replace the write-through store; see [`ShardMapBuilder::write_through`].
+    pub fn write_through(
+        mut self,
+        store: std::sync::Arc<dyn WriteThrough<K, V>>,
+        mode: WriteThroughMode,
+    ) -> Self
+    where
+        K: Clone,
+    {
+        self.write_through = Some(WriteThroughHook {
+            store,
+            mode,
+            clone_key: K::clone,
+        });
+        self
+    }
+
+    /// Build the map.
+    pub fn build(self) -> Result<crate::ShardMap<K, V>, Error> {
+        let mut map = crate::ShardMap::with_config(self.config)?;
+        if let Some(normalizer) = self.normalizer {
+            map = map.with_key_normalizer(normalizer);
+        }
+        if let Some(hook) = self.write_through {
+            map = map.with_write_through(hook);
+        }
+        Ok(map)
+    }
+}
+
 /// Create a hash function instance based on the configuration.
-pub(crate) fn create_hasher(hash_fn: HashFunction) -> ShardHasher {
+pub(crate) fn create_hasher(hash_fn: HashFunction, seed: Option<u128>) -> ShardHasher {
     match hash_fn {
-        HashFunction::AHash => ShardHasher::AHash,
+        HashFunction::AHash => match seed {
+            Some(seed) => ShardHasher::SeededAHash {
+                seed,
+                state: seeded_ahash(seed),
+            },
+            None => ShardHasher::AHash,
+        },
         #[cfg(feature = "fxhash")]
         HashFunction::FxHash => ShardHasher::FxHash,
     }
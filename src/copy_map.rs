@@ -0,0 +1,205 @@
+//! Sharded map that stores `Copy` values inline instead of behind an `Arc`.
+
+use crate::config::{create_hasher, valid_shard_count, Config, Router};
+use crate::error::Error;
+use crate::hash::{seeded_ahash, ShardHasher};
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+use std::hash::Hash;
+use std::sync::Arc;
+
+type InlineTable<K, V> = HashMap<K, V, ahash::RandomState>;
+
+/// A sharded concurrent map whose values are stored inline, from
+/// [`ShardMapBuilder::inline_values`](crate::ShardMapBuilder::inline_values).
+///
+/// [`ShardMap`](crate::ShardMap) wraps every value in an `Arc`, which for small `Copy` values
+/// such as `u64` counters costs an allocation per insert and an indirection per read. Here
+/// values sit directly in each shard's table and `get` returns a copy. Routing is the same
+/// as `ShardMap` built from the same config: shard count, hash function, seed and router.
+///
+/// The map is deliberately small: no entry bounds, metrics, hooks, watchers or key
+/// normalizer. Use `ShardMap` when you need those or when values are large.
+///
+/// # Example
+///
+/// ```rust
+/// use shardmap::ShardMapBuilder;
+///
+/// let hits = ShardMapBuilder::new().inline_values().build::<&str, u64>().unwrap();
+/// hits.insert("home", 1);
+/// hits.update(&"home", |n| *n += 1);
+/// assert_eq!(hits.get(&"home"), Some(2));
+/// ```
+pub struct CopyShardMap<K, V> {
+    shards: Box<[RwLock<InlineTable<K, V>>]>,
+    hash: Arc<ShardHasher>,
+    router: Router,
+}
+
+impl<K, V> CopyShardMap<K, V>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Copy + Send + Sync,
+{
+    /// Create a map with the default config (16 shards, ahash).
+    pub fn new() -> Self {
+        Self::with_config(Config::default()).expect("default config is valid")
+    }
+
+    /// Create a map with `shard_count` shards. Must be a power of two and greater than 0.
+    pub fn with_shard_count(shard_count: usize) -> Result<Self, Error> {
+        Self::with_config(Config::default().shard_count(shard_count)?)
+    }
+
+    /// Create a map from `config`. Fails with [`Error::BoundNotSupported`] if the config sets
+    /// `max_entries_per_shard`.
+    pub fn with_config(config: Config) -> Result<Self, Error> {
+        if !valid_shard_count(config.shard_count) {
+            return Err(Error::InvalidShardCount);
+        }
+        if config.max_entries_per_shard.is_some() {
+            return Err(Error::BoundNotSupported);
+        }
+        let capacity = config.capacity_per_shard.unwrap_or(0);
+        let shards = (0..config.shard_count)
+            .map(|_| {
+                let table_state = match config.table_seed {
+                    Some(seed) => seeded_ahash(seed),
+                    None => ahash::RandomState::new(),
+                };
+                RwLock::new(HashMap::with_capacity_and_hasher(capacity, table_state))
+            })
+            .collect();
+        Ok(Self {
+            shards,
+            hash: config
+                .shared_hasher
+                .unwrap_or_else(|| Arc::new(create_hasher(config.hash_function, config.seed))),
+            router: config.routing.into(),
+        })
+    }
+
+    #[inline]
+    fn shard(&self, key: &K) -> &RwLock<InlineTable<K, V>> {
+        &self.shards[self.shard_for_key(key)]
+    }
+
+    /// Insert a key-value pair, returning the previous value if the key was present.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).write().insert(key, value)
+    }
+
+    /// A copy of the value for the key.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.shard(key).read().get(key).copied()
+    }
+
+    /// Whether the key is present.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.shard(key).read().contains_key(key)
+    }
+
+    /// Remove the key, returning its value if it was present.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.shard(key).write().remove(key)
+    }
+
+    /// Apply `f` to the value in place under the shard's write lock, returning the new value,
+    /// or `None` if the key is absent.
+    pub fn update<F>(&self, key: &K, f: F) -> Option<V>
+    where
+        F: FnOnce(&mut V),
+    {
+        let mut table = self.shard(key).write();
+        let value = table.get_mut(key)?;
+        f(value);
+        Some(*value)
+    }
+
+    /// The value for the key, inserting `value` first if the key is absent.
+    pub fn get_or_insert(&self, key: K, value: V) -> V {
+        *self.shard(&key).write().entry(key).or_insert(value)
+    }
+
+    /// Total number of entries. Read-locks every shard in turn.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    /// Whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.read().is_empty())
+    }
+
+    /// Remove every entry, one shard at a time.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.write().clear();
+        }
+    }
+
+    /// Number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The shard index `key` routes to; same as [`ShardMap::shard_for_key`](crate::ShardMap::shard_for_key)
+    /// for a map built from the same config.
+    #[inline]
+    pub fn shard_for_key(&self, key: &K) -> usize {
+        self.router
+            .route(self.hash.hash_key(key), self.shards.len())
+    }
+
+    /// Per-shard entry counts.
+    pub fn shard_loads(&self) -> Vec<usize> {
+        self.shards.iter().map(|shard| shard.read().len()).collect()
+    }
+
+    /// Copy out every entry. Each shard is read under its lock; the map as a whole is not a
+    /// single snapshot.
+    pub fn iter_snapshot(&self) -> std::vec::IntoIter<(K, V)>
+    where
+        K: Clone,
+    {
+        let mut entries = Vec::new();
+        for shard in self.shards.iter() {
+            entries.extend(shard.read().iter().map(|(k, v)| (k.clone(), *v)));
+        }
+        entries.into_iter()
+    }
+}
+
+/// Builder for a [`CopyShardMap`], from
+/// [`ShardMapBuilder::inline_values`](crate::ShardMapBuilder::inline_values). Carries the
+/// config set so far.
+pub struct CopyShardMapBuilder {
+    config: Config,
+}
+
+impl CopyShardMapBuilder {
+    pub(crate) fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Build the map. Fails with [`Error::BoundNotSupported`] if `max_entries_per_shard` was
+    /// set.
+    pub fn build<K, V>(self) -> Result<CopyShardMap<K, V>, Error>
+    where
+        K: Hash + Eq + Send + Sync,
+        V: Copy + Send + Sync,
+    {
+        CopyShardMap::with_config(self.config)
+    }
+}
+
+impl<K, V> Default for CopyShardMap<K, V>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Copy + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
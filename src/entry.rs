@@ -0,0 +1,175 @@
+//! Entry API: inspect and then change one key under its shard's write lock.
+
+use crate::shard::{Shard, ShardWriteGuard};
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// A view into a single key of a [`ShardMap`](crate::ShardMap), from
+/// [`ShardMap::entry`](crate::ShardMap::entry).
+///
+/// The key's shard stays write-locked until the entry is dropped or consumed, so the
+/// occupied/vacant state can't change underneath it. Don't call back into the map for a key
+/// on the same shard while holding an entry; that deadlocks.
+pub enum Entry<'a, K: Hash + Eq, V> {
+    /// The key is present.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// The key is absent.
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// A present key, with its shard write-locked.
+pub struct OccupiedEntry<'a, K: Hash + Eq, V> {
+    shard: &'a Shard<K, V>,
+    map: ShardWriteGuard<'a, K, V>,
+    key: K,
+    /// The value was handed out by `get_mut`; reported to the write-through store on drop.
+    dirty: bool,
+}
+
+/// An absent key, with its shard write-locked.
+pub struct VacantEntry<'a, K: Hash + Eq, V> {
+    shard: &'a Shard<K, V>,
+    map: ShardWriteGuard<'a, K, V>,
+    key: K,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+{
+    /// Lock `shard` for writing and classify `key`.
+    pub(crate) fn new(shard: &'a Shard<K, V>, key: K) -> Self {
+        let map = shard.write_lock();
+        if map.contains_key(&key) {
+            Entry::Occupied(OccupiedEntry {
+                shard,
+                map,
+                key,
+                dirty: false,
+            })
+        } else {
+            Entry::Vacant(VacantEntry { shard, map, key })
+        }
+    }
+
+    /// The occupied entry for the key, inserting `value` first if it is absent. The lock
+    /// stays held, so the value can be changed with [`OccupiedEntry::get_mut`] before any
+    /// other thread sees it.
+    pub fn or_insert(self, value: V) -> OccupiedEntry<'a, K, V>
+    where
+        K: Clone,
+    {
+        match self {
+            Entry::Occupied(entry) => entry,
+            Entry::Vacant(entry) => entry.insert_entry(value),
+        }
+    }
+
+    /// The entry's key.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+}
+
+impl<K, V> OccupiedEntry<'_, K, V>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+{
+    /// The entry's key.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The stored value.
+    pub fn get(&self) -> &Arc<V> {
+        self.map
+            .get(&self.key)
+            .expect("occupied entry holds its key")
+    }
+
+    /// Mutable access to the stored value, cloning it first if its `Arc` is shared.
+    pub fn get_mut(&mut self) -> &mut V
+    where
+        V: Clone,
+    {
+        self.dirty = true;
+        let arc = self
+            .map
+            .get_mut(&self.key)
+            .expect("occupied entry holds its key");
+        Arc::make_mut(arc)
+    }
+
+    /// Replace the stored value, returning the previous one. The lock stays held.
+    pub fn insert(&mut self, value: V) -> Arc<V> {
+        self.dirty = false;
+        self.shard
+            .replace_locked(&mut self.map, &self.key, Arc::new(value))
+            .expect("occupied entry holds its key")
+    }
+
+    /// Remove the entry, returning its value, and release the lock.
+    pub fn remove(mut self) -> Arc<V> {
+        self.dirty = false;
+        self.shard
+            .remove_locked(&mut self.map, &self.key)
+            .expect("occupied entry holds its key")
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+{
+    /// The key that would be inserted.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Give back the key without inserting.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Insert `value` for the key, returning the stored `Arc`, and release the lock.
+    ///
+    /// On a bounded shard this evicts like [`insert`](crate::ShardMap::insert).
+    pub fn insert(mut self, value: V) -> Arc<V> {
+        let arc = Arc::new(value);
+        self.shard
+            .insert_locked(&mut self.map, self.key, Arc::clone(&arc));
+        arc
+    }
+
+    /// Insert `value` for the key and keep the lock, returning the now occupied entry.
+    /// Evicts on a full shard like [`insert`](Self::insert).
+    pub fn insert_entry(mut self, value: V) -> OccupiedEntry<'a, K, V>
+    where
+        K: Clone,
+    {
+        self.shard
+            .insert_locked(&mut self.map, self.key.clone(), Arc::new(value));
+        OccupiedEntry {
+            shard: self.shard,
+            map: self.map,
+            key: self.key,
+            dirty: false,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for OccupiedEntry<'_, K, V> {
+    fn drop(&mut self) {
+        if self.dirty {
+            if let Some(value) = self.map.get(&self.key) {
+                self.shard.mirror().inserted(&self.key, value);
+            }
+        }
+    }
+}
@@ -1,5 +1,9 @@
 /// Errors that can occur when operating on a ShardMap.
+///
+/// New variants may be added in minor releases; match on [`kind`](Error::kind) for a stable
+/// classification.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
     /// The requested key was not found in the map.
     KeyNotFound,
@@ -7,6 +11,19 @@ pub enum Error {
     KeyAlreadyExists,
     /// The shard count is invalid (must be a power of two and greater than 0).
     InvalidShardCount,
+    /// The key's shard is at its `max_entries_per_shard` bound and the map rejects overflow.
+    ShardFull,
+    /// The per-shard entry bound is invalid (must be greater than 0).
+    InvalidCapacity,
+    /// A router returned a shard index outside `0..shard_count`.
+    InvalidShardIndex,
+    /// Two maps don't share a layout (shard count, hasher, router and bound).
+    IncompatibleMaps,
+    /// A per-shard input (e.g. a loads snapshot) doesn't have one element per shard.
+    ShardCountMismatch,
+    /// The map type has no entry bound, but the config sets `max_entries_per_shard` (e.g. an
+    /// inline-value [`CopyShardMap`](crate::CopyShardMap)).
+    BoundNotSupported,
 }
 
 impl std::fmt::Display for Error {
@@ -17,8 +34,122 @@ impl std::fmt::Display for Error {
             Error::InvalidShardCount => {
                 write!(f, "shard count must be a power of two and greater than 0")
             }
+            Error::ShardFull => write!(f, "shard is full"),
+            Error::InvalidCapacity => {
+                write!(f, "max entries per shard must be greater than 0")
+            }
+            Error::InvalidShardIndex => write!(f, "router returned an out-of-range shard index"),
+            Error::IncompatibleMaps => {
+                write!(f, "maps differ in shard count, hasher, router or bound")
+            }
+            Error::ShardCountMismatch => {
+                write!(f, "per-shard input length doesn't match the shard count")
+            }
+            Error::BoundNotSupported => {
+                write!(f, "this map type doesn't support max_entries_per_shard")
+            }
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+/// A timed operation such as [`ShardMap::get_timeout`](crate::ShardMap::get_timeout) couldn't
+/// acquire its shard lock in time. Nothing was read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timed out waiting for a shard lock")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// A reservation such as [`ShardMap::try_reserve`](crate::ShardMap::try_reserve) couldn't
+/// allocate. Shards reserved before the failing one keep their new capacity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TryReserveError {
+    /// The requested capacity exceeds what a shard's table can address.
+    CapacityOverflow,
+    /// The allocator failed for this layout.
+    AllocError {
+        /// The layout of the allocation that failed.
+        layout: std::alloc::Layout,
+    },
+}
+
+impl TryReserveError {
+    /// Translate a table's reservation error. Not a `From` impl, so the hash table crate
+    /// stays out of the public API.
+    pub(crate) fn from_table(err: hashbrown::TryReserveError) -> Self {
+        match err {
+            hashbrown::TryReserveError::CapacityOverflow => TryReserveError::CapacityOverflow,
+            hashbrown::TryReserveError::AllocError { layout } => {
+                TryReserveError::AllocError { layout }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryReserveError::CapacityOverflow => write!(f, "requested capacity overflows"),
+            TryReserveError::AllocError { layout } => {
+                write!(f, "failed to allocate {} bytes", layout.size())
+            }
+        }
+    }
+}
+
+impl std::error::Error for TryReserveError {}
+
+/// Stable classification of an [`Error`], for matching without naming every variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// A key that should exist does not.
+    NotFound,
+    /// A key that should be new already exists.
+    AlreadyExists,
+    /// A bounded shard has no room.
+    Full,
+    /// The map's configuration or router is invalid.
+    InvalidConfig,
+    /// An argument is unusable for this call (e.g. maps with different layouts).
+    InvalidInput,
+}
+
+impl Error {
+    /// The error's stable category.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::KeyNotFound => ErrorKind::NotFound,
+            Error::KeyAlreadyExists => ErrorKind::AlreadyExists,
+            Error::ShardFull => ErrorKind::Full,
+            Error::InvalidShardCount
+            | Error::InvalidCapacity
+            | Error::InvalidShardIndex
+            | Error::BoundNotSupported => ErrorKind::InvalidConfig,
+            Error::IncompatibleMaps | Error::ShardCountMismatch => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// Wraps the error in an `io::Error` of the matching [`io::ErrorKind`](std::io::ErrorKind):
+/// `NotFound`, `AlreadyExists`, `InvalidInput` for configuration and input errors, and
+/// `Other` for a full shard. The original error is kept as the inner error, so it can be
+/// recovered with `get_ref()` and `downcast_ref::<Error>()`.
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err.kind() {
+            ErrorKind::NotFound => std::io::ErrorKind::NotFound,
+            ErrorKind::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+            ErrorKind::Full => std::io::ErrorKind::Other,
+            ErrorKind::InvalidConfig | ErrorKind::InvalidInput => std::io::ErrorKind::InvalidInput,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
@@ -1,17 +1,36 @@
+use crate::config::HashFunction;
 use std::hash::{Hash, Hasher};
 
 /// Hash function implementation for shard assignment.
 /// Uses an enum to avoid trait object limitations with generics.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub enum ShardHasher {
     /// AHash implementation (default, fast and well-distributed).
     #[default]
     AHash,
+    /// AHash with keys pinned from a user seed (see `ShardMapBuilder::fixed_seed`).
+    SeededAHash {
+        /// The seed the keys were derived from.
+        seed: u128,
+        /// The pinned hasher state.
+        state: ahash::RandomState,
+    },
     /// FxHash implementation (faster but potentially less distributed).
     #[cfg(feature = "fxhash")]
     FxHash,
 }
 
+/// Shows the hash function and whether it is seeded, never the seed itself.
+impl std::fmt::Debug for ShardHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (hash_function, seed) = self.describe();
+        f.debug_struct("ShardHasher")
+            .field("hash_function", &hash_function)
+            .field("seeded", &seed.is_some())
+            .finish()
+    }
+}
+
 impl ShardHasher {
     /// Hash a key to determine which shard it belongs to.
     pub fn hash_key<K: Hash + ?Sized>(&self, key: &K) -> u64 {
@@ -21,6 +40,7 @@ impl ShardHasher {
                 key.hash(&mut hasher);
                 hasher.finish()
             }
+            ShardHasher::SeededAHash { state, .. } => state.hash_one(key),
             #[cfg(feature = "fxhash")]
             ShardHasher::FxHash => {
                 let mut hasher = fxhash::FxHasher::default();
@@ -29,4 +49,82 @@ impl ShardHasher {
             }
         }
     }
+
+    /// The configured hash function and fixed seed this hasher was built from.
+    pub(crate) fn describe(&self) -> (HashFunction, Option<u128>) {
+        match self {
+            ShardHasher::AHash => (HashFunction::AHash, None),
+            ShardHasher::SeededAHash { seed, .. } => (HashFunction::AHash, Some(*seed)),
+            #[cfg(feature = "fxhash")]
+            ShardHasher::FxHash => (HashFunction::FxHash, None),
+        }
+    }
+
+    /// Whether both hashers produce the same hash for every key.
+    pub(crate) fn same_as(&self, other: &ShardHasher) -> bool {
+        match (self, other) {
+            (ShardHasher::AHash, ShardHasher::AHash) => true,
+            (
+                ShardHasher::SeededAHash { seed: a, .. },
+                ShardHasher::SeededAHash { seed: b, .. },
+            ) => a == b,
+            #[cfg(feature = "fxhash")]
+            (ShardHasher::FxHash, ShardHasher::FxHash) => true,
+            _ => false,
+        }
+    }
+
+    /// Hash every key and pass each hash through `route`, writing the results to `out`.
+    ///
+    /// The hasher choice is matched once per batch rather than per key, so each loop body
+    /// is monomorphic and can be inlined and unrolled for fixed-width keys.
+    pub fn route_batch<T, F>(&self, keys: &[T], out: &mut [usize], route: F)
+    where
+        T: Hash,
+        F: Fn(u64) -> usize,
+    {
+        fn fill<T, H, F>(keys: &[T], out: &mut [usize], hash: H, route: F)
+        where
+            H: Fn(&T) -> u64,
+            F: Fn(u64) -> usize,
+        {
+            for (key, slot) in keys.iter().zip(out.iter_mut()) {
+                *slot = route(hash(key));
+            }
+        }
+
+        match self {
+            ShardHasher::AHash => fill(
+                keys,
+                out,
+                |key| {
+                    let mut hasher = ahash::AHasher::default();
+                    key.hash(&mut hasher);
+                    hasher.finish()
+                },
+                route,
+            ),
+            ShardHasher::SeededAHash { state, .. } => {
+                fill(keys, out, |key| state.hash_one(key), route)
+            }
+            #[cfg(feature = "fxhash")]
+            ShardHasher::FxHash => fill(
+                keys,
+                out,
+                |key| {
+                    let mut hasher = fxhash::FxHasher::default();
+                    key.hash(&mut hasher);
+                    hasher.finish()
+                },
+                route,
+            ),
+        }
+    }
+}
+
+/// Build an ahash state whose four keys are derived only from `seed`.
+pub(crate) fn seeded_ahash(seed: u128) -> ahash::RandomState {
+    let lo = seed as u64;
+    let hi = (seed >> 64) as u64;
+    ahash::RandomState::with_seeds(lo, hi, !lo, !hi)
 }
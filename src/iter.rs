@@ -1,4 +1,5 @@
 use crate::shard::Shard;
+use std::collections::VecDeque;
 use std::hash::Hash;
 use std::sync::Arc;
 
@@ -25,7 +26,7 @@ where
         for shard in shards {
             let map = shard.read_lock();
             for (key, value) in map.iter() {
-                entries.push((key.clone(), value.clone()));
+                entries.push((key.clone(), Arc::clone(value)));
             }
         }
 
@@ -65,19 +66,24 @@ impl<K, V> ExactSizeIterator for SnapshotIter<K, V> where K: Clone {}
 ///
 /// Note: This implementation collects entries from each shard into a buffer
 /// to avoid lifetime issues with holding locks across iterator calls.
-pub struct ConcurrentIter<'a, K, V> {
-    shards: &'a [Shard<K, V>],
+///
+/// The iterator shares the map's shard storage rather than borrowing the map, so it is
+/// `'static` (for `'static` keys and values) and keeps working after the map is dropped.
+/// If the map is consumed or dropped meanwhile, shards not yet visited yield whatever they
+/// still hold: everything after a drop, nothing after an `into_*` method moved it out.
+pub struct ConcurrentIter<K, V> {
+    shards: Arc<[Shard<K, V>]>,
     current_shard: usize,
     buffer: Vec<(K, Arc<V>)>,
     buffer_index: usize,
 }
 
-impl<'a, K, V> ConcurrentIter<'a, K, V>
+impl<K, V> ConcurrentIter<K, V>
 where
     K: Hash + Eq + Send + Sync + Clone,
     V: Send + Sync,
 {
-    pub(crate) fn new(shards: &'a [Shard<K, V>]) -> Self {
+    pub(crate) fn new(shards: Arc<[Shard<K, V>]>) -> Self {
         Self {
             shards,
             current_shard: 0,
@@ -99,7 +105,7 @@ where
 
             // Collect entries from this shard
             for (key, value) in guard.iter() {
-                self.buffer.push((key.clone(), value.clone()));
+                self.buffer.push((key.clone(), Arc::clone(value)));
             }
 
             // If we got entries, we're done
@@ -117,7 +123,7 @@ where
     }
 }
 
-impl<'a, K, V> Iterator for ConcurrentIter<'a, K, V>
+impl<K, V> Iterator for ConcurrentIter<K, V>
 where
     K: Hash + Eq + Send + Sync + Clone,
     V: Send + Sync,
@@ -135,4 +141,65 @@ where
         self.buffer_index += 1;
         Some(item)
     }
+
+    /// At least the entries already buffered, with no upper bound: writers can grow the
+    /// shards not yet visited before they are read. Use
+    /// [`ShardMap::len_hint`](crate::ShardMap::len_hint) for an approximate total to
+    /// pre-size with.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.buffer.len() - self.buffer_index, None)
+    }
+}
+
+/// Iterator over snapshot chunks of at most `chunk_size` entries.
+///
+/// Shards are snapshotted one at a time, only when the buffered entries can't fill the next
+/// chunk, so peak memory stays around one chunk plus one shard. Each shard's entries are a
+/// consistent view of that shard when it was read; the map as a whole is not.
+pub struct SnapshotChunks<'a, K, V> {
+    shards: &'a [Shard<K, V>],
+    next_shard: usize,
+    chunk_size: usize,
+    buffer: VecDeque<(K, Arc<V>)>,
+}
+
+impl<'a, K, V> SnapshotChunks<'a, K, V>
+where
+    K: Hash + Eq + Send + Sync + Clone,
+    V: Send + Sync,
+{
+    pub(crate) fn new(shards: &'a [Shard<K, V>], chunk_size: usize) -> Self {
+        assert!(
+            chunk_size > 0,
+            "snapshot_chunks: chunk_size must be greater than 0"
+        );
+        Self {
+            shards,
+            next_shard: 0,
+            chunk_size,
+            buffer: VecDeque::new(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for SnapshotChunks<'a, K, V>
+where
+    K: Hash + Eq + Send + Sync + Clone,
+    V: Send + Sync,
+{
+    type Item = Vec<(K, Arc<V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.len() < self.chunk_size && self.next_shard < self.shards.len() {
+            let guard = self.shards[self.next_shard].read_lock();
+            self.buffer
+                .extend(guard.iter().map(|(k, v)| (k.clone(), Arc::clone(v))));
+            self.next_shard += 1;
+        }
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let take = self.chunk_size.min(self.buffer.len());
+        Some(self.buffer.drain(..take).collect())
+    }
 }
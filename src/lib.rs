@@ -12,6 +12,8 @@
 //!   [`shard_for_key`](ShardMap::shard_for_key) or a custom [`ShardRouter`] to control placement.
 //! - **Values as `Arc<V>`** — Stored values are reference-counted so readers can clone the `Arc`
 //!   and use the value without holding the shard lock (cheap sharing, no copy of `V`).
+//!   For small `Copy` values, [`CopyShardMap`] (via [`ShardMapBuilder::inline_values`]) stores
+//!   them inline instead.
 //!
 //! ## When to use ShardMap
 //!
@@ -27,6 +29,14 @@
 //! | `metrics`     | —       | Per-shard read/write/remove and lock-acquisition counters. |
 //! | `lock-timing` | —       | Per-shard lock wait time. **Debugging/profiling only**; not for production hot paths. |
 //! | `fxhash`      | —       | Use FxHash for shard assignment. |
+//! | `op-trace`    | —       | Per-shard ring buffer of recent operations. **Debugging only.** |
+//! | `watch`       | —       | `watch_loads()` / `watch_key()` publish shard loads and key values on tokio `watch` channels. |
+//! | `invariant-checks` | — | `check_invariants()` consistency assertions for tests and fuzz targets. |
+//! | `deterministic` | — | `ShardMapBuilder::deterministic()`: fixed hashers for reproducible iteration order in tests. |
+//! | `lock-introspect` | — | `locked_shard_count()` samples how many shards are write-locked. |
+//! | `rayon`       | —       | `into_par_iter()` consumes the map in parallel, one shard per task; `par_drain_filter()` drains shards in parallel. |
+//! | `lru`         | —       | `OnFull::EvictLeastRecent` access-order eviction for bounded maps. |
+//! | `bloom`       | —       | `build_bloom()` snapshots the keys into a `BloomFilter` for lock-free negative lookups. |
 //!
 //! ## Quick example
 //!
@@ -72,7 +82,8 @@
 //! ## Custom routing
 //!
 //! Implement [`ShardRouter`] and pass [`RoutingConfig::Custom(Box::new(your_router))`](RoutingConfig::Custom)
-//! to the builder. See [`DefaultRouter`] for the default `hash & (shard_count - 1)` behavior.
+//! to the builder, or use [`routing_fn`](ShardMapBuilder::routing_fn) for a plain closure. See
+//! [`DefaultRouter`] for the default `hash & (shard_count - 1)` behavior.
 //!
 //! ## Non-goals
 //!
@@ -81,28 +92,66 @@
 #![deny(missing_docs)]
 #![warn(clippy::all)]
 
+/// Bloom filter snapshots of a map's keys.
+#[cfg(feature = "bloom")]
+pub mod bloom;
 /// Configuration and builder types.
 pub mod config;
+/// Inline-value map for `Copy` values.
+pub mod copy_map;
+/// Entry API for single-key inspect-then-modify.
+pub mod entry;
 /// Error types.
 pub mod error;
 /// Hash function implementations.
 pub mod hash;
 /// Iterator implementations.
 pub mod iter;
+/// Eviction-order tracking for bounded shards.
+mod order;
+/// Read-only map handle.
+pub mod read_only;
+/// Read-through sources for lazy backfill.
+pub mod read_through;
 /// Internal shard implementation.
 pub mod shard;
 /// Main ShardMap implementation.
 pub mod shardmap;
+/// Per-key coordination for single-flight fills.
+mod single_flight;
 /// Statistics and diagnostics types.
 pub mod stats;
+/// Recent-operation tracing.
+#[cfg(feature = "op-trace")]
+pub mod trace;
+/// Per-key change subscriptions.
+#[cfg(feature = "watch")]
+mod watchers;
+/// Write-through hooks for backing stores.
+pub mod write_through;
 
 // Re-export main types
+#[cfg(feature = "bloom")]
+pub use bloom::BloomFilter;
 pub use config::{
-    Config, DefaultRouter, HashFunction, RoutingConfig, ShardMapBuilder, ShardRouter,
+    round_shard_count, valid_shard_count, Config, DefaultRouter, HashFunction, OnFull, Rebalancer,
+    RoutingConfig, RoutingDescriptor, RoutingKind, ShardMapBuilder, ShardRouter,
+    TypedShardMapBuilder,
 };
-pub use error::Error;
-pub use shardmap::ShardMap;
-pub use stats::{Diagnostics, ShardDiagnostics, ShardOps, Stats};
+pub use copy_map::{CopyShardMap, CopyShardMapBuilder};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{Error, ErrorKind, TimedOut, TryReserveError};
+pub use read_only::ReadOnly;
+pub use read_through::ReadThrough;
+pub use shard::ShardTable;
+pub use shardmap::{Diff, InsertOutcome, KeyNormalizer, RemovedEntry, ShardMap};
+pub use stats::{
+    Diagnostics, DiagnosticsDelta, DiagnosticsSnapshot, ProbeStats, ShardDiagnostics, ShardOps,
+    Stats,
+};
+#[cfg(feature = "op-trace")]
+pub use trace::OpKind;
+pub use write_through::{WriteThrough, WriteThroughMode};
 
 #[cfg(test)]
 mod tests {
@@ -167,6 +216,7 @@ mod tests {
         assert_eq!(loads.iter().sum::<usize>(), 2);
         let diag = map.diagnostics();
         assert_eq!(diag.total_entries, 2);
+        assert!(diag.shards.iter().all(|s| s.capacity >= s.entries));
         assert!(diag.max_load_ratio >= 1.0);
     }
 
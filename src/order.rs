@@ -0,0 +1,54 @@
+use std::collections::VecDeque;
+
+/// Eviction order for one bounded shard.
+///
+/// Every touch hands out a new sequence number, which the shard stores in the entry's
+/// [`Slot`](crate::shard::Slot), and queues `(hash, seq)`. A queued pair is live while the
+/// entry with that hash still carries that seq, so keys that collide on the hash are told
+/// apart, and pairs left behind by a later touch or a removal are skipped when popped. The
+/// shard does the liveness checks, since only it can look entries up.
+pub(crate) struct EvictionOrder {
+    queue: VecDeque<(u64, u64)>,
+    next_seq: u64,
+}
+
+impl EvictionOrder {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            // 0 marks an entry that was never tracked.
+            next_seq: 1,
+        }
+    }
+
+    /// Queue a touch of the entry with this hash as the newest, returning the sequence
+    /// number to store in its slot.
+    pub fn touch(&mut self, hash: u64) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue.push_back((hash, seq));
+        seq
+    }
+
+    /// Pop the oldest live pair, dropping stale ones on the way.
+    pub fn pop_oldest(&mut self, mut live: impl FnMut(u64, u64) -> bool) -> Option<(u64, u64)> {
+        while let Some((hash, seq)) = self.queue.pop_front() {
+            if live(hash, seq) {
+                return Some((hash, seq));
+            }
+        }
+        None
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Drop stale pairs once the queue outgrows the `tracked` entries by more than double,
+    /// keeping memory proportional to the shard's size.
+    pub fn compact(&mut self, tracked: usize, mut live: impl FnMut(u64, u64) -> bool) {
+        if self.queue.len() > 2 * tracked + 32 {
+            self.queue.retain(|&(hash, seq)| live(hash, seq));
+        }
+    }
+}
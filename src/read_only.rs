@@ -0,0 +1,108 @@
+//! Read-only handle for passing a map to code that must not mutate it.
+
+use crate::iter::SnapshotIter;
+use crate::stats::{Diagnostics, Stats};
+use crate::ShardMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// A borrowed, read-only view of a [`ShardMap`], from
+/// [`ShardMap::as_read_only`](crate::ShardMap::as_read_only).
+///
+/// Exposes lookups, iteration and diagnostics but no inserting, removing, updating or
+/// clearing method, so read-only contracts are enforced at compile time. It is a `Copy`
+/// wrapper around `&ShardMap` with no runtime cost.
+///
+/// ```compile_fail,E0599
+/// use shardmap::ShardMap;
+///
+/// let map: ShardMap<&str, i32> = ShardMap::new();
+/// map.as_read_only().insert("key", 1);
+/// ```
+pub struct ReadOnly<'a, K, V> {
+    map: &'a ShardMap<K, V>,
+}
+
+impl<K, V> Clone for ReadOnly<'_, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for ReadOnly<'_, K, V> {}
+
+impl<'a, K, V> ReadOnly<'a, K, V>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+{
+    pub(crate) fn new(map: &'a ShardMap<K, V>) -> Self {
+        Self { map }
+    }
+
+    /// See [`ShardMap::get`].
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.map.get(key)
+    }
+
+    /// See [`ShardMap::peek`].
+    pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+        self.map.peek(key)
+    }
+
+    /// See [`ShardMap::contains_key`].
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// See [`ShardMap::len`].
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// See [`ShardMap::len_hint`].
+    pub fn len_hint(&self) -> usize {
+        self.map.len_hint()
+    }
+
+    /// See [`ShardMap::is_empty`].
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// See [`ShardMap::iter_snapshot`].
+    pub fn iter_snapshot(&self) -> SnapshotIter<K, V>
+    where
+        K: Clone,
+    {
+        self.map.iter_snapshot()
+    }
+
+    /// See [`ShardMap::shard_count`].
+    pub fn shard_count(&self) -> usize {
+        self.map.shard_count()
+    }
+
+    /// See [`ShardMap::shard_for_key`].
+    pub fn shard_for_key<Q>(&self, key: &Q) -> usize
+    where
+        Q: Hash + ?Sized,
+    {
+        self.map.shard_for_key(key)
+    }
+
+    /// See [`ShardMap::shard_loads`].
+    pub fn shard_loads(&self) -> Vec<usize> {
+        self.map.shard_loads()
+    }
+
+    /// See [`ShardMap::diagnostics`].
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.map.diagnostics()
+    }
+
+    /// See [`ShardMap::stats`].
+    pub fn stats(&self) -> Stats {
+        self.map.stats()
+    }
+}
@@ -0,0 +1,12 @@
+//! Read-through sources for lazily backfilling misses.
+
+/// A source that can supply values missing from a [`ShardMap`](crate::ShardMap).
+///
+/// Register one with [`ShardMap::with_read_through`](crate::ShardMap::with_read_through) and
+/// read with [`get_through`](crate::ShardMap::get_through).
+pub trait ReadThrough<K, V>: Send + Sync {
+    /// Load the value for `key`, or `None` if the source doesn't have it either.
+    ///
+    /// Called without any shard lock held, so it may be slow (e.g. a database query).
+    fn load(&self, key: &K) -> Option<V>;
+}
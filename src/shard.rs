@@ -1,14 +1,329 @@
+use crate::config::{OnFull, ShardBound};
+use crate::error::{Error, TimedOut, TryReserveError};
+use crate::order::EvictionOrder;
+use crate::shardmap::RemovedEntry;
+use crate::single_flight::Flights;
 use crate::stats::ShardStats;
+#[cfg(feature = "op-trace")]
+use crate::trace::{OpKind, OpTrace};
+#[cfg(feature = "watch")]
+use crate::watchers::KeyWatchers;
+use crate::write_through::{Mirror, WriteThroughHook};
 use hashbrown::HashMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 use std::borrow::Borrow;
+use std::cell::Cell;
 use std::hash::Hash;
+use std::mem::ManuallyDrop;
+use std::ops::{ControlFlow, Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+
+/// A shard's table. Its `BuildHasher` is random per shard unless the map was built with
+/// `ShardMapBuilder::deterministic`.
+pub(crate) type Table<K, V> = HashMap<K, Slot<V>, ahash::RandomState>;
+
+/// A stored value, with the sequence number of its latest touch in the shard's eviction
+/// order. The seq is 0 on shards that don't track order and for entries added without
+/// tracking (e.g. through `rebuild`). It changes under the order mutex, possibly while only
+/// the read lock is held (LRU touches), hence the atomic.
+pub(crate) struct Slot<V> {
+    value: Arc<V>,
+    seq: AtomicU64,
+}
+
+impl<V> Slot<V> {
+    #[inline]
+    pub fn new(value: Arc<V>) -> Self {
+        Self {
+            value,
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn into_value(self) -> Arc<V> {
+        self.value
+    }
+
+    #[inline]
+    fn seq(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn set_seq(&self, seq: u64) {
+        self.seq.store(seq, Ordering::Relaxed);
+    }
+}
+
+impl<V> Deref for Slot<V> {
+    type Target = Arc<V>;
+
+    #[inline]
+    fn deref(&self) -> &Arc<V> {
+        &self.value
+    }
+}
+
+impl<V> DerefMut for Slot<V> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Arc<V> {
+        &mut self.value
+    }
+}
+
+/// One shard's entries, handed out by [`ShardMap::rebuild`](crate::ShardMap::rebuild) for
+/// direct mutation without locking.
+///
+/// A thin wrapper over the shard's hash table exposing the usual map operations, so the
+/// table's concrete type stays private. Nothing here updates eviction order, watchers or
+/// operation counters, and keys inserted must route to this shard. Writes are reported to
+/// the map's write-through store; values handed out mutably (`get_mut`, `iter_mut`,
+/// `retain`) are reported with whatever they hold when the table is dropped.
+pub struct ShardTable<'a, K: Hash + Eq, V> {
+    table: &'a mut Table<K, V>,
+    mirror: &'a Mirror<K, V>,
+    /// Keys whose values were handed out mutably, to report on drop.
+    touched: Vec<K>,
+    /// Every value was handed out mutably.
+    all_touched: bool,
+}
+
+impl<'a, K: Hash + Eq, V> ShardTable<'a, K, V> {
+    pub(crate) fn new(table: &'a mut Table<K, V>, mirror: &'a Mirror<K, V>) -> Self {
+        Self {
+            table,
+            mirror,
+            touched: Vec::new(),
+            all_touched: false,
+        }
+    }
+
+    /// Number of entries in the shard.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Whether the shard has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Entries the shard can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.table.capacity()
+    }
+
+    /// The value for the key, if present.
+    pub fn get<Q>(&self, key: &Q) -> Option<&Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.table.get(key).map(|slot| &slot.value)
+    }
+
+    /// The value slot for the key, to replace the `Arc` or (with `Arc::make_mut`) edit it.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (stored, slot) = self.table.get_key_value_mut(key)?;
+        if let Some(hook) = self.mirror.hook() {
+            self.touched.push((hook.clone_key)(stored));
+        }
+        Some(&mut slot.value)
+    }
+
+    /// Whether the key is present.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.table.contains_key(key)
+    }
+
+    /// Insert a key-value pair, returning the previous value if the key was present.
+    pub fn insert(&mut self, key: K, value: Arc<V>) -> Option<Arc<V>> {
+        self.mirror.inserted(&key, &value);
+        self.table
+            .insert(key, Slot::new(value))
+            .map(Slot::into_value)
+    }
+
+    /// Remove the key, returning its value if it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (key, slot) = self.table.remove_entry(key)?;
+        self.mirror.removed(&key);
+        Some(slot.into_value())
+    }
+
+    /// Keep only the entries for which `f` returns true.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut Arc<V>) -> bool,
+    {
+        let mirror = self.mirror;
+        self.table.retain(|k, slot| {
+            let keep = f(k, &mut slot.value);
+            if !keep {
+                mirror.removed(k);
+            }
+            keep
+        });
+        self.all_touched = true;
+    }
+
+    /// Remove every entry, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        if self.mirror.is_active() {
+            self.table.keys().for_each(|k| self.mirror.removed(k));
+        }
+        self.table.clear();
+        self.touched.clear();
+        self.all_touched = false;
+    }
+
+    /// Reserve room for at least `additional` more entries.
+    pub fn reserve(&mut self, additional: usize) {
+        self.table.reserve(additional);
+    }
+
+    /// Shrink the capacity as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.table.shrink_to_fit();
+    }
+
+    /// Iterate over the entries in table order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Arc<V>)> + '_ {
+        self.table.iter().map(|(k, slot)| (k, &slot.value))
+    }
+
+    /// Iterate over the entries with mutable value slots.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut Arc<V>)> + '_ {
+        self.all_touched = true;
+        self.table.iter_mut().map(|(k, slot)| (k, &mut slot.value))
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for ShardTable<'_, K, V> {
+    fn drop(&mut self) {
+        if self.all_touched {
+            for (k, slot) in self.table.iter() {
+                self.mirror.inserted(k, slot);
+            }
+            return;
+        }
+        for key in self.touched.drain(..) {
+            if let Some(slot) = self.table.get(&key) {
+                self.mirror.inserted(&key, slot);
+            }
+        }
+    }
+}
+
+/// Previous value for the key, and the entry evicted to make room.
+pub(crate) type EvictingInsert<K, V> = (Option<Arc<V>>, Option<RemovedEntry<K, V>>);
+
+thread_local! {
+    /// Nesting depth of `ShardMap::freeze_for_read` on this thread. While non-zero, reads on
+    /// this thread lock recursively so they don't queue behind writers that are themselves
+    /// waiting for the read locks this thread already holds.
+    static GLOBAL_READ_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Marks the current thread as holding every shard's read lock until dropped.
+pub(crate) struct GlobalReadScope(());
+
+impl GlobalReadScope {
+    pub(crate) fn enter() -> Self {
+        GLOBAL_READ_DEPTH.with(|depth| depth.set(depth.get() + 1));
+        GlobalReadScope(())
+    }
+}
+
+impl Drop for GlobalReadScope {
+    fn drop(&mut self) {
+        GLOBAL_READ_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Write access to a shard's table. When dropped, just before the lock is released, it
+/// refreshes the shard's lock-free length hint and, with the `watch` feature, publishes
+/// changes to watched keys; writes queued for an `AfterLock` write-through store are
+/// reported just after.
+pub(crate) struct ShardWriteGuard<'a, K: Hash + Eq, V> {
+    guard: ManuallyDrop<RwLockWriteGuard<'a, Table<K, V>>>,
+    len_hint: &'a AtomicUsize,
+    mirror: &'a Mirror<K, V>,
+    #[cfg(feature = "watch")]
+    watchers: &'a KeyWatchers<K, V>,
+}
+
+impl<K: Hash + Eq, V> Deref for ShardWriteGuard<'_, K, V> {
+    type Target = Table<K, V>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<K: Hash + Eq, V> DerefMut for ShardWriteGuard<'_, K, V> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for ShardWriteGuard<'_, K, V> {
+    fn drop(&mut self) {
+        self.len_hint.store(self.guard.len(), Ordering::Relaxed);
+        #[cfg(feature = "watch")]
+        self.watchers.notify(&self.guard);
+        let pending = self.mirror.take_pending();
+        // SAFETY: `guard` is dropped exactly once, here, and not used afterwards.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.mirror.report(pending);
+    }
+}
 
 /// A single shard containing a HashMap protected by a read-write lock.
 pub(crate) struct Shard<K, V> {
-    map: RwLock<HashMap<K, Arc<V>>>,
+    map: RwLock<Table<K, V>>,
     stats: ShardStats,
+    /// Entry count as of the last write lock release, readable without locking.
+    len_hint: AtomicUsize,
+    /// When set, `get`/`contains_key` read without the lock and writes panic.
+    frozen: AtomicBool,
+    /// Max entries and overflow policy in bounded mode.
+    bound: Option<ShardBound>,
+    /// Eviction order, tracked whenever the shard is bounded. Locked after `map`.
+    order: Option<Mutex<EvictionOrder>>,
+    #[cfg(feature = "op-trace")]
+    trace: OpTrace,
+    #[cfg(feature = "watch")]
+    watchers: KeyWatchers<K, V>,
+    /// Single-flight fills in progress for keys of this shard.
+    flights: Flights<K>,
+    /// Where writes are reported for the map's write-through store.
+    mirror: Mirror<K, V>,
+}
+
+impl<K, V> Shard<K, V> {
+    /// Where this shard reports writes, for callers that change values through a write lock
+    /// themselves.
+    pub fn mirror(&self) -> &Mirror<K, V> {
+        &self.mirror
+    }
 }
 
 impl<K, V> Shard<K, V>
@@ -22,44 +337,304 @@ where
 
     /// Create a shard with at least the given capacity. Zero means default.
     pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_bound(capacity, None, ahash::RandomState::new())
+    }
+
+    /// Create a shard with the given initial capacity, optional entry bound and table hasher.
+    pub fn with_capacity_and_bound(
+        capacity: usize,
+        bound: Option<ShardBound>,
+        table_state: ahash::RandomState,
+    ) -> Self {
         Self {
-            map: RwLock::new(HashMap::with_capacity(capacity)),
+            map: RwLock::new(HashMap::with_capacity_and_hasher(capacity, table_state)),
             stats: ShardStats::new(),
+            len_hint: AtomicUsize::new(0),
+            frozen: AtomicBool::new(false),
+            bound,
+            order: bound.map(|_| Mutex::new(EvictionOrder::new())),
+            #[cfg(feature = "op-trace")]
+            trace: OpTrace::new(),
+            #[cfg(feature = "watch")]
+            watchers: KeyWatchers::new(),
+            flights: Flights::new(),
+            mirror: Mirror::new(None),
         }
     }
 
+    /// Report this shard's writes to `hook`'s store from now on.
+    pub fn set_write_through(&mut self, hook: WriteThroughHook<K, V>) {
+        self.mirror = Mirror::new(Some(hook));
+    }
+
     #[inline]
-    fn read_guard(&self) -> parking_lot::RwLockReadGuard<'_, HashMap<K, Arc<V>>> {
+    fn read_guard(&self) -> parking_lot::RwLockReadGuard<'_, Table<K, V>> {
+        self.read_guard_within(None)
+            .expect("untimed lock acquisition can't time out")
+    }
+
+    /// Take the read lock, giving up after `timeout` if one is given.
+    #[inline]
+    fn read_guard_within(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Option<parking_lot::RwLockReadGuard<'_, Table<K, V>>> {
         #[cfg(feature = "lock-timing")]
         let start = std::time::Instant::now();
-        let guard = self.map.read();
+        let recursive = GLOBAL_READ_DEPTH.with(Cell::get) > 0;
+        let guard = match (timeout, recursive) {
+            (None, true) => self.map.read_recursive(),
+            (None, false) => self.map.read(),
+            (Some(timeout), true) => self.map.try_read_recursive_for(timeout)?,
+            (Some(timeout), false) => self.map.try_read_for(timeout)?,
+        };
         #[cfg(feature = "lock-timing")]
         self.stats
             .record_lock_wait(start.elapsed().as_nanos() as u64);
         self.stats.record_lock_acquisition();
-        guard
+        Some(guard)
     }
 
     #[inline]
-    fn write_guard(&self) -> parking_lot::RwLockWriteGuard<'_, HashMap<K, Arc<V>>> {
+    fn write_guard(&self) -> ShardWriteGuard<'_, K, V> {
+        self.write_guard_within(None)
+            .expect("untimed lock acquisition can't time out")
+    }
+
+    /// Take the write lock, giving up after `timeout` if one is given.
+    #[inline]
+    fn write_guard_within(&self, timeout: Option<Duration>) -> Option<ShardWriteGuard<'_, K, V>> {
         #[cfg(feature = "lock-timing")]
         let start = std::time::Instant::now();
-        let guard = self.map.write();
+        let guard = match timeout {
+            None => self.map.write(),
+            Some(timeout) => self.map.try_write_for(timeout)?,
+        };
         #[cfg(feature = "lock-timing")]
         self.stats
             .record_lock_wait(start.elapsed().as_nanos() as u64);
         self.stats.record_lock_acquisition();
-        guard
+        // Checked under the write lock so it can't race with `freeze`.
+        assert!(
+            !self.frozen.load(Ordering::Acquire),
+            "write to a ShardMap shard while reads are frozen"
+        );
+        Some(ShardWriteGuard {
+            guard: ManuallyDrop::new(guard),
+            len_hint: &self.len_hint,
+            mirror: &self.mirror,
+            #[cfg(feature = "watch")]
+            watchers: &self.watchers,
+        })
+    }
+
+    /// Unlocked view of the map while frozen, or `None` if not frozen.
+    #[inline]
+    fn frozen_view(&self) -> Option<&Table<K, V>> {
+        if self.frozen.load(Ordering::Acquire) {
+            // SAFETY: `frozen` is only set under the write lock, and every writer checks it
+            // under the write lock before mutating, so no mutation happens while it is set.
+            // `unfreeze` is unsafe and requires that no frozen reads are still in flight.
+            Some(unsafe { &*self.map.data_ptr() })
+        } else {
+            None
+        }
+    }
+
+    /// Stop taking the read lock in `get`/`contains_key`; writes panic until unfrozen.
+    pub fn freeze(&self) {
+        let _guard = self.map.write();
+        self.frozen.store(true, Ordering::Release);
+    }
+
+    /// Resume locked reads.
+    ///
+    /// # Safety
+    ///
+    /// No unlocked read started while frozen may still be in progress.
+    pub unsafe fn unfreeze(&self) {
+        let _guard = self.map.write();
+        self.frozen.store(false, Ordering::Release);
+    }
+
+    /// Whether reads are currently frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Acquire)
+    }
+
+    /// Whether some thread holds this shard's write lock right now. Racy; never blocks.
+    #[cfg(feature = "lock-introspect")]
+    pub fn is_write_locked(&self) -> bool {
+        self.map.is_locked_exclusive()
+    }
+
+    /// The entry bound this shard was created with, if any.
+    pub fn bound(&self) -> Option<ShardBound> {
+        self.bound
+    }
+
+    /// Whether a checked insert of `key` into `map`, this shard's locked table, must fail
+    /// with [`Error::ShardFull`]: the shard is bounded with `OnFull::Reject`, full, and
+    /// `key` is new.
+    #[inline]
+    pub fn rejects(&self, map: &Table<K, V>, key: &K) -> bool {
+        matches!(self.bound, Some(bound) if bound.on_full == OnFull::Reject
+            && map.len() >= bound.max_entries
+            && !map.contains_key(key))
+    }
+
+    /// Make room for `key` under the bound. Called with the write lock held. Returns the
+    /// entry evicted to make room, if any.
+    ///
+    /// Never refuses: a shard with `OnFull::Reject` evicts like `EvictOldest` here, and
+    /// checked inserts call [`rejects`](Self::rejects) first.
+    #[inline]
+    fn admit(&self, map: &mut Table<K, V>, key: &K) -> Option<(K, Arc<V>)> {
+        match self.bound {
+            Some(bound) if map.len() >= bound.max_entries && !map.contains_key(key) => {
+                // All policies share the order queue; EvictLeastRecent also touches it on
+                // reads.
+                let evicted = self.evict_oldest(map);
+                if let Some((key, _)) = &evicted {
+                    self.stats.record_remove();
+                    self.mirror.removed(key);
+                }
+                evicted
+            }
+            _ => None,
+        }
+    }
+
+    /// Remove the oldest (or least recently used) tracked entry. Falls back to an arbitrary entry if none of the
+    /// tracked ones are present (e.g. entries added through `rebuild`).
+    fn evict_oldest(&self, map: &mut Table<K, V>) -> Option<(K, Arc<V>)> {
+        if let Some(order) = &self.order {
+            let mut order = order.lock();
+            if let Some((hash, seq)) = order.pop_oldest(|hash, seq| Self::is_live(map, hash, seq)) {
+                // Find the key carrying `seq` (colliding keys share `hash`), then remove it by
+                // address; the table isn't modified in between, so the key doesn't move.
+                let target = map
+                    .raw_entry()
+                    .from_hash(hash, |k| map.get(k).is_some_and(|slot| slot.seq() == seq))
+                    .map(|(k, _)| k as *const K);
+                let entry = map
+                    .raw_entry_mut()
+                    .from_hash(hash, |k| target.is_some_and(|t| std::ptr::eq(k, t)));
+                if let hashbrown::hash_map::RawEntryMut::Occupied(e) = entry {
+                    let (key, slot) = e.remove_entry();
+                    return Some((key, slot.into_value()));
+                }
+            }
+        }
+        map.extract_if(|_, _| true)
+            .next()
+            .map(|(key, slot)| (key, slot.into_value()))
+    }
+
+    /// Whether the entry with this hash still carries `seq`, i.e. the queued touch is its
+    /// latest one.
+    fn is_live(map: &Table<K, V>, hash: u64, seq: u64) -> bool {
+        map.raw_entry()
+            .from_hash(hash, |k| map.get(k).is_some_and(|slot| slot.seq() == seq))
+            .is_some()
+    }
+
+    /// Insert a key known to be absent, after `admit`. Tracks order, counts the write and
+    /// reports it to the write-through store.
+    #[inline]
+    fn insert_new(&self, map: &mut Table<K, V>, key: K, value: Arc<V>) {
+        self.mirror.inserted(&key, &value);
+        self.insert_tracked(map, key, value);
+        self.stats.record_write();
+    }
+
+    /// Insert a key known to be absent as the newest entry in the eviction order.
+    #[inline]
+    fn insert_tracked(&self, map: &mut Table<K, V>, key: K, value: Arc<V>) {
+        let slot = Slot::new(value);
+        match &self.order {
+            Some(order) => {
+                let mut order = order.lock();
+                slot.set_seq(order.touch(map.hasher().hash_one(&key)));
+                map.insert(key, slot);
+                order.compact(map.len(), |hash, seq| Self::is_live(map, hash, seq));
+            }
+            None => {
+                map.insert(key, slot);
+            }
+        }
+    }
+
+    /// Mark a present key as most recently used (LRU mode only). Takes the order mutex, so
+    /// it is safe under a read lock.
+    #[cfg(feature = "lru")]
+    #[inline]
+    fn touch_recent<Q>(&self, map: &Table<K, V>, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if !matches!(self.bound, Some(bound) if bound.on_full == OnFull::EvictLeastRecent) {
+            return;
+        }
+        if let (Some(order), Some(slot)) = (&self.order, map.get(key)) {
+            let mut order = order.lock();
+            slot.set_seq(order.touch(map.hasher().hash_one(key)));
+            order.compact(map.len(), |hash, seq| Self::is_live(map, hash, seq));
+        }
+    }
+
+    /// Insert a key-value pair, returning the previous value and any entry evicted to make
+    /// room.
+    pub fn insert_evicting(&self, key: K, value: V) -> EvictingInsert<K, V> {
+        self.insert_evicting_in(self.write_guard(), key, Arc::new(value))
+    }
+
+    /// `insert_evicting` into this shard's table through an already taken write lock, which
+    /// is released on return.
+    pub fn insert_evicting_in(
+        &self,
+        mut map: ShardWriteGuard<'_, K, V>,
+        key: K,
+        value: Arc<V>,
+    ) -> EvictingInsert<K, V> {
+        self.stats.record_touch();
+        if let Some((stored_key, slot)) = map.get_key_value_mut(&key) {
+            let previous = std::mem::replace(&mut slot.value, value);
+            self.mirror.inserted(stored_key, slot);
+            #[cfg(feature = "lru")]
+            self.touch_recent(&map, &key);
+            return (Some(previous), None);
+        }
+        let evicted = self.admit(&mut map, &key);
+        self.insert_new(&mut map, key, value);
+        (None, evicted)
+    }
+
+    /// Insert a key-value pair, replacing both the stored key and value if the key exists.
+    /// Returns the previously stored key object and value.
+    pub fn insert_full(&self, key: K, value: V) -> (Option<K>, Option<Arc<V>>) {
+        self.stats.record_touch();
+        let mut map = self.write_guard();
+        let value = Arc::new(value);
+        let key = match map.entry(key) {
+            hashbrown::hash_map::Entry::Occupied(entry) => {
+                let (previous_key, previous) = entry.replace_entry(Slot::new(Arc::clone(&value)));
+                self.mirror.inserted(&previous_key, &value);
+                #[cfg(feature = "lru")]
+                self.touch_recent(&map, &previous_key);
+                return (Some(previous_key), Some(previous.into_value()));
+            }
+            hashbrown::hash_map::Entry::Vacant(entry) => entry.into_key(),
+        };
+        self.admit(&mut map, &key);
+        self.insert_new(&mut map, key, value);
+        (None, None)
     }
 
     /// Insert a key-value pair, returning the previous value if any.
     pub fn insert(&self, key: K, value: V) -> Option<Arc<V>> {
-        let mut map = self.write_guard();
-        let result = map.insert(key, Arc::new(value));
-        if result.is_none() {
-            self.stats.record_write();
-        }
-        result
+        self.insert_evicting(key, value).0
     }
 
     /// Get a value by key, returning an Arc to enable zero-copy access.
@@ -68,12 +643,97 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        let map = self.read_guard();
-        let result = map.get(key).cloned();
+        self.read_value(key, Arc::clone)
+    }
+
+    /// `get` that waits at most `timeout` for the read lock.
+    pub fn get_timeout<Q>(&self, key: &Q, timeout: Duration) -> Result<Option<Arc<V>>, TimedOut>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read_value_within(key, Some(timeout), Arc::clone)
+    }
+
+    /// Get a clone of the value by key, cloned while the read lock is held.
+    pub fn get_cloned<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.read_value(key, |value| V::clone(value))
+    }
+
+    /// Look up `key` and project the stored value with `f` under the read lock
+    /// (or the frozen view). Counts a read and touches recency on a hit.
+    fn read_value<Q, R>(&self, key: &Q, f: impl FnOnce(&Arc<V>) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.read_value_within(key, None, f)
+            .expect("untimed lock acquisition can't time out")
+    }
+
+    /// `read_value` that gives up after `timeout`, if one is given, waiting for the lock.
+    fn read_value_within<Q, R>(
+        &self,
+        key: &Q,
+        timeout: Option<Duration>,
+        f: impl FnOnce(&Arc<V>) -> R,
+    ) -> Result<Option<R>, TimedOut>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.stats.record_touch();
+        let lookup = |map: &Table<K, V>| {
+            let result = map.get(key).map(|slot| f(slot));
+            #[cfg(feature = "lru")]
+            if result.is_some() {
+                self.touch_recent(map, key);
+            }
+            result
+        };
+        let result = match self.frozen_view() {
+            Some(map) => lookup(map),
+            None => {
+                let map = self.read_guard_within(timeout).ok_or(TimedOut)?;
+                lookup(&map)
+            }
+        };
+        self.stats.record_lookup(result.is_some());
         if result.is_some() {
             self.stats.record_read();
         }
-        result
+        Ok(result)
+    }
+
+    /// Look up every key under one read lock (or the frozen view), adding each hit to `out`
+    /// under its stored key. Counted like one `get` per key.
+    pub fn get_entries_into<Q>(&self, keys: &[&Q], out: &mut std::collections::HashMap<K, Arc<V>>)
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized,
+    {
+        let lookup = |map: &Table<K, V>, out: &mut std::collections::HashMap<K, Arc<V>>| {
+            for &key in keys {
+                self.stats.record_touch();
+                let hit = map.get_key_value(key);
+                self.stats.record_lookup(hit.is_some());
+                if let Some((stored, value)) = hit {
+                    self.stats.record_read();
+                    #[cfg(feature = "lru")]
+                    self.touch_recent(map, key);
+                    out.insert(stored.clone(), Arc::clone(value));
+                }
+            }
+        };
+        match self.frozen_view() {
+            Some(map) => lookup(map, out),
+            None => lookup(&self.read_guard(), out),
+        }
     }
 
     /// Remove a key-value pair, returning the value if it existed.
@@ -82,12 +742,12 @@ where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
+        self.stats.record_touch();
         let mut map = self.write_guard();
-        let result = map.remove(key);
-        if result.is_some() {
-            self.stats.record_remove();
-        }
-        result
+        let (removed_key, slot) = map.remove_entry(key)?;
+        self.stats.record_remove();
+        self.mirror.removed(&removed_key);
+        Some(slot.into_value())
     }
 
     /// Update a value using a closure, returning the new value if the key existed.
@@ -107,12 +767,37 @@ where
             let value = Arc::make_mut(arc_value);
             f(value);
             self.stats.record_write();
-            Some(arc_value.clone())
+            self.mirror.inserted(key, arc_value);
+            let result = arc_value.clone();
+            #[cfg(feature = "lru")]
+            self.touch_recent(&map, key);
+            Some(result)
         } else {
             None
         }
     }
 
+    /// Apply `f` to each present key under a single write lock. Returns how many were updated.
+    pub fn update_many<F>(&self, keys: &[&K], f: &mut F) -> usize
+    where
+        F: FnMut(&K, &mut V),
+        V: Clone,
+    {
+        let mut map = self.write_guard();
+        let mut updated = 0;
+        for &key in keys {
+            if let Some(arc_value) = map.get_mut(key) {
+                f(key, Arc::make_mut(arc_value));
+                self.stats.record_write();
+                self.mirror.inserted(key, arc_value);
+                #[cfg(feature = "lru")]
+                self.touch_recent(&map, key);
+                updated += 1;
+            }
+        }
+        updated
+    }
+
     /// Get the number of entries in this shard.
     pub fn len(&self) -> usize {
         self.read_guard().len()
@@ -130,8 +815,29 @@ where
 
     /// Remove all entries from this shard.
     pub fn clear(&self) {
+        self.clear_with(true);
+    }
+
+    /// Remove all entries. With `retain_capacity == false` the map is swapped for a fresh
+    /// empty one under the same write lock, releasing its allocation.
+    pub fn clear_with(&self, retain_capacity: bool) {
         let mut map = self.write_guard();
-        map.clear();
+        if self.mirror.is_active() {
+            map.keys().for_each(|k| self.mirror.removed(k));
+        }
+        if retain_capacity {
+            map.clear();
+        } else {
+            *map = HashMap::with_hasher(map.hasher().clone());
+        }
+        if let Some(order) = &self.order {
+            let mut order = order.lock();
+            if retain_capacity {
+                order.clear();
+            } else {
+                *order = EvictionOrder::new();
+            }
+        }
     }
 
     /// Retain only entries for which the predicate returns true.
@@ -141,7 +847,134 @@ where
         V: Clone,
     {
         let mut map = self.write_guard();
-        map.retain(|k, arc_v| f(k, Arc::make_mut(arc_v)));
+        map.retain(|k, arc_v| {
+            let keep = f(k, Arc::make_mut(arc_v));
+            if keep {
+                self.mirror.inserted(k, arc_v);
+            } else {
+                self.stats.record_remove();
+                self.mirror.removed(k);
+            }
+            keep
+        });
+    }
+
+    /// Apply `f` to every value in place, cloning only values whose `Arc` is shared.
+    pub fn for_each_mut<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V),
+        V: Clone,
+    {
+        let mut map = self.write_guard();
+        for (k, arc_v) in map.iter_mut() {
+            f(k, Arc::make_mut(arc_v));
+            self.mirror.inserted(k, arc_v);
+        }
+    }
+
+    /// Apply `f` to every value whose `Arc` is unique; returns how many shared ones were
+    /// skipped.
+    pub fn try_for_each_mut<F>(&self, mut f: F) -> usize
+    where
+        F: FnMut(&K, &mut V),
+    {
+        let mut map = self.write_guard();
+        let mut skipped = 0;
+        for (k, arc_v) in map.iter_mut() {
+            match Arc::get_mut(arc_v) {
+                Some(value) => {
+                    f(k, value);
+                    self.mirror.inserted(k, arc_v);
+                }
+                None => skipped += 1,
+            }
+        }
+        skipped
+    }
+
+    /// `retain` whose predicate can stop the pass: after `Break`, remaining entries are kept
+    /// without calling `f`. Returns `false` if `f` broke.
+    pub fn retain_controlled<F>(&self, mut f: F) -> bool
+    where
+        F: FnMut(&K, &mut V) -> ControlFlow<(), bool>,
+        V: Clone,
+    {
+        let mut map = self.write_guard();
+        let mut stopped = false;
+        map.retain(|k, arc_v| {
+            if stopped {
+                return true;
+            }
+            // A shared value is cloned for `f` and written back only on `Continue(true)`, so
+            // the entry `f` breaks on keeps its `Arc`.
+            let decision = match Arc::get_mut(arc_v) {
+                Some(value) => f(k, value),
+                None => {
+                    let mut value = V::clone(arc_v);
+                    let decision = f(k, &mut value);
+                    if decision == ControlFlow::Continue(true) {
+                        **arc_v = Arc::new(value);
+                    }
+                    decision
+                }
+            };
+            let keep = match decision {
+                ControlFlow::Continue(keep) => keep,
+                ControlFlow::Break(()) => {
+                    stopped = true;
+                    self.mirror.inserted(k, arc_v);
+                    return true;
+                }
+            };
+            if keep {
+                self.mirror.inserted(k, arc_v);
+            } else {
+                self.stats.record_remove();
+                self.mirror.removed(k);
+            }
+            keep
+        });
+        !stopped
+    }
+
+    /// Like `retain`, but returns the removed entries.
+    pub fn retain_collecting<F>(&self, mut f: F, removed: &mut Vec<(K, Arc<V>)>)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+        V: Clone,
+    {
+        let mut map = self.write_guard();
+        removed.extend(
+            map.extract_if(|k, arc_v| {
+                let remove = !f(k, Arc::make_mut(arc_v));
+                if remove {
+                    self.stats.record_remove();
+                    self.mirror.removed(k);
+                } else {
+                    self.mirror.inserted(k, arc_v);
+                }
+                remove
+            })
+            .map(|(k, slot)| (k, slot.into_value())),
+        );
+    }
+
+    /// Remove and return every entry for which `f` returns true, under the write lock.
+    pub fn drain_where<F>(&self, mut f: F) -> Vec<(K, Arc<V>)>
+    where
+        F: FnMut(&K, &Arc<V>) -> bool,
+    {
+        let mut map = self.write_guard();
+        map.extract_if(|k, arc_v| {
+            let remove = f(k, arc_v);
+            if remove {
+                self.stats.record_remove();
+                self.mirror.removed(k);
+            }
+            remove
+        })
+        .map(|(k, slot)| (k, slot.into_value()))
+        .collect()
     }
 
     /// Shrink the underlying storage to fit the current length.
@@ -150,6 +983,61 @@ where
         map.shrink_to_fit();
     }
 
+    /// Move every entry into a freshly allocated table with the same hasher, sized for the
+    /// current length, dropping the tombstones left by removals.
+    pub fn rebuild_table(&self) {
+        let mut map = self.write_guard();
+        let mut fresh = HashMap::with_capacity_and_hasher(map.len(), map.hasher().clone());
+        fresh.extend(map.drain());
+        *map = fresh;
+    }
+
+    /// Point every value equal to one in `interned` at that shared `Arc`, adding unseen
+    /// values to `interned`. Returns how many allocations were dropped as a result.
+    pub fn coalesce_values(&self, interned: &mut hashbrown::HashSet<Arc<V>>) -> usize
+    where
+        V: Hash + Eq,
+    {
+        let mut map = self.write_guard();
+        let mut freed = 0;
+        for slot in map.values_mut() {
+            match interned.get(slot.as_ref()) {
+                Some(shared) if !Arc::ptr_eq(shared, slot) => {
+                    let previous = std::mem::replace(&mut **slot, Arc::clone(shared));
+                    if Arc::strong_count(&previous) == 1 {
+                        freed += 1;
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    interned.insert(Arc::clone(slot));
+                }
+            }
+        }
+        freed
+    }
+
+    /// Grow the table so it can hold at least `target` entries without resizing. Never shrinks.
+    pub fn reserve_to(&self, target: usize) {
+        let mut map = self.write_guard();
+        let additional = target.saturating_sub(map.len());
+        map.reserve(additional);
+    }
+
+    /// Reserve room for `additional` more entries, reporting allocation failure instead of
+    /// aborting.
+    pub fn try_reserve(&self, additional: usize) -> Result<(), TryReserveError> {
+        self.write_guard()
+            .try_reserve(additional)
+            .map_err(TryReserveError::from_table)
+    }
+
+    /// Number of `get`/`insert`/`remove` calls routed to this shard.
+    #[cfg(feature = "metrics")]
+    pub fn touches(&self) -> u64 {
+        self.stats.touches()
+    }
+
     /// Get a snapshot of statistics for this shard.
     pub fn stats(&self) -> crate::stats::ShardOps {
         self.stats.snapshot()
@@ -158,96 +1046,529 @@ where
     /// Collect diagnostics for this shard (entries + ops snapshot).
     pub(crate) fn diagnostics_snapshot(&self) -> crate::stats::ShardDiagnostics {
         let ops = self.stats.snapshot();
+        let (entries, capacity) = {
+            let map = self.read_guard();
+            (map.len(), map.capacity())
+        };
         crate::stats::ShardDiagnostics {
-            entries: self.len(),
+            entries,
+            capacity,
             reads: ops.reads,
             writes: ops.writes,
             removes: ops.removes,
             lock_acquisitions: ops.lock_acquisitions,
             lock_wait_nanos: ops.lock_wait_nanos,
+            get_hits: ops.get_hits,
+            get_misses: ops.get_misses,
         }
     }
 
+    /// Single-flight fills in progress on this shard.
+    pub fn flights(&self) -> &Flights<K> {
+        &self.flights
+    }
+
     /// Get a read lock for iteration purposes.
-    pub fn read_lock(&self) -> parking_lot::RwLockReadGuard<'_, HashMap<K, Arc<V>>> {
+    pub fn read_lock(&self) -> parking_lot::RwLockReadGuard<'_, Table<K, V>> {
         self.read_guard()
     }
 
+    /// The hasher state of this shard's table, for building tables that iterate alike.
+    pub fn table_state(&self) -> ahash::RandomState {
+        self.read_guard().hasher().clone()
+    }
+
+    /// Consume the shard and take its entries; no locking needed with ownership.
+    pub fn into_entries(self) -> impl Iterator<Item = (K, Arc<V>)> {
+        self.map
+            .into_inner()
+            .into_iter()
+            .map(|(k, slot)| (k, slot.into_value()))
+    }
+
+    /// Get a write lock for multi-key mutation.
+    pub fn write_lock(&self) -> ShardWriteGuard<'_, K, V> {
+        self.write_guard()
+    }
+
+    /// `write_lock` that gives up after waiting `timeout`.
+    pub fn try_write_lock_for(&self, timeout: Duration) -> Option<ShardWriteGuard<'_, K, V>> {
+        self.write_guard_within(Some(timeout))
+    }
+
+    /// Record a write performed through `write_lock`.
+    #[inline]
+    pub fn record_write(&self) {
+        self.stats.record_write();
+    }
+
+    /// Run `f` on the table through a [`ShardTable`], without locking since access is
+    /// exclusive. Its writes are reported to the write-through store before returning.
+    pub fn with_table_mut<R>(&mut self, f: impl FnOnce(&mut ShardTable<'_, K, V>) -> R) -> R {
+        let result = f(&mut ShardTable::new(self.map.get_mut(), &self.mirror));
+        self.mirror.report(self.mirror.take_pending());
+        self.sync_len_hint();
+        result
+    }
+
+    /// `with_table_mut` under the write lock, for when the shard is shared.
+    pub fn with_table_locked<R>(&self, f: impl FnOnce(&mut ShardTable<'_, K, V>) -> R) -> R {
+        // The table is dropped, reporting its touched values, before the guard releases
+        // the lock.
+        f(&mut ShardTable::new(&mut self.write_guard(), &self.mirror))
+    }
+
+    /// Entry count as of the last write through a lock; never blocks. Writes made through
+    /// [`with_table_mut`](Self::with_table_mut) show up once it returns.
+    pub fn len_hint(&self) -> usize {
+        self.len_hint.load(Ordering::Relaxed)
+    }
+
+    /// Refresh the length hint after changing the table without a lock.
+    fn sync_len_hint(&mut self) {
+        *self.len_hint.get_mut() = self.map.get_mut().len();
+    }
+
+    /// Whether `additional` new keys fit in `map` (this shard's locked table) without a
+    /// checked insert being rejected. Always true unless the shard is bounded with
+    /// `OnFull::Reject`.
+    pub fn has_room_for(&self, map: &Table<K, V>, additional: usize) -> bool {
+        match self.bound {
+            Some(bound) if bound.on_full == OnFull::Reject => {
+                map.len() + additional <= bound.max_entries
+            }
+            _ => true,
+        }
+    }
+
+    /// Insert a new key into `map`, this shard's table whose write lock the caller holds,
+    /// evicting to stay within the bound and tracking order.
+    pub fn insert_locked(&self, map: &mut Table<K, V>, key: K, value: Arc<V>) {
+        self.admit(map, &key);
+        self.insert_new(map, key, value);
+    }
+
+    /// Replace the value of a present key in `map`, this shard's table whose write lock the
+    /// caller holds. Returns the previous value, or `None` if the key is absent.
+    pub fn replace_locked(&self, map: &mut Table<K, V>, key: &K, value: Arc<V>) -> Option<Arc<V>> {
+        let slot = map.get_mut(key)?;
+        let previous = std::mem::replace(&mut slot.value, value);
+        self.mirror.inserted(key, slot);
+        self.stats.record_write();
+        #[cfg(feature = "lru")]
+        self.touch_recent(map, key);
+        Some(previous)
+    }
+
+    /// Remove a key from `map`, this shard's table whose write lock the caller holds.
+    pub fn remove_locked(&self, map: &mut Table<K, V>, key: &K) -> Option<Arc<V>> {
+        let slot = map.remove(key)?;
+        self.stats.record_remove();
+        self.mirror.removed(key);
+        Some(slot.into_value())
+    }
+
+    /// Take a key out of `map`, this shard's table whose write lock the caller holds, to
+    /// move it to another shard of the same map with
+    /// [`insert_relocated_locked`](Self::insert_relocated_locked). The key stays in the map,
+    /// so the move isn't reported to the write-through store.
+    pub fn extract_locked(&self, map: &mut Table<K, V>, key: &K) -> Option<Arc<V>> {
+        let slot = map.remove(key)?;
+        self.stats.record_remove();
+        Some(slot.into_value())
+    }
+
+    /// Insert a key moved from another shard by [`extract_locked`](Self::extract_locked),
+    /// evicting to stay within the bound.
+    pub fn insert_relocated_locked(&self, map: &mut Table<K, V>, key: K, value: Arc<V>) {
+        self.admit(map, &key);
+        self.insert_tracked(map, key, value);
+        self.stats.record_write();
+    }
+
+    /// Exchange contents (entries and eviction order) with another shard under both write
+    /// locks. Locks are taken in address order so opposite swaps can't deadlock.
+    pub fn swap_contents(&self, other: &Shard<K, V>) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        let (first, second) = if (self as *const Self) < (other as *const Self) {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut first_map = first.write_guard();
+        let mut second_map = second.write_guard();
+        std::mem::swap(&mut *first_map, &mut *second_map);
+        // Each side's store sees its old keys go and the swapped-in entries arrive.
+        for (shard, old, new) in [
+            (first, &second_map, &first_map),
+            (second, &first_map, &second_map),
+        ] {
+            if shard.mirror.is_active() {
+                old.keys().for_each(|k| shard.mirror.removed(k));
+                new.iter()
+                    .for_each(|(k, slot)| shard.mirror.inserted(k, slot));
+            }
+        }
+        if let (Some(a), Some(b)) = (&first.order, &second.order) {
+            std::mem::swap(&mut *a.lock(), &mut *b.lock());
+        }
+        first.stats.record_write();
+        second.stats.record_write();
+    }
+
+    /// Remove and return every entry matching `pred`, for relocation between shards.
+    pub fn extract_where<F>(&self, mut pred: F) -> Vec<(K, Arc<V>)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let mut map = self.write_guard();
+        map.extract_if(|k, _| pred(k))
+            .map(|(key, slot)| (key, slot.into_value()))
+            .collect()
+    }
+
+    /// Insert a relocated entry, ignoring the bound.
+    pub fn insert_exclusive(&self, key: K, value: Arc<V>) {
+        let mut map = self.write_guard();
+        match map.get_mut(&key) {
+            Some(slot) => **slot = value,
+            None => self.insert_tracked(&mut map, key, value),
+        }
+    }
+
+    /// Move this shard out, leaving an empty shard with the same bound and table hasher.
+    pub fn take(&mut self) -> Self {
+        let table_state = self.map.get_mut().hasher().clone();
+        std::mem::replace(
+            self,
+            Self::with_capacity_and_bound(0, self.bound, table_state),
+        )
+    }
+
+    /// Move this shard's entries and eviction order into a new shard under the write lock,
+    /// leaving this one empty. Operation counters start over in the new shard.
+    pub fn detach(&self) -> Self {
+        let mut map = self.write_guard();
+        let table_state = map.hasher().clone();
+        let entries = std::mem::replace(&mut *map, HashMap::with_hasher(table_state.clone()));
+        let mut detached = Self::with_capacity_and_bound(0, self.bound, table_state);
+        detached.mirror = Mirror::new(self.mirror.hook().cloned());
+        detached.len_hint.store(entries.len(), Ordering::Relaxed);
+        *detached.map.write() = entries;
+        if let (Some(from), Some(to)) = (&self.order, &detached.order) {
+            std::mem::swap(&mut *from.lock(), &mut *to.lock());
+        }
+        detached
+    }
+
+    /// Record an operation in this shard's trace buffer.
+    #[cfg(feature = "op-trace")]
+    #[inline]
+    pub fn trace(&self, kind: OpKind, key_hash: u64) {
+        self.trace.record(kind, key_hash);
+    }
+
+    /// The last `limit` traced operations, oldest first.
+    #[cfg(feature = "op-trace")]
+    pub fn recent_ops(&self, limit: usize) -> Vec<(OpKind, u64)> {
+        self.trace.recent(limit)
+    }
+
+    /// Subscribe to changes of `key`'s value. The receiver starts with the current value.
+    #[cfg(feature = "watch")]
+    pub fn watch_key(&self, key: K) -> tokio::sync::watch::Receiver<Option<Arc<V>>> {
+        // Holding the read lock keeps writers out between reading and registering.
+        let map = self.read_guard();
+        let current = map.get(&key).map(|slot| Arc::clone(slot));
+        self.watchers.subscribe(key, current)
+    }
+
     /// Check if a key exists without cloning the value.
     pub fn contains_key(&self, key: &K) -> bool {
-        self.read_guard().contains_key(key)
+        match self.frozen_view() {
+            Some(map) => map.contains_key(key),
+            None => self.read_guard().contains_key(key),
+        }
+    }
+
+    /// Check several keys under one read lock (or the frozen view), setting `found[idx]` for
+    /// each `(idx, key)` that is present.
+    pub fn contains_many<Q>(&self, keys: &[(usize, &Q)], found: &mut [bool])
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let check = |map: &Table<K, V>, found: &mut [bool]| {
+            for &(idx, key) in keys {
+                found[idx] = map.contains_key(key);
+            }
+        };
+        match self.frozen_view() {
+            Some(map) => check(map, found),
+            None => check(&self.read_guard(), found),
+        }
+    }
+
+    /// Whether the value stored for `key` is the same allocation as `arc`.
+    pub fn holds_same(&self, key: &K, arc: &Arc<V>) -> bool {
+        let same = |map: &Table<K, V>| map.get(key).is_some_and(|v| Arc::ptr_eq(v, arc));
+        match self.frozen_view() {
+            Some(map) => same(map),
+            None => same(&self.read_guard()),
+        }
+    }
+
+    /// `get` without any access bookkeeping: no read, hit/miss or touch counts and no LRU
+    /// recency bump. The lock acquisition itself is still counted.
+    pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+        match self.frozen_view() {
+            Some(map) => map.get(key).map(|slot| Arc::clone(slot)),
+            None => self.read_guard().get(key).map(|slot| Arc::clone(slot)),
+        }
     }
 
     /// Atomically rename a key within this shard.
     /// Returns Ok(()) on success, or an error if the old key doesn't exist
     /// or the new key already exists.
-    pub fn rename(&self, old_key: &K, new_key: K) -> Result<(), crate::error::Error> {
+    pub fn rename(&self, old_key: &K, new_key: K) -> Result<(), Error> {
         let mut map = self.write_guard();
 
         if !map.contains_key(old_key) {
-            return Err(crate::error::Error::KeyNotFound);
+            return Err(Error::KeyNotFound);
         }
 
         if map.contains_key(&new_key) {
-            return Err(crate::error::Error::KeyAlreadyExists);
+            return Err(Error::KeyAlreadyExists);
         }
 
         // Atomic operation: remove and insert in one lock acquisition
-        if let Some(value) = map.remove(old_key) {
-            map.insert(new_key, value);
-            self.stats.record_write();
+        if let Some((old_key, slot)) = map.remove_entry(old_key) {
+            self.mirror.removed(&old_key);
+            self.insert_new(&mut map, new_key, slot.into_value());
             Ok(())
         } else {
-            Err(crate::error::Error::KeyNotFound)
+            Err(Error::KeyNotFound)
+        }
+    }
+
+    /// Insert an existing Arc, respecting the bound (used for cross-shard renames).
+    pub fn checked_insert_arc(&self, key: K, value: Arc<V>) -> Result<Option<Arc<V>>, Error> {
+        let mut map = self.write_guard();
+        if let Some(slot) = map.get_mut(&key) {
+            let previous = std::mem::replace(&mut slot.value, value);
+            self.mirror.inserted(&key, slot);
+            return Ok(Some(previous));
+        }
+        if self.rejects(&map, &key) {
+            return Err(Error::ShardFull);
         }
+        self.admit(&mut map, &key);
+        self.insert_new(&mut map, key, value);
+        Ok(None)
     }
 
-    /// Insert a value with an existing Arc (used for cross-shard renames).
+    /// Insert a value with an existing Arc, ignoring the bound (used to restore entries that
+    /// were just removed, and to fill sibling maps).
     pub fn insert_arc(&self, key: K, value: Arc<V>) -> Option<Arc<V>> {
         let mut map = self.write_guard();
-        let result = map.insert(key, value);
-        if result.is_none() {
-            self.stats.record_write();
+        if let Some(slot) = map.get_mut(&key) {
+            let previous = std::mem::replace(&mut slot.value, value);
+            self.mirror.inserted(&key, slot);
+            return Some(previous);
         }
-        result
+        self.insert_new(&mut map, key, value);
+        None
     }
 
     /// Get the value for the key, or insert and return the new Arc.
     pub fn get_or_insert(&self, key: K, value: V) -> Arc<V> {
         let mut map = self.write_guard();
-        if let Some(arc) = map.get(&key) {
-            return arc.clone();
+        if let Some(slot) = map.get(&key) {
+            let arc = Arc::clone(slot);
+            #[cfg(feature = "lru")]
+            self.touch_recent(&map, &key);
+            return arc;
         }
-        self.stats.record_write();
+        self.admit(&mut map, &key);
         let arc = Arc::new(value);
-        map.insert(key, arc.clone());
+        self.insert_new(&mut map, key, arc.clone());
         arc
     }
 
     /// Get the value for the key, or compute with f, insert, and return the new Arc.
     pub fn get_or_insert_with<F>(&self, key: K, f: F) -> Arc<V>
+    where
+        F: FnOnce() -> V,
+    {
+        self.get_or_insert_with_status(key, f).0
+    }
+
+    /// `get_or_insert_with` that also reports whether `f` ran and its value was inserted.
+    pub fn get_or_insert_with_status<F>(&self, key: K, f: F) -> (Arc<V>, bool)
     where
         F: FnOnce() -> V,
     {
         let mut map = self.write_guard();
-        if let Some(arc) = map.get(&key) {
-            return arc.clone();
+        if let Some(slot) = map.get(&key) {
+            let arc = Arc::clone(slot);
+            #[cfg(feature = "lru")]
+            self.touch_recent(&map, &key);
+            return (arc, false);
         }
-        self.stats.record_write();
+        // Build the value before evicting anything, so a panicking `f` unwinds with the
+        // shard exactly as it was.
         let arc = Arc::new(f());
-        map.insert(key, arc.clone());
+        self.admit(&mut map, &key);
+        self.insert_new(&mut map, key, arc.clone());
+        (arc, true)
+    }
+
+    /// `get_or_insert`, then apply `f` to the stored value under the same write lock. A new
+    /// value is changed before it is inserted; a present one is changed copy-on-write.
+    pub fn get_or_insert_then<F>(&self, key: K, mut value: V, f: F) -> Arc<V>
+    where
+        F: FnOnce(&mut V),
+        V: Clone,
+    {
+        let mut map = self.write_guard();
+        if let Some(slot) = map.get_mut(&key) {
+            f(Arc::make_mut(slot));
+            let arc = Arc::clone(slot);
+            self.stats.record_write();
+            self.mirror.inserted(&key, slot);
+            #[cfg(feature = "lru")]
+            self.touch_recent(&map, &key);
+            return arc;
+        }
+        f(&mut value);
+        let arc = Arc::new(value);
+        self.admit(&mut map, &key);
+        self.insert_new(&mut map, key, arc.clone());
         arc
     }
 
+    /// Batched `get_or_insert_with` under a single write lock. Each item carries its
+    /// caller-side index, which is returned alongside the resulting Arc.
+    pub fn get_or_insert_many<F>(
+        &self,
+        items: Vec<(usize, K)>,
+        make: &mut F,
+    ) -> Vec<(usize, Arc<V>)>
+    where
+        F: FnMut(&K) -> V,
+    {
+        let mut map = self.write_guard();
+        let mut out = Vec::with_capacity(items.len());
+        for (idx, key) in items {
+            if let Some(slot) = map.get(&key) {
+                out.push((idx, Arc::clone(slot)));
+                #[cfg(feature = "lru")]
+                self.touch_recent(&map, &key);
+                continue;
+            }
+            let arc = Arc::new(make(&key));
+            self.admit(&mut map, &key);
+            self.insert_new(&mut map, key, arc.clone());
+            out.push((idx, arc));
+        }
+        out
+    }
+
+    /// Insert or replace every item under a single write lock. Returns how many keys
+    /// already existed.
+    pub fn overwrite_many(&self, items: Vec<(K, V)>) -> usize {
+        let mut map = self.write_guard();
+        let mut overwritten = 0;
+        for (key, value) in items {
+            if let Some(slot) = map.get_mut(&key) {
+                **slot = Arc::new(value);
+                self.mirror.inserted(&key, slot);
+                #[cfg(feature = "lru")]
+                self.touch_recent(&map, &key);
+                overwritten += 1;
+                continue;
+            }
+            self.admit(&mut map, &key);
+            self.insert_new(&mut map, key, Arc::new(value));
+        }
+        overwritten
+    }
+
+    /// Insert every item under a single write lock, combining with the present value via
+    /// `resolve(key, existing, incoming)` when the key exists. Returns how many were combined.
+    pub fn merge_many<F>(&self, items: Vec<(K, V)>, resolve: &F) -> usize
+    where
+        F: Fn(&K, &V, V) -> V,
+    {
+        let mut map = self.write_guard();
+        let mut merged = 0;
+        for (key, value) in items {
+            if let Some(slot) = map.get_mut(&key) {
+                **slot = Arc::new(resolve(&key, slot, value));
+                self.stats.record_write();
+                self.mirror.inserted(&key, slot);
+                #[cfg(feature = "lru")]
+                self.touch_recent(&map, &key);
+                merged += 1;
+                continue;
+            }
+            self.admit(&mut map, &key);
+            self.insert_new(&mut map, key, Arc::new(value));
+        }
+        merged
+    }
+
+    /// Apply removals, then upserts, under a single write lock.
+    pub fn apply_changes(&self, removals: Vec<K>, upserts: Vec<(K, V)>) {
+        let mut map = self.write_guard();
+        for key in removals {
+            if map.remove(&key).is_some() {
+                self.stats.record_remove();
+                self.mirror.removed(&key);
+            }
+        }
+        for (key, value) in upserts {
+            if let Some(slot) = map.get_mut(&key) {
+                **slot = Arc::new(value);
+                self.mirror.inserted(&key, slot);
+                #[cfg(feature = "lru")]
+                self.touch_recent(&map, &key);
+                continue;
+            }
+            self.admit(&mut map, &key);
+            self.insert_new(&mut map, key, Arc::new(value));
+        }
+    }
+
     /// Insert only if the key is not present. Ok(inserted) or Err(existing).
     pub fn try_insert(&self, key: K, value: V) -> Result<Arc<V>, Arc<V>> {
+        self.try_insert_arc(key, Arc::new(value))
+    }
+
+    /// `try_insert` with an already shared value.
+    pub fn try_insert_arc(&self, key: K, arc: Arc<V>) -> Result<Arc<V>, Arc<V>> {
         let mut map = self.write_guard();
-        if let Some(arc) = map.get(&key) {
-            return Err(arc.clone());
+        if let Some(existing) = map.get(&key) {
+            return Err(Arc::clone(existing));
         }
+        self.admit(&mut map, &key);
+        self.insert_new(&mut map, key, arc.clone());
+        Ok(arc)
+    }
+
+    /// `try_insert_arc` for a value just loaded by read-through. The insert came from the
+    /// backing store, so it isn't reported back to the write-through store; evictions it
+    /// causes still are.
+    pub fn cache_loaded(&self, key: K, arc: Arc<V>) -> Result<Arc<V>, Arc<V>> {
+        let mut map = self.write_guard();
+        if let Some(existing) = map.get(&key) {
+            return Err(Arc::clone(existing));
+        }
+        self.admit(&mut map, &key);
+        self.insert_tracked(&mut map, key, arc.clone());
         self.stats.record_write();
-        let arc = Arc::new(value);
-        map.insert(key, arc.clone());
         Ok(arc)
     }
 }
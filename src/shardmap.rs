@@ -1,12 +1,63 @@
-use crate::config::{create_hasher, Config, RoutingConfig};
-use crate::error::Error;
-use crate::hash::ShardHasher;
-use crate::shard::Shard;
-use crate::stats::{Diagnostics, ShardDiagnostics, ShardOps, Stats};
+use crate::config::{
+    create_hasher, valid_shard_count, Config, DefaultRouter, HashFunction, Router, RoutingConfig,
+    RoutingDescriptor, ShardRouter, SplitRouter,
+};
+use crate::entry::Entry;
+use crate::error::{Error, TimedOut, TryReserveError};
+use crate::hash::{seeded_ahash, ShardHasher};
+use crate::read_only::ReadOnly;
+use crate::read_through::ReadThrough;
+use crate::shard::{EvictingInsert, GlobalReadScope, Shard, ShardTable, ShardWriteGuard};
+use crate::single_flight::Entered;
+use crate::stats::{
+    Diagnostics, DiagnosticsSnapshot, ProbeStats, ShardDiagnostics, ShardOps, Stats,
+};
+#[cfg(feature = "op-trace")]
+use crate::trace::OpKind;
+use crate::write_through::WriteThroughHook;
 use std::borrow::Borrow;
 use std::hash::Hash;
 use std::sync::Arc;
 
+/// An entry taken out of the map (e.g. by eviction): the key and its shared value.
+pub type RemovedEntry<K, V> = (K, Arc<V>);
+
+/// What [`ShardMap::insert_classified`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertOutcome<V> {
+    /// The key was new; holds the stored value.
+    Inserted(Arc<V>),
+    /// The key was present and its value replaced.
+    Updated {
+        /// The value that was replaced.
+        old: Arc<V>,
+        /// The value now stored.
+        new: Arc<V>,
+    },
+}
+
+/// Key transform applied before routing and storage; see
+/// [`ShardMapBuilder::key_normalizer`](crate::ShardMapBuilder::key_normalizer).
+pub type KeyNormalizer<K> = Arc<dyn Fn(&K) -> K + Send + Sync>;
+
+/// A change set for [`ShardMap::apply_diff`]: keys to insert or overwrite, and keys to remove.
+#[derive(Debug, Clone)]
+pub struct Diff<K, V> {
+    /// Entries to insert, replacing the value of keys that already exist.
+    pub upserts: Vec<(K, V)>,
+    /// Keys to remove. Absent keys are ignored.
+    pub removals: Vec<K>,
+}
+
+impl<K, V> Default for Diff<K, V> {
+    fn default() -> Self {
+        Self {
+            upserts: Vec::new(),
+            removals: Vec::new(),
+        }
+    }
+}
+
 /// High-performance concurrent sharded map.
 ///
 /// Splits your data across multiple shards, each with its own lock. This means
@@ -26,10 +77,15 @@ use std::sync::Arc;
 /// }
 /// ```
 pub struct ShardMap<K, V> {
-    shards: Vec<Shard<K, V>>,
-    shard_mask: usize,
-    hash: ShardHasher,
-    routing: RoutingConfig,
+    /// Shared with detached iterators from `iter_concurrent`.
+    shards: Arc<[Shard<K, V>]>,
+    hash: Arc<ShardHasher>,
+    router: Router,
+    normalizer: Option<KeyNormalizer<K>>,
+    read_through: Option<Arc<dyn ReadThrough<K, V>>>,
+    /// Per-shard write counts at the last `shrink_idle_shards` call.
+    #[cfg(feature = "metrics")]
+    write_baseline: parking_lot::Mutex<Option<Vec<u64>>>,
 }
 
 impl<K, V> ShardMap<K, V>
@@ -58,44 +114,332 @@ where
         Self::with_config(config).unwrap()
     }
 
+    /// Create a map with `shard_count` shards (a power of two) and room for at least
+    /// `total_capacity` entries, split evenly across the shards (rounded up).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<u64, u64> = ShardMap::with_capacity_and_shards(10_000, 32)?;
+    /// assert_eq!(map.shard_count(), 32);
+    /// assert!(map.capacity() >= 10_000);
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn with_capacity_and_shards(
+        total_capacity: usize,
+        shard_count: usize,
+    ) -> Result<Self, Error> {
+        let config = Config::default().shard_count(shard_count)?;
+        let cap_per_shard = total_capacity.div_ceil(shard_count);
+        Self::with_config(config.capacity_per_shard(cap_per_shard))
+    }
+
+    /// Build a map with `shard_count` shards (a power of two) holding `pairs`, the fastest
+    /// way to load a known dataset.
+    ///
+    /// Every shard is presized to `ceil(pairs.len() / shard_count)` entries, or to its share
+    /// of `pairs` if routing gave it more, so no table resizes during the load. Pairs are
+    /// grouped by shard and each group is inserted under one lock; for duplicate keys the
+    /// last pair wins.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::from_pairs(vec![("a", 1), ("b", 2)], 4)?;
+    /// assert_eq!(map.len(), 2);
+    /// assert_eq!(*map.get(&"b").unwrap(), 2);
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn from_pairs(pairs: Vec<(K, V)>, shard_count: usize) -> Result<Self, Error> {
+        let map = Self::with_capacity_and_shards(pairs.len(), shard_count)?;
+        let mut buckets: Vec<Vec<(K, V)>> = (0..shard_count).map(|_| Vec::new()).collect();
+        for (key, value) in pairs {
+            let shard_idx = map.shard_index(&key);
+            buckets[shard_idx].push((key, value));
+        }
+        for (shard, items) in map.shards.iter().zip(buckets) {
+            if !items.is_empty() {
+                shard.reserve_to(items.len());
+                shard.overwrite_many(items);
+            }
+        }
+        Ok(map)
+    }
+
     /// Create a new map with custom config.
     pub fn with_config(config: Config) -> Result<Self, Error> {
-        if config.shard_count == 0 || !config.shard_count.is_power_of_two() {
+        if !valid_shard_count(config.shard_count) {
             return Err(Error::InvalidShardCount);
         }
 
+        if config.max_entries_per_shard == Some(0) {
+            return Err(Error::InvalidCapacity);
+        }
+
         let shard_count = config.shard_count;
         let cap_per_shard = config.capacity_per_shard.unwrap_or(0);
+        let bound = config.shard_bound();
         let mut shards = Vec::with_capacity(shard_count);
         for _ in 0..shard_count {
-            shards.push(Shard::with_capacity(cap_per_shard));
+            let table_state = match config.table_seed {
+                Some(seed) => seeded_ahash(seed),
+                None => ahash::RandomState::new(),
+            };
+            shards.push(Shard::with_capacity_and_bound(
+                cap_per_shard,
+                bound,
+                table_state,
+            ));
         }
 
         Ok(Self {
-            shards,
-            shard_mask: shard_count - 1,
-            hash: create_hasher(config.hash_function),
-            routing: config.routing,
+            shards: shards.into(),
+            hash: config
+                .shared_hasher
+                .unwrap_or_else(|| Arc::new(create_hasher(config.hash_function, config.seed))),
+            router: config.routing.into(),
+            normalizer: None,
+            read_through: None,
+            #[cfg(feature = "metrics")]
+            write_baseline: parking_lot::Mutex::new(None),
         })
     }
 
-    /// Route a key hash to a shard index.
+    /// Set the key normalizer; only for freshly built maps, since present keys are not
+    /// re-normalized.
+    pub(crate) fn with_key_normalizer(mut self, normalizer: KeyNormalizer<K>) -> Self {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    /// Report every write to `hook`'s store; only for freshly built maps.
+    pub(crate) fn with_write_through(mut self, hook: WriteThroughHook<K, V>) -> Self {
+        let shards = Arc::get_mut(&mut self.shards).expect("a freshly built map isn't shared");
+        for shard in shards {
+            shard.set_write_through(hook.clone());
+        }
+        self
+    }
+
+    /// Backfill misses in [`get_through`](Self::get_through) from `source`, e.g. when this map
+    /// is a front cache for a database. Sibling maps don't inherit it.
+    pub fn with_read_through(mut self, source: Arc<dyn ReadThrough<K, V>>) -> Self {
+        self.read_through = Some(source);
+        self
+    }
+
+    /// Get a value, loading and caching it from the [`ReadThrough`] source on a miss.
+    ///
+    /// [`load`](ReadThrough::load) runs without the shard lock held, so a slow source doesn't
+    /// block other operations on the shard. If another thread cached the same key meanwhile,
+    /// its value is kept and returned, and the freshly loaded one is dropped. Backfilled
+    /// values are not reported to a write-through store. Without a registered source this is the same as [`get`](Self::get).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{ReadThrough, ShardMap};
+    /// use std::sync::Arc;
+    ///
+    /// struct Squares;
+    /// impl ReadThrough<u64, u64> for Squares {
+    ///     fn load(&self, key: &u64) -> Option<u64> {
+    ///         Some(key * key)
+    ///     }
+    /// }
+    ///
+    /// let map = ShardMap::new().with_read_through(Arc::new(Squares));
+    /// assert_eq!(*map.get_through(&12).unwrap(), 144);
+    /// assert!(map.contains_key(&12));
+    /// ```
+    pub fn get_through(&self, key: &K) -> Option<Arc<V>>
+    where
+        K: Clone,
+    {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let hash = self.key_hash(key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Get, hash);
+        let shard = &self.shards[shard_idx];
+        if let Some(value) = shard.get(key) {
+            return Some(value);
+        }
+        let loaded = self.read_through.as_ref()?.load(key)?;
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        let loaded = Arc::new(loaded);
+        match shard.cache_loaded(key.clone(), loaded) {
+            Ok(cached) | Err(cached) => Some(cached),
+        }
+    }
+
+    /// Insert into a shard, reporting the write to the write-through store if there is one.
+    fn shard_insert(&self, shard_idx: usize, key: K, value: V) -> EvictingInsert<K, V> {
+        self.shard_insert_in(
+            shard_idx,
+            self.shards[shard_idx].write_lock(),
+            key,
+            Arc::new(value),
+        )
+    }
+
+    /// `shard_insert` through an already taken write lock on that shard.
+    fn shard_insert_in(
+        &self,
+        shard_idx: usize,
+        map: ShardWriteGuard<'_, K, V>,
+        key: K,
+        value: Arc<V>,
+    ) -> EvictingInsert<K, V> {
+        self.shards[shard_idx].insert_evicting_in(map, key, value)
+    }
+
+    /// Move the shards out, leaving the map with none. If a detached
+    /// [`iter_concurrent`](Self::iter_concurrent) iterator still shares them, their contents
+    /// are moved out under each shard's write lock instead, and the iterator sees empty
+    /// shards from then on.
+    fn take_shards(&mut self) -> Vec<Shard<K, V>> {
+        let shards = match Arc::get_mut(&mut self.shards) {
+            Some(shards) => shards.iter_mut().map(Shard::take).collect(),
+            None => self.shards.iter().map(Shard::detach).collect(),
+        };
+        self.shards = Arc::new([]);
+        shards
+    }
+
+    /// Apply the key normalizer, if any, to an owned key.
+    #[inline]
+    fn normalize(&self, key: K) -> K {
+        match &self.normalizer {
+            Some(normalize) => normalize(&key),
+            None => key,
+        }
+    }
+
+    /// Apply the key normalizer, if any, to a borrowed key; the normalized key lives in `slot`.
+    #[inline]
+    fn normalize_ref<'a>(&self, key: &'a K, slot: &'a mut Option<K>) -> &'a K {
+        match &self.normalizer {
+            Some(normalize) => slot.insert(normalize(key)),
+            None => key,
+        }
+    }
+
+    /// Apply the key normalizer, if any, to a batch of borrowed keys; the normalized keys
+    /// live in `slot`.
+    fn normalize_all<'a>(&self, keys: &[&'a K], slot: &'a mut Vec<K>) -> Vec<&'a K> {
+        match &self.normalizer {
+            Some(normalize) => {
+                *slot = keys.iter().map(|key| normalize(key)).collect();
+                slot.iter().collect()
+            }
+            None => keys.to_vec(),
+        }
+    }
+
+    /// Route a key hash to a shard index. The router is always consulted, including for
+    /// `RoutingConfig::Default` (which uses `DefaultRouter`).
     #[inline]
     fn route_hash(&self, hash: u64) -> usize {
-        match &self.routing {
-            RoutingConfig::Default => (hash as usize) & self.shard_mask,
-            RoutingConfig::Custom(router) => router.route(hash, self.shards.len()),
+        self.router.route(hash, self.shards.len())
+    }
+
+    /// An empty map with the same shard count, bound, hashers, router and key normalizer.
+    fn empty_sibling(&self) -> Self {
+        let bound = self.shards[0].bound();
+        Self {
+            shards: self
+                .shards
+                .iter()
+                .map(|shard| Shard::with_capacity_and_bound(0, bound, shard.table_state()))
+                .collect(),
+            hash: self.hash.clone(),
+            router: self.router.clone(),
+            normalizer: self.normalizer.clone(),
+            read_through: None,
+            #[cfg(feature = "metrics")]
+            write_baseline: parking_lot::Mutex::new(None),
         }
     }
 
-    /// Figure out which shard this key belongs to.
+    /// Figure out which shard this (already normalized) key belongs to.
     #[inline]
     fn shard_index(&self, key: &K) -> usize {
-        let hash = self.hash.hash_key(key);
-        self.route_hash(hash)
+        self.routed_index(self.key_hash(key))
+    }
+
+    /// The routing hash of an already normalized key.
+    #[inline]
+    fn key_hash(&self, key: &K) -> u64 {
+        self.hash.hash_key(key)
+    }
+
+    /// Panic if the map has a key normalizer. For methods taking borrowed keys or
+    /// caller-computed hashes, which can't be normalized.
+    #[inline]
+    fn assert_unnormalized(&self, method: &str) {
+        assert!(
+            self.normalizer.is_none(),
+            "{method}: not supported on a map with a key normalizer"
+        );
+    }
+
+    /// Returns the value fed to the router for this key (the hasher output), after applying
+    /// the key normalizer.
+    ///
+    /// Routing is two stages: the hasher turns a key into a `u64`, then the router turns that
+    /// `u64` into a shard index. `routed_index(routing_value(&k)) == shard_for_key(&k)`.
+    #[inline]
+    pub fn routing_value(&self, key: &K) -> u64 {
+        let mut normalized = None;
+        self.key_hash(self.normalize_ref(key, &mut normalized))
+    }
+
+    /// A standalone hash function identical to [`hash_for_key`](Self::hash_for_key), for
+    /// auxiliary structures over the same keys (e.g. a bloom filter) that must hash exactly
+    /// like the map, fixed seed included.
+    ///
+    /// The closure shares the map's hasher, so it doesn't borrow the map and can be sent to
+    /// other threads.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<String, ()> = ShardMap::new();
+    /// let hash = map.hasher_for_key::<str>();
+    /// assert_eq!(hash("user:42"), map.hash_for_key("user:42"));
+    /// ```
+    pub fn hasher_for_key<Q>(&self) -> impl Fn(&Q) -> u64 + Clone + Send + Sync + 'static
+    where
+        Q: Hash + ?Sized,
+    {
+        let hasher = Arc::clone(&self.hash);
+        move |key: &Q| hasher.hash_key(key)
+    }
+
+    /// This map's hasher, to build other maps that hash identically with
+    /// [`ShardMapBuilder::shared_hasher`](crate::ShardMapBuilder::shared_hasher).
+    pub fn shared_hasher(&self) -> Arc<ShardHasher> {
+        Arc::clone(&self.hash)
+    }
+
+    /// Returns the shard index the router picks for a routing value (second routing stage).
+    #[inline]
+    pub fn routed_index(&self, value: u64) -> usize {
+        self.route_hash(value)
     }
 
-    /// Returns the hash of a key for shard routing. Use with `shard_for_hash` or `*_by_hash` when you already have a hash.
+    /// Returns the hash of a key for shard routing. Same as [`routing_value`](Self::routing_value),
+    /// but accepts borrowed forms of the key, which are hashed as given: the key normalizer
+    /// is not applied. Use with `shard_for_hash` or `*_by_hash` when you already have a hash.
     #[inline]
     pub fn hash_for_key<Q>(&self, key: &Q) -> u64
     where
@@ -104,10 +448,16 @@ where
         self.hash.hash_key(key)
     }
 
+    /// Returns the number of shards. Shard indices are `0..shard_count()`.
+    #[inline]
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
     /// Returns which shard index the given hash maps to. Use with pre-hashed keys.
     #[inline]
     pub fn shard_for_hash(&self, hash: u64) -> usize {
-        self.route_hash(hash)
+        self.routed_index(hash)
     }
 
     /// Returns which shard index the given key maps to.
@@ -115,6 +465,15 @@ where
     /// Use this for observability, shard-aware logic (e.g. per-shard eviction),
     /// or to interpret `stats().operations[shard_for_key(k)]`.
     ///
+    /// Every value of a zero-sized key type (such as `()`) hashes alike, so such keys all
+    /// route to a single shard; see [`is_degenerate`](Self::is_degenerate).
+    ///
+    /// # Panics
+    ///
+    /// Panics on a map with a [key normalizer](crate::ShardMapBuilder::key_normalizer), which
+    /// can't be applied to a borrowed key; route with
+    /// `routed_index(routing_value(&key))` instead.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -131,11 +490,137 @@ where
     where
         Q: Hash + ?Sized,
     {
+        self.assert_unnormalized("shard_for_key");
         self.shard_for_hash(self.hash_for_key(key))
     }
 
+    /// The shard `key` belongs to, for persistent per-shard layouts (e.g. one file per shard).
+    ///
+    /// Same result as [`shard_for_key`](Self::shard_for_key); this name marks call sites that
+    /// rely on placement being stable. Placement is stable across runs only for maps built
+    /// with [`fixed_seed`](crate::ShardMapBuilder::fixed_seed) and default routing, given the
+    /// same shard count and the version caveats documented on `fixed_seed`; without a seed,
+    /// ahash keys are generated per process. Use [`compute_shard`](Self::compute_shard) to
+    /// get the same answer without a live map. Panics like `shard_for_key` on a map with a
+    /// key normalizer.
+    pub fn canonical_shard<Q>(&self, key: &Q) -> usize
+    where
+        Q: Hash + ?Sized,
+    {
+        self.shard_for_key(key)
+    }
+
+    /// Compute the shard of `key` for a map with `shard_count` shards, the given hash function
+    /// and fixed seed, and default routing, without building a map, e.g. for offline tooling
+    /// that lays out per-shard files.
+    ///
+    /// Matches [`canonical_shard`](Self::canonical_shard) on such a map. With `seed: None`
+    /// and `HashFunction::AHash` the result only matches maps in the current process (ahash
+    /// keys are per process). Returns `Error::InvalidShardCount` unless `shard_count` is a
+    /// nonzero power of two.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{HashFunction, ShardMap, ShardMapBuilder};
+    ///
+    /// let seed = 0x5eed;
+    /// let map = ShardMapBuilder::new()
+    ///     .shard_count(64)?
+    ///     .fixed_seed(seed)
+    ///     .build::<&str, u32>()?;
+    /// let offline =
+    ///     ShardMap::<&str, u32>::compute_shard(&"user:42", 64, HashFunction::AHash, Some(seed))?;
+    /// assert_eq!(offline, map.canonical_shard(&"user:42"));
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn compute_shard<Q>(
+        key: &Q,
+        shard_count: usize,
+        hash_function: HashFunction,
+        seed: Option<u128>,
+    ) -> Result<usize, Error>
+    where
+        Q: Hash + ?Sized,
+    {
+        let config = Config::default().shard_count(shard_count)?;
+        let hash = create_hasher(hash_function, seed).hash_key(key);
+        Ok(DefaultRouter.route(hash, config.shard_count))
+    }
+
+    /// Describe this map's routing so it can be reproduced outside the process, e.g. by a
+    /// coordinator that forwards requests to the node owning a shard.
+    ///
+    /// Custom routers are reported as [`RoutingKind::Custom`](crate::RoutingKind::Custom):
+    /// the descriptor then only records the shard count and hasher. Those maps, and ahash maps
+    /// without a fixed seed, can't be reproduced elsewhere, and
+    /// [`is_reproducible`](RoutingDescriptor::is_reproducible) is `false`. A key normalizer,
+    /// if set, must also be applied externally (as `shard_for_key` does not).
+    pub fn routing_descriptor(&self) -> RoutingDescriptor {
+        let (hash_function, seed) = self.hash.describe();
+        RoutingDescriptor {
+            shard_count: self.shards.len(),
+            hash_function,
+            seed,
+            routing_kind: self.router.kind(),
+        }
+    }
+
+    /// Route a batch of `u32` keys: `out[i] = shard_for_key(&keys[i])`.
+    ///
+    /// For packet-style pipelines that route many small integer keys. The hasher and router
+    /// are resolved once per batch, so the per-key loop has no branches on them; the
+    /// `route_batch` benchmark compares it with calling [`shard_for_key`](Self::shard_for_key)
+    /// per key. Results are identical to `shard_for_key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `out.len() != keys.len()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<u32, ()> = ShardMap::new();
+    /// let keys = [1, 2, 3];
+    /// let mut shards = [0; 3];
+    /// map.route_u32_batch(&keys, &mut shards);
+    /// assert_eq!(shards[2], map.shard_for_key(&3));
+    /// ```
+    pub fn route_u32_batch(&self, keys: &[u32], out: &mut [usize]) {
+        self.route_batch(keys, out);
+    }
+
+    /// Route a batch of `u64` keys; see [`route_u32_batch`](Self::route_u32_batch).
+    pub fn route_u64_batch(&self, keys: &[u64], out: &mut [usize]) {
+        self.route_batch(keys, out);
+    }
+
+    fn route_batch<T: Hash>(&self, keys: &[T], out: &mut [usize]) {
+        assert_eq!(
+            keys.len(),
+            out.len(),
+            "route batch: `out` must be as long as `keys`"
+        );
+        let shard_count = self.shards.len();
+        match &self.router {
+            Router::Default => self
+                .hash
+                .route_batch(keys, out, |hash| DefaultRouter.route(hash, shard_count)),
+            Router::Custom(router) => self
+                .hash
+                .route_batch(keys, out, |hash| router.route(hash, shard_count)),
+        }
+    }
+
     /// Insert a key-value pair. Returns the old value if the key existed.
     ///
+    /// On a bounded map, a new key in a full shard evicts the shard's oldest entry (or least
+    /// recently used, with `OnFull::EvictLeastRecent`). That includes
+    /// [`OnFull::Reject`](crate::OnFull::Reject) maps; use
+    /// [`checked_insert`](Self::checked_insert) to be refused instead.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -146,8 +631,166 @@ where
     /// assert_eq!(map.insert("key", "new_value").unwrap().as_ref(), &"value");
     /// ```
     pub fn insert(&self, key: K, value: V) -> Option<Arc<V>> {
-        let shard_idx = self.shard_index(&key);
-        self.shards[shard_idx].insert(key, value)
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shard_insert(shard_idx, key, value).0
+    }
+
+    /// Insert a key-value pair and report whether it added the key or replaced a value,
+    /// with the stored `Arc`s on both sides, e.g. to emit create/update events.
+    ///
+    /// The outcome is decided under the same write lock as the insert, so concurrent callers
+    /// for one new key see exactly one `Inserted`. An entry evicted from a bounded shard to
+    /// make room is not part of the outcome; use [`insert_evicting`](Self::insert_evicting)
+    /// for that.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{InsertOutcome, ShardMap};
+    ///
+    /// let map = ShardMap::new();
+    /// assert!(matches!(map.insert_classified("k", 1), InsertOutcome::Inserted(_)));
+    /// match map.insert_classified("k", 2) {
+    ///     InsertOutcome::Updated { old, new } => assert_eq!((*old, *new), (1, 2)),
+    ///     InsertOutcome::Inserted(_) => unreachable!(),
+    /// }
+    /// ```
+    pub fn insert_classified(&self, key: K, value: V) -> InsertOutcome<V> {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        let new = Arc::new(value);
+        let map = self.shards[shard_idx].write_lock();
+        match self
+            .shard_insert_in(shard_idx, map, key, Arc::clone(&new))
+            .0
+        {
+            Some(old) => InsertOutcome::Updated { old, new },
+            None => InsertOutcome::Inserted(new),
+        }
+    }
+
+    /// Like [`insert`](Self::insert), but waits at most `timeout` for the shard's write lock,
+    /// for SLA-bound handlers that would rather fail than queue behind a long writer.
+    /// Returns [`TimedOut`] without inserting if the lock isn't acquired in time.
+    pub fn insert_timeout(
+        &self,
+        key: K,
+        value: V,
+        timeout: std::time::Duration,
+    ) -> Result<Option<Arc<V>>, TimedOut> {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        let map = self.shards[shard_idx]
+            .try_write_lock_for(timeout)
+            .ok_or(TimedOut)?;
+        Ok(self.shard_insert_in(shard_idx, map, key, Arc::new(value)).0)
+    }
+
+    /// Insert a key-value pair, or fail with [`Error::ShardFull`] if the map is bounded with
+    /// [`OnFull::Reject`](crate::OnFull::Reject) and the key is new to a full shard.
+    /// Returns the old value if the key existed. Never fails on an unbounded map, and
+    /// evicts like [`insert`](Self::insert) with the other policies.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{Error, OnFull, ShardMapBuilder};
+    ///
+    /// let map = ShardMapBuilder::new()
+    ///     .shard_count(1)?
+    ///     .max_entries_per_shard(1)
+    ///     .on_full(OnFull::Reject)
+    ///     .build::<&str, i32>()?;
+    /// assert_eq!(map.checked_insert("a", 1), Ok(None));
+    /// assert_eq!(map.checked_insert("b", 2), Err(Error::ShardFull));
+    /// assert_eq!(*map.checked_insert("a", 3)?.unwrap(), 1); // overwrites are always allowed
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn checked_insert(&self, key: K, value: V) -> Result<Option<Arc<V>>, Error> {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        let shard = &self.shards[shard_idx];
+        let map = shard.write_lock();
+        if shard.rejects(&map, &key) {
+            return Err(Error::ShardFull);
+        }
+        Ok(self.shard_insert_in(shard_idx, map, key, Arc::new(value)).0)
+    }
+
+    /// Insert a key-value pair and report what it displaced: the previous value for this
+    /// key, and the entry evicted to make room.
+    ///
+    /// An entry is only evicted on a bounded map when the key is new and its shard is full;
+    /// the evicted entry is the shard's oldest by insertion order (or least recently used,
+    /// with `OnFull::EvictLeastRecent` and the `lru` feature).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{OnFull, ShardMapBuilder};
+    ///
+    /// let map = ShardMapBuilder::new()
+    ///     .shard_count(1)?
+    ///     .max_entries_per_shard(2)
+    ///     .on_full(OnFull::EvictOldest)
+    ///     .build::<&str, i32>()?;
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// let (previous, evicted) = map.insert_evicting("c", 3);
+    /// assert!(previous.is_none());
+    /// assert_eq!(evicted.map(|(k, _)| k), Some("a"));
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn insert_evicting(
+        &self,
+        key: K,
+        value: V,
+    ) -> (Option<Arc<V>>, Option<RemovedEntry<K, V>>) {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shard_insert(shard_idx, key, value)
+    }
+
+    /// Insert a key-value pair, returning the previously stored key object and value.
+    ///
+    /// Unlike [`insert`](Self::insert), which keeps the existing key when overwriting, this
+    /// replaces the stored key with `key` and hands back the old key object. Useful with
+    /// interned keys, where equal keys can still differ by identity.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// assert_eq!(map.insert_full("k".to_string(), 1), (None, None));
+    /// let (old_key, old_value) = map.insert_full("k".to_string(), 2);
+    /// assert_eq!(old_key.as_deref(), Some("k"));
+    /// assert_eq!(old_value.as_deref(), Some(&1));
+    /// ```
+    pub fn insert_full(&self, key: K, value: V) -> (Option<K>, Option<Arc<V>>) {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shards[shard_idx].insert_full(key, value)
     }
 
     /// Get a value by key. Returns an `Arc<V>` so you can share it without copying.
@@ -166,10 +809,97 @@ where
     /// }
     /// ```
     pub fn get(&self, key: &K) -> Option<Arc<V>> {
-        let shard_idx = self.shard_index(key);
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let hash = self.key_hash(key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Get, hash);
         self.shards[shard_idx].get(key)
     }
 
+    /// Like [`get`](Self::get), but waits at most `timeout` for the shard's read lock, for
+    /// SLA-bound handlers that would rather give up than wait behind a long writer. Returns
+    /// [`TimedOut`] if the lock isn't acquired in time.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    /// use std::time::Duration;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("k", 1);
+    /// let value = map.get_timeout(&"k", Duration::from_micros(500))?;
+    /// assert_eq!(value.as_deref(), Some(&1));
+    /// # Ok::<(), shardmap::TimedOut>(())
+    /// ```
+    pub fn get_timeout(
+        &self,
+        key: &K,
+        timeout: std::time::Duration,
+    ) -> Result<Option<Arc<V>>, TimedOut> {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let hash = self.key_hash(key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Get, hash);
+        self.shards[shard_idx].get_timeout(key, timeout)
+    }
+
+    /// Like [`get`](Self::get), but invisible to access metrics, for monitoring code that
+    /// inspects values.
+    ///
+    /// `get` counts a read (and a hit or miss and a touch with `metrics`), bumps LRU recency
+    /// with `lru`, and is recorded by `op-trace`; `peek` does none of these, so it doesn't skew
+    /// `stats()`, `access_distribution()` or eviction order. It still takes the shard read
+    /// lock, which `lock_acquisitions` counts.
+    pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let shard_idx = self.shard_index(key);
+        self.shards[shard_idx].peek(key)
+    }
+
+    /// Get a clone of the value for `key`, or `default` if it is absent.
+    ///
+    /// The value is cloned while the shard's read lock is held, so callers get a plain `V`
+    /// without going through `Option<Arc<V>>`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("retries", 3);
+    /// assert_eq!(map.get_cloned_or(&"retries", 0), 3);
+    /// assert_eq!(map.get_cloned_or(&"timeout", 30), 30);
+    /// ```
+    pub fn get_cloned_or(&self, key: &K, default: V) -> V
+    where
+        V: Clone,
+    {
+        self.get_cloned_or_else(key, || default)
+    }
+
+    /// Like [`get_cloned_or`](Self::get_cloned_or), but computes the default lazily with `f`
+    /// only when the key is absent.
+    pub fn get_cloned_or_else<F>(&self, key: &K, f: F) -> V
+    where
+        V: Clone,
+        F: FnOnce() -> V,
+    {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let hash = self.key_hash(key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Get, hash);
+        self.shards[shard_idx].get_cloned(key).unwrap_or_else(f)
+    }
+
     /// Remove a key-value pair, returning the value if it existed.
     ///
     /// # Example
@@ -183,74 +913,2381 @@ where
     /// assert!(map.get(&"key").is_none());
     /// ```
     pub fn remove(&self, key: &K) -> Option<Arc<V>> {
-        let shard_idx = self.shard_index(key);
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let hash = self.key_hash(key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Remove, hash);
         self.shards[shard_idx].remove(key)
     }
 
     /// Get a value by key using a precomputed hash for shard selection (avoids re-hashing for routing).
+    ///
+    /// # Panics
+    ///
+    /// This and the other `*_by_hash` methods panic on a map with a
+    /// [key normalizer](crate::ShardMapBuilder::key_normalizer): the key and its hash are
+    /// used as given, so they would miss normalized entries.
     pub fn get_by_hash<Q>(&self, key: &Q, key_hash: u64) -> Option<Arc<V>>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
+        self.assert_unnormalized("get_by_hash");
         let shard_idx = self.shard_for_hash(key_hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Get, key_hash);
         self.shards[shard_idx].get(key)
     }
 
-    /// Insert using a precomputed hash for shard selection. Returns the previous value if the key existed.
-    pub fn insert_by_hash(&self, key: K, value: V, key_hash: u64) -> Option<Arc<V>> {
-        let shard_idx = self.shard_for_hash(key_hash);
-        self.shards[shard_idx].insert(key, value)
-    }
-
-    /// Remove by key using a precomputed hash for shard selection.
-    pub fn remove_by_hash<Q>(&self, key: &Q, key_hash: u64) -> Option<Arc<V>>
+    /// Batch existence check with precomputed hashes: `result[i]` is whether `keys[i].0` is
+    /// present, routing each key by its hash `keys[i].1`.
+    ///
+    /// Keys are grouped by shard and each involved shard's read lock is taken once, so a
+    /// batch costs one lock per shard instead of one per key. Like the other `*_by_hash`
+    /// methods, the hash must be [`hash_for_key`](Self::hash_for_key) of the key.
+    pub fn contains_many_by_hash<Q>(&self, keys: &[(&Q, u64)]) -> Vec<bool>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        let shard_idx = self.shard_for_hash(key_hash);
-        self.shards[shard_idx].remove(key)
+        self.assert_unnormalized("contains_many_by_hash");
+        let mut buckets: Vec<Vec<(usize, &Q)>> =
+            (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (idx, &(key, key_hash)) in keys.iter().enumerate() {
+            let shard_idx = self.shard_for_hash(key_hash);
+            #[cfg(feature = "op-trace")]
+            self.shards[shard_idx].trace(OpKind::Get, key_hash);
+            buckets[shard_idx].push((idx, key));
+        }
+
+        let mut found = vec![false; keys.len()];
+        for (shard, items) in self.shards.iter().zip(buckets) {
+            if !items.is_empty() {
+                shard.contains_many(&items, &mut found);
+            }
+        }
+        found
     }
 
-    /// Returns whether the map contains a value for the given key.
-    pub fn contains_key(&self, key: &K) -> bool {
-        let shard_idx = self.shard_index(key);
+    /// Look up several keys at once, returning only the ones found, keyed by their stored key.
+    ///
+    /// Keys are grouped by shard and each involved shard's read lock is taken once. Absent
+    /// keys are simply missing from the result, and a key requested twice appears once.
+    /// Panics on a map with a [key normalizer](crate::ShardMapBuilder::key_normalizer), which
+    /// can't be applied to borrowed keys.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a".to_string(), 1);
+    /// map.insert("b".to_string(), 2);
+    /// let found = map.get_map(["a", "missing"]);
+    /// assert_eq!(found.len(), 1);
+    /// assert_eq!(*found["a"], 1);
+    /// ```
+    pub fn get_map<'a, Q>(
+        &self,
+        keys: impl IntoIterator<Item = &'a Q>,
+    ) -> std::collections::HashMap<K, Arc<V>>
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized + 'a,
+    {
+        self.assert_unnormalized("get_map");
+        let mut buckets: Vec<Vec<&Q>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for key in keys {
+            let hash = self.hash_for_key(key);
+            let shard_idx = self.routed_index(hash);
+            #[cfg(feature = "op-trace")]
+            self.shards[shard_idx].trace(OpKind::Get, hash);
+            buckets[shard_idx].push(key);
+        }
+
+        let mut found = std::collections::HashMap::new();
+        for (shard, keys) in self.shards.iter().zip(buckets) {
+            if !keys.is_empty() {
+                shard.get_entries_into(&keys, &mut found);
+            }
+        }
+        found
+    }
+
+    /// Insert using a precomputed hash for shard selection. Returns the previous value if the key existed.
+    pub fn insert_by_hash(&self, key: K, value: V, key_hash: u64) -> Option<Arc<V>> {
+        self.assert_unnormalized("insert_by_hash");
+        let shard_idx = self.shard_for_hash(key_hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, key_hash);
+        self.shards[shard_idx].insert(key, value)
+    }
+
+    /// Remove by key using a precomputed hash for shard selection.
+    pub fn remove_by_hash<Q>(&self, key: &Q, key_hash: u64) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.assert_unnormalized("remove_by_hash");
+        let shard_idx = self.shard_for_hash(key_hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Remove, key_hash);
+        self.shards[shard_idx].remove(key)
+    }
+
+    /// Returns whether the map contains a value for the given key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let shard_idx = self.shard_index(key);
         self.shards[shard_idx].contains_key(key)
     }
 
+    /// Whether the map still holds exactly `arc` for `key`: the same allocation
+    /// (`Arc::ptr_eq`), not merely an equal value.
+    ///
+    /// Lets callers that cache an `Arc<V>` locally check cheaply whether it is still current;
+    /// any overwrite (`insert`, `update` of a shared value, ...) or removal makes it `false`.
+    /// Checked under the shard's read lock.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("config", 1);
+    /// let cached = map.get(&"config").unwrap();
+    /// assert!(map.holds_same(&"config", &cached));
+    /// map.insert("config", 1);
+    /// assert!(!map.holds_same(&"config", &cached));
+    /// ```
+    pub fn holds_same(&self, key: &K, arc: &Arc<V>) -> bool {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let shard_idx = self.shard_index(key);
+        self.shards[shard_idx].holds_same(key, arc)
+    }
+
+    /// Freeze the map for a write-quiescent read phase: `get` and `contains_key` skip the
+    /// shard read lock entirely until [`unfreeze_reads`](Self::unfreeze_reads).
+    ///
+    /// This is an expert performance knob. While frozen, any write (insert, remove, update,
+    /// clear, ...) panics instead of mutating. Freezing takes each shard's write lock briefly,
+    /// so it waits for in-flight writes to finish. Iterators and `len` still lock as usual.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("k", 1);
+    /// map.freeze_reads();
+    /// assert_eq!(*map.get(&"k").unwrap(), 1);
+    /// // SAFETY: no reads are running concurrently with this call.
+    /// unsafe { map.unfreeze_reads() };
+    /// map.insert("k", 2);
+    /// ```
+    pub fn freeze_reads(&self) {
+        for shard in self.shards.iter() {
+            shard.freeze();
+        }
+    }
+
+    /// Return to locked reads and allow writes again.
+    ///
+    /// # Safety
+    ///
+    /// Frozen reads don't hold any lock, so unfreezing can't wait for them. The caller must
+    /// ensure no `get`/`contains_key` call is running concurrently with this call (e.g. by
+    /// joining the reader threads first); otherwise a subsequent write could race with it.
+    pub unsafe fn unfreeze_reads(&self) {
+        for shard in self.shards.iter() {
+            shard.unfreeze();
+        }
+    }
+
+    /// Whether reads are currently frozen on every shard (see
+    /// [`freeze_reads`](Self::freeze_reads)). Shards are frozen and unfrozen one at a time, so
+    /// this is `false` while a `freeze_reads` call is still in progress.
+    pub fn reads_frozen(&self) -> bool {
+        self.shards.iter().all(|s| s.is_frozen())
+    }
+
+    /// Returns whether any entry has a value equal to `value`.
+    ///
+    /// This is an O(n) scan under each shard's read lock in turn, stopping at the first match.
+    /// Intended for small maps or debugging, not hot paths.
+    pub fn contains_value(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        self.shards
+            .iter()
+            .any(|shard| shard.read_lock().values().any(|v| ***v == *value))
+    }
+
+    /// Returns the first key found whose value equals `value`, or `None`.
+    ///
+    /// O(n) scan that stops at the first match. "First" follows shard and table order, which
+    /// is not insertion order. Use [`find_keys_by_value`](Self::find_keys_by_value) for all
+    /// matches.
+    pub fn find_key_by_value(&self, value: &V) -> Option<K>
+    where
+        K: Clone,
+        V: PartialEq,
+    {
+        self.shards.iter().find_map(|shard| {
+            shard
+                .read_lock()
+                .iter()
+                .find(|(_, v)| ****v == *value)
+                .map(|(k, _)| k.clone())
+        })
+    }
+
+    /// Reduce `f(value)` over every entry with `add`, starting from `init`, e.g. to total a
+    /// counter field across the map.
+    ///
+    /// Each shard is folded under its read lock, one shard at a time, without a snapshot.
+    /// The result is not a point-in-time total if writers run concurrently.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", (2u32, "x"));
+    /// map.insert("b", (5u32, "y"));
+    /// assert_eq!(map.sum_by(|v| v.0 as u64, 0, |a, b| a + b), 7);
+    /// ```
+    pub fn sum_by<F, A, G>(&self, f: F, init: A, add: G) -> A
+    where
+        F: Fn(&V) -> A,
+        G: Fn(A, A) -> A,
+    {
+        let mut total = init;
+        for shard in self.shards.iter() {
+            for value in shard.read_lock().values() {
+                total = add(total, f(value));
+            }
+        }
+        total
+    }
+
+    /// Sum `f(value)` over every entry as `i64`; shorthand for [`sum_by`](Self::sum_by).
+    /// Overflow wraps in release builds and panics in debug builds, like `+`.
+    pub fn sum_i64<F>(&self, f: F) -> i64
+    where
+        F: Fn(&V) -> i64,
+    {
+        self.sum_by(f, 0, |a, b| a + b)
+    }
+
+    /// The entry with the greatest value according to `compare`, or `None` if the map is empty.
+    ///
+    /// O(n) scan, one shard read lock at a time; only the current best entry is retained
+    /// (one key clone per shard at most), so nothing like a snapshot is allocated. Among equal
+    /// values, which entry wins is unspecified.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 3);
+    /// map.insert("b", 7);
+    /// map.insert("c", 5);
+    /// let (key, value) = map.max_by(|a, b| a.cmp(b)).unwrap();
+    /// assert_eq!((key, *value), ("b", 7));
+    /// ```
+    pub fn max_by<F>(&self, compare: F) -> Option<(K, Arc<V>)>
+    where
+        F: Fn(&V, &V) -> std::cmp::Ordering,
+        K: Clone,
+    {
+        self.extreme_by(compare)
+    }
+
+    /// The entry with the smallest value according to `compare`, or `None` if the map is
+    /// empty. Same scan and tie behavior as [`max_by`](Self::max_by).
+    pub fn min_by<F>(&self, compare: F) -> Option<(K, Arc<V>)>
+    where
+        F: Fn(&V, &V) -> std::cmp::Ordering,
+        K: Clone,
+    {
+        self.extreme_by(|a, b| compare(b, a))
+    }
+
+    /// Shared scan for `max_by`/`min_by`: the entry that `compare` ranks greatest.
+    fn extreme_by<F>(&self, compare: F) -> Option<(K, Arc<V>)>
+    where
+        F: Fn(&V, &V) -> std::cmp::Ordering,
+        K: Clone,
+    {
+        let mut best: Option<(K, Arc<V>)> = None;
+        for shard in self.shards.iter() {
+            let map = shard.read_lock();
+            let Some((key, value)) = map.iter().max_by(|a, b| compare(a.1, b.1)) else {
+                continue;
+            };
+            let better = match &best {
+                Some((_, current)) => compare(value, current).is_gt(),
+                None => true,
+            };
+            if better {
+                best = Some((key.clone(), Arc::clone(value)));
+            }
+        }
+        best
+    }
+
+    /// The `n` entries with the largest `score(value)`, highest first, e.g. for "top N"
+    /// dashboards. Ties are broken arbitrarily.
+    ///
+    /// Shards are scanned one at a time under their read locks while a heap keeps only the
+    /// best `n` so far, so memory stays O(n) and keys are cloned only when they make the
+    /// cut; the whole map is never snapshotted or sorted.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let scores = ShardMap::new();
+    /// for (player, score) in [("ann", 30), ("bob", 10), ("cy", 20)] {
+    ///     scores.insert(player, score);
+    /// }
+    /// let top: Vec<_> = scores
+    ///     .top_values_by(2, |s| *s)
+    ///     .into_iter()
+    ///     .map(|(k, _)| k)
+    ///     .collect();
+    /// assert_eq!(top, ["ann", "cy"]);
+    /// ```
+    pub fn top_values_by<F>(&self, n: usize, score: F) -> Vec<(K, Arc<V>)>
+    where
+        F: Fn(&V) -> i64,
+        K: Clone,
+    {
+        use std::cmp::Reverse;
+        use std::collections::BinaryHeap;
+
+        if n == 0 {
+            return Vec::new();
+        }
+        // Min-heap on score holding the best `n` seen; `seq` keeps the ordering total
+        // without requiring `K: Ord`.
+        let mut heap: BinaryHeap<Reverse<(i64, usize)>> = BinaryHeap::with_capacity(n + 1);
+        let mut slots: Vec<Option<(K, Arc<V>)>> = Vec::with_capacity(n + 1);
+        let mut free: Vec<usize> = Vec::new();
+        for shard in self.shards.iter() {
+            let map = shard.read_lock();
+            for (key, value) in map.iter() {
+                let s = score(value);
+                if heap.len() == n {
+                    match heap.peek() {
+                        Some(Reverse((min, _))) if s > *min => {}
+                        _ => continue,
+                    }
+                    let Reverse((_, slot)) = heap.pop().expect("heap holds n > 0 entries");
+                    slots[slot] = None;
+                    free.push(slot);
+                }
+                let entry = Some((key.clone(), Arc::clone(value)));
+                let slot = match free.pop() {
+                    Some(slot) => {
+                        slots[slot] = entry;
+                        slot
+                    }
+                    None => {
+                        slots.push(entry);
+                        slots.len() - 1
+                    }
+                };
+                heap.push(Reverse((s, slot)));
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_, slot))| slots[slot].take().expect("slot is live"))
+            .collect()
+    }
+
+    /// Returns every key whose value equals `value`. O(n) scan of the whole map.
+    pub fn find_keys_by_value(&self, value: &V) -> Vec<K>
+    where
+        K: Clone,
+        V: PartialEq,
+    {
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            let map = shard.read_lock();
+            keys.extend(
+                map.iter()
+                    .filter(|(_, v)| ****v == *value)
+                    .map(|(k, _)| k.clone()),
+            );
+        }
+        keys
+    }
+
+    /// Keys present in `self` but not in `other`, e.g. for "what was removed between these
+    /// two versions". Order is unspecified.
+    ///
+    /// Each shard of `self` is snapshotted under its read lock, then checked against `other`;
+    /// the result is not a single atomic view of both maps under concurrent writes. The maps
+    /// may use different shard counts, hashers or routers.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let old = ShardMap::new();
+    /// old.insert("a", 1);
+    /// old.insert("b", 2);
+    /// let new = ShardMap::new();
+    /// new.insert("b", 2);
+    /// assert_eq!(old.keys_difference(&new), vec!["a"]);
+    /// assert_eq!(old.keys_intersection(&new), vec!["b"]);
+    /// ```
+    pub fn keys_difference(&self, other: &ShardMap<K, V>) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.keys_filtered_by(other, false)
+    }
+
+    /// Keys present in both `self` and `other`. Order is unspecified. Same consistency as
+    /// [`keys_difference`](Self::keys_difference).
+    pub fn keys_intersection(&self, other: &ShardMap<K, V>) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.keys_filtered_by(other, true)
+    }
+
+    /// Keys of `self` whose presence in `other` equals `present`.
+    fn keys_filtered_by(&self, other: &ShardMap<K, V>, present: bool) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            // Snapshot first so we never hold a lock of `self` while locking `other`
+            // (they may be the same map).
+            let snapshot: Vec<K> = shard.read_lock().keys().cloned().collect();
+            keys.extend(
+                snapshot
+                    .into_iter()
+                    .filter(|k| other.shards[other.shard_index(k)].contains_key(k) == present),
+            );
+        }
+        keys
+    }
+
     /// Remove all entries from the map.
     pub fn clear(&self) {
-        for shard in &self.shards {
+        for shard in self.shards.iter() {
             shard.clear();
         }
     }
 
+    /// Remove all entries, choosing whether to keep each shard's allocated capacity.
+    ///
+    /// `clear_with(true)` is the same as [`clear`](Self::clear). With `false`, each shard's
+    /// table is replaced by a fresh empty one under its write lock, releasing memory in one
+    /// step per shard instead of `clear()` followed by `shrink_to_fit()`.
+    pub fn clear_with(&self, retain_capacity: bool) {
+        for shard in self.shards.iter() {
+            shard.clear_with(retain_capacity);
+        }
+    }
+
     /// Retain only entries for which the predicate returns true.
     /// Requires `V: Clone` because values may be cloned when modified in place.
+    /// With `metrics`, each dropped entry counts as a remove, as do the entries dropped by
+    /// the other `retain_*` variants and [`drain_filter`](Self::drain_filter).
     pub fn retain<F>(&self, mut f: F)
     where
         F: FnMut(&K, &mut V) -> bool,
         V: Clone,
     {
-        for shard in &self.shards {
+        for shard in self.shards.iter() {
             shard.retain(&mut f);
         }
     }
 
-    /// Total capacity across all shards (number of elements that can be stored without reallocating).
-    pub fn capacity(&self) -> usize {
-        self.shards.iter().map(|s| s.capacity()).sum()
+    /// Like [`retain`](Self::retain), but visits shards in a random order, freshly shuffled
+    /// on each call, so concurrent GC passes don't all queue on shard 0 and then move in
+    /// lockstep. The result is the same as `retain`: each entry is decided by `f` exactly
+    /// once, and only the order of shards (and their locks) differs.
+    pub fn retain_shuffled<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+        V: Clone,
+    {
+        for idx in self.shuffled_shard_order() {
+            self.shards[idx].retain(&mut f);
+        }
+    }
+
+    /// Shard indices in a random order, seeded per call.
+    fn shuffled_shard_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.shards.len()).collect();
+        // splitmix64, seeded from a fresh random hasher state.
+        let mut state = ahash::RandomState::new().hash_one(order.len());
+        let mut next = || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        for i in (1..order.len()).rev() {
+            order.swap(i, (next() % (i as u64 + 1)) as usize);
+        }
+        order
+    }
+
+    /// Like [`retain`](Self::retain), calling `progress(shards_done, total_shards)` after each
+    /// shard is finished, for long GC passes over large maps.
+    ///
+    /// `progress` runs with no shard lock held, so it can log, sleep or yield, or check a
+    /// shutdown flag between shards. `shards_done` counts up from 1 to `total_shards`.
+    pub fn retain_with_progress<F, P>(&self, mut f: F, mut progress: P)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+        P: FnMut(usize, usize),
+        V: Clone,
+    {
+        let total = self.shards.len();
+        for (idx, shard) in self.shards.iter().enumerate() {
+            shard.retain(&mut f);
+            progress(idx + 1, total);
+        }
+    }
+
+    /// Like [`retain`](Self::retain), but the predicate can stop the whole pass, e.g. on a
+    /// deadline: `Continue(keep)` decides the entry as `retain` would, and `Break(())` stops
+    /// immediately, leaving the entry it was called with and every entry not yet visited
+    /// in the map. Decisions made before the break stay applied.
+    ///
+    /// As in [`for_each_mut`](Self::for_each_mut), a value held only by the map is changed in
+    /// place. A value whose `Arc` is also held elsewhere is cloned for `f` and written back
+    /// only on `Continue(true)`, so the entry `f` breaks on keeps its original `Arc`. Decide
+    /// on `Break` before changing the value: a change to an unshared value is already made.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    /// use std::ops::ControlFlow;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("stale", 0);
+    /// let deadline = Instant::now() + Duration::from_millis(5);
+    /// map.retain_controlled(|_, v| {
+    ///     if Instant::now() >= deadline {
+    ///         return ControlFlow::Break(());
+    ///     }
+    ///     ControlFlow::Continue(*v > 0)
+    /// });
+    /// ```
+    pub fn retain_controlled<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> std::ops::ControlFlow<(), bool>,
+        V: Clone,
+    {
+        for shard in self.shards.iter() {
+            if !shard.retain_controlled(&mut f) {
+                break;
+            }
+        }
+    }
+
+    /// Mutate every value in place, one shard write lock at a time.
+    ///
+    /// Values whose `Arc` is held only by the map are changed directly; values also held
+    /// elsewhere are cloned first (copy-on-write, as in [`update`](Self::update)), so
+    /// outstanding `Arc`s keep the old value. Use [`try_for_each_mut`](Self::try_for_each_mut)
+    /// when `V` isn't `Clone`.
+    pub fn for_each_mut<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V),
+        V: Clone,
+    {
+        for shard in self.shards.iter() {
+            shard.for_each_mut(&mut f);
+        }
+    }
+
+    /// Like [`for_each_mut`](Self::for_each_mut) without requiring `V: Clone`: values whose
+    /// `Arc` is shared outside the map are skipped instead of cloned. Returns the number
+    /// skipped, so `0` means every value was visited.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", vec![1]);
+    /// map.insert("b", vec![2]);
+    /// let held = map.get(&"b").unwrap();
+    /// assert_eq!(map.try_for_each_mut(|_, v| v.push(0)), 1);
+    /// assert_eq!(*map.get(&"a").unwrap(), [1, 0]);
+    /// assert_eq!(*held, [2]);
+    /// ```
+    pub fn try_for_each_mut<F>(&self, mut f: F) -> usize
+    where
+        F: FnMut(&K, &mut V),
+    {
+        self.shards
+            .iter()
+            .map(|shard| shard.try_for_each_mut(&mut f))
+            .sum()
+    }
+
+    /// Hand each shard's entries to `f` mutably, with no locking. Requires exclusive access.
+    ///
+    /// Intended for single-threaded bulk rebuild phases where per-shard locks are pure
+    /// overhead. `f` receives the shard index and that shard's [`ShardTable`]. Keys inserted here must
+    /// route to that shard (check with [`shard_for_key`](Self::shard_for_key) beforehand),
+    /// otherwise they won't be found by the locked API. Operation counters are not updated,
+    /// but writes still reach a write-through store.
+    /// While a detached [`iter_concurrent`](Self::iter_concurrent) iterator is alive, each
+    /// shard is write-locked for `f` instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    /// use std::sync::Arc;
+    ///
+    /// let mut map = ShardMap::new();
+    /// let shard = map.shard_for_key(&"k");
+    /// map.rebuild(|idx, entries| {
+    ///     if idx == shard {
+    ///         entries.insert("k", Arc::new(1));
+    ///     }
+    /// });
+    /// assert_eq!(*map.get(&"k").unwrap(), 1);
+    /// ```
+    pub fn rebuild<F>(&mut self, mut f: F)
+    where
+        F: FnMut(usize, &mut ShardTable<'_, K, V>),
+    {
+        for idx in 0..self.shards.len() {
+            match Arc::get_mut(&mut self.shards) {
+                Some(shards) => shards[idx].with_table_mut(|table| f(idx, table)),
+                // A detached `iter_concurrent` still shares the shards.
+                None => self.shards[idx].with_table_locked(|table| f(idx, table)),
+            }
+        }
+    }
+
+    /// Switch to a different router and move every entry to the shard it now routes to.
+    ///
+    /// Takes `&mut self`, so nothing else can touch the map during the switch. The new router
+    /// is checked against every stored key first: if it returns an index outside
+    /// `0..shard_count()` the call fails with [`Error::InvalidShardIndex`], and if the move
+    /// would push a shard of a bounded map past `max_entries_per_shard` it fails with
+    /// [`Error::ShardFull`]. [`RoutingConfig::Default`] masks the hash with
+    /// `shard_count - 1`, so it fails with [`Error::InvalidShardCount`] on a map whose shard
+    /// count isn't a power of two, e.g. a half from [`split_shards`](Self::split_shards). On
+    /// error the map and its router are unchanged. The shard count stays the same; entries
+    /// that stay put keep their eviction order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{RoutingConfig, ShardMap, ShardRouter};
+    ///
+    /// struct Modulo;
+    /// impl ShardRouter for Modulo {
+    ///     fn route(&self, key_hash: u64, shard_count: usize) -> usize {
+    ///         (key_hash % shard_count as u64) as usize
+    ///     }
+    /// }
+    ///
+    /// let mut map = ShardMap::new();
+    /// map.insert("k", 1);
+    /// map.set_routing(RoutingConfig::Custom(Box::new(Modulo)))?;
+    /// assert_eq!(*map.get(&"k").unwrap(), 1);
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn set_routing(&mut self, routing: RoutingConfig) -> Result<(), Error> {
+        if matches!(routing, RoutingConfig::Default) && !valid_shard_count(self.shards.len()) {
+            return Err(Error::InvalidShardCount);
+        }
+        let router = Router::from(routing);
+        self.check_relocation(&router)?;
+        self.router = router;
+        self.relocate_misplaced();
+        Ok(())
+    }
+
+    /// Check that routing every stored key through `router` stays in range and, on a bounded
+    /// map, leaves no shard past `max_entries_per_shard`.
+    fn check_relocation(&self, router: &Router) -> Result<(), Error> {
+        let shard_count = self.shards.len();
+        let mut loads = vec![0usize; shard_count];
+        for shard in self.shards.iter() {
+            for key in shard.read_lock().keys() {
+                let target = router.route(self.hash.hash_key(key), shard_count);
+                *loads.get_mut(target).ok_or(Error::InvalidShardIndex)? += 1;
+            }
+        }
+        if let Some(bound) = self.shards[0].bound() {
+            if loads.iter().any(|&load| load > bound.max_entries) {
+                return Err(Error::ShardFull);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move every entry that no longer routes to the shard it is stored in, and return how
+    /// many were moved.
+    ///
+    /// Entries end up misplaced when a stateful custom router changes its decisions (see
+    /// [`feed_loads_to_router`](Self::feed_loads_to_router)); such entries are unreachable
+    /// through keyed lookups until moved. Takes `&mut self`, so nothing else can touch the
+    /// map meanwhile. [`set_routing`](Self::set_routing) does this automatically.
+    ///
+    /// Like `set_routing`, every key is checked first: the call fails with
+    /// [`Error::InvalidShardIndex`] if the router returns an index outside
+    /// `0..shard_count()`, and with [`Error::ShardFull`] if the moves would push a shard of a
+    /// bounded map past `max_entries_per_shard`. On error nothing is moved.
+    pub fn compact(&mut self) -> Result<usize, Error> {
+        self.check_relocation(&self.router)?;
+        Ok(self.relocate_misplaced())
+    }
+
+    /// Move misplaced entries to the shard the current router picks. The router must only
+    /// return in-range indices for stored keys.
+    fn relocate_misplaced(&mut self) -> usize {
+        let shard_count = self.shards.len();
+        let mut moving = Vec::new();
+        for (idx, shard) in self.shards.iter().enumerate() {
+            let (hash, router) = (&self.hash, &self.router);
+            moving
+                .push(shard.extract_where(|k| router.route(hash.hash_key(k), shard_count) != idx));
+        }
+        let mut moved = 0;
+        for (key, value) in moving.into_iter().flatten() {
+            let target = self.shard_index(&key);
+            self.shards[target].insert_exclusive(key, value);
+            moved += 1;
+        }
+        moved
+    }
+
+    /// Like [`retain`](Self::retain), but returns the entries that were removed.
+    ///
+    /// Useful for GC passes that need to act on what was pruned (e.g. enqueue evicted keys for
+    /// a downstream delete).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("keep", 1);
+    /// map.insert("drop", 0);
+    /// let removed = map.retain_collecting(|_, v| *v > 0);
+    /// assert_eq!(removed.len(), 1);
+    /// assert_eq!(removed[0].0, "drop");
+    /// ```
+    pub fn retain_collecting<F>(&self, mut f: F) -> Vec<(K, Arc<V>)>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+        V: Clone,
+    {
+        let mut removed = Vec::new();
+        for shard in self.shards.iter() {
+            shard.retain_collecting(&mut f, &mut removed);
+        }
+        removed
+    }
+
+    /// Exchange contents with `other`, e.g. to swap in a freshly built map when
+    /// double-buffering.
+    ///
+    /// Both maps must share a layout: the same shard count, hasher (and seed), router (both
+    /// default, or the same shared custom router, as with [`spawn_sibling`](Self::spawn_sibling))
+    /// and per-shard bound; otherwise this returns [`Error::IncompatibleMaps`] and changes
+    /// nothing. Each pair of corresponding shards is swapped under both write locks, one pair
+    /// after another, so the swap is atomic per shard but not across the whole map: a
+    /// concurrent reader may see some shards already swapped and others not yet.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let live = ShardMap::new();
+    /// live.insert("v", 1);
+    /// let next = live.spawn_sibling();
+    /// next.insert("v", 2);
+    /// live.swap_contents(&next)?;
+    /// assert_eq!(*live.get(&"v").unwrap(), 2);
+    /// assert_eq!(*next.get(&"v").unwrap(), 1);
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn swap_contents(&self, other: &ShardMap<K, V>) -> Result<(), Error> {
+        let compatible = self.shards.len() == other.shards.len()
+            && self.hash.same_as(&other.hash)
+            && self.router.same_as(&other.router)
+            && self.shards[0].bound() == other.shards[0].bound();
+        if !compatible {
+            return Err(Error::IncompatibleMaps);
+        }
+        for (mine, theirs) in self.shards.iter().zip(other.shards.iter()) {
+            mine.swap_contents(theirs);
+        }
+        Ok(())
+    }
+
+    /// Create a new, empty map with the same configuration as this one.
+    ///
+    /// The sibling gets the same shard count, hasher (including a fixed seed), router,
+    /// per-shard bound and key normalizer, so every key lands on the same shard index in both
+    /// maps. Useful for
+    /// double-buffering: fill the sibling, then swap it in.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<&str, i32> = ShardMap::new();
+    /// let next = map.spawn_sibling();
+    /// assert_eq!(next.shard_count(), map.shard_count());
+    /// assert_eq!(next.shard_for_key(&"a"), map.shard_for_key(&"a"));
+    /// ```
+    pub fn spawn_sibling(&self) -> ShardMap<K, V> {
+        self.empty_sibling()
+    }
+
+    /// Split the current contents into two new maps: entries for which `pred` returns true,
+    /// and the rest. Both maps share this map's shard count, hasher and router.
+    ///
+    /// Values are not cloned: the new maps hold the same `Arc`s as this one. This map is left
+    /// unchanged. Each shard is read-locked in turn, so the split is per-shard consistent.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// let (even, odd) = map.partition(|_, v| v % 2 == 0);
+    /// assert!(even.contains_key(&"b"));
+    /// assert!(odd.contains_key(&"a"));
+    /// ```
+    pub fn partition<F>(&self, pred: F) -> (ShardMap<K, V>, ShardMap<K, V>)
+    where
+        F: Fn(&K, &V) -> bool,
+        K: Clone,
+    {
+        let matching = self.empty_sibling();
+        let rest = self.empty_sibling();
+        for (idx, shard) in self.shards.iter().enumerate() {
+            let map = shard.read_lock();
+            for (k, v) in map.iter() {
+                let target = if pred(k, v) { &matching } else { &rest };
+                target.shards[idx].insert_arc(k.clone(), Arc::clone(v));
+            }
+        }
+        (matching, rest)
+    }
+
+    /// Total capacity across all shards (number of elements that can be stored without reallocating).
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|s| s.capacity()).sum()
+    }
+
+    /// Shrink each shard to fit its current length. Reduces memory use after removals.
+    pub fn shrink_to_fit(&self) {
+        for shard in self.shards.iter() {
+            shard.shrink_to_fit();
+        }
+    }
+
+    /// Rebuild one shard's table from its current entries, dropping the tombstones a long run
+    /// of inserts and removes leaves behind, and trimming capacity to the shard's length.
+    ///
+    /// Holds only that shard's write lock, so it is a cheap, targeted alternative to
+    /// [`rebuild`](Self::rebuild). Entries keep their `Arc`s, so watchers see no change.
+    /// Fails with [`Error::InvalidShardIndex`] if `shard_idx` is not in `0..shard_count()`.
+    pub fn rebuild_shard(&self, shard_idx: usize) -> Result<(), Error> {
+        self.shards
+            .get(shard_idx)
+            .ok_or(Error::InvalidShardIndex)?
+            .rebuild_table();
+        Ok(())
+    }
+
+    /// Make entries with equal values share one `Arc` allocation, e.g. after a bulk load that
+    /// created many equal but separate values. Returns how many allocations were freed.
+    ///
+    /// This is an expensive maintenance operation: it visits every entry, takes each shard's
+    /// write lock in turn (blocking that shard meanwhile) and keeps an interning table with
+    /// one `Arc` per distinct value until it finishes. An allocation still referenced outside
+    /// the map (e.g. an `Arc` returned by `get`) stays alive and is not counted as freed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    /// use std::sync::Arc;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", "eu-west".to_string());
+    /// map.insert("b", "eu-west".to_string());
+    /// assert_eq!(map.coalesce_values(), 1);
+    /// assert!(Arc::ptr_eq(&map.get(&"a").unwrap(), &map.get(&"b").unwrap()));
+    /// ```
+    pub fn coalesce_values(&self) -> usize
+    where
+        V: Hash + Eq,
+    {
+        let mut interned = hashbrown::HashSet::new();
+        self.shards
+            .iter()
+            .map(|shard| shard.coalesce_values(&mut interned))
+            .sum()
+    }
+
+    /// Grow every shard so the map can hold `target_total` entries without resizing, e.g.
+    /// ahead of an expected load spike.
+    ///
+    /// Each shard is reserved up to `ceil(target_total / shard_count)` entries, under its
+    /// write lock, one shard at a time. Unlike a `reserve(additional)`, the target is
+    /// absolute: shards already that large are left alone, and nothing is ever shrunk. A
+    /// skewed key set can still outgrow one shard early.
+    pub fn reserve_to_total(&self, target_total: usize) {
+        let per_shard = target_total.div_ceil(self.shards.len());
+        for shard in self.shards.iter() {
+            shard.reserve_to(per_shard);
+        }
+    }
+
+    /// Reserve room for `additional` more entries, returning an error instead of aborting if
+    /// the allocation fails, so a service near its memory limit can shed load instead.
+    ///
+    /// Each shard reserves `ceil(additional / shard_count)` entries under its write lock, one
+    /// shard at a time, and the first failure is returned. Shards reserved before it keep
+    /// their new capacity.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<u64, u64> = ShardMap::new();
+    /// map.try_reserve(10_000).unwrap();
+    /// assert!(map.capacity() >= 10_000);
+    /// assert!(map.try_reserve(usize::MAX).is_err());
+    /// ```
+    pub fn try_reserve(&self, additional: usize) -> Result<(), TryReserveError> {
+        let per_shard = additional.div_ceil(self.shards.len());
+        for shard in self.shards.iter() {
+            shard.try_reserve(per_shard)?;
+        }
+        Ok(())
+    }
+
+    /// Shrink shards that have gone cold: those whose write count advanced by at most
+    /// `write_count_threshold` since the previous call. Returns the indices of the shrunk
+    /// shards. Requires the `metrics` feature.
+    ///
+    /// The map keeps the per-shard write counts from the previous call as its baseline, so
+    /// the first call only records one and shrinks nothing; call it periodically (e.g. from a
+    /// maintenance timer) and idleness is judged per interval. Removals don't count as
+    /// writes, so a shard that is only being drained is considered idle.
+    #[cfg(feature = "metrics")]
+    pub fn shrink_idle_shards(&self, write_count_threshold: u64) -> Vec<usize> {
+        let mut baseline = self.write_baseline.lock();
+        let current: Vec<u64> = self.shards.iter().map(|s| s.stats().writes).collect();
+        let mut shrunk = Vec::new();
+        if let Some(previous) = baseline.as_ref() {
+            for (idx, shard) in self.shards.iter().enumerate() {
+                if current[idx].saturating_sub(previous[idx]) <= write_count_threshold {
+                    shard.shrink_to_fit();
+                    shrunk.push(idx);
+                }
+            }
+        }
+        *baseline = Some(current);
+        shrunk
+    }
+
+    /// Get the value for the key, or insert the value and return a new `Arc<V>`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// let v = map.get_or_insert("counter", 0);
+    /// assert_eq!(*v, 0);
+    /// map.get_or_insert("counter", 99); // no-op, already present
+    /// assert_eq!(*map.get(&"counter").unwrap(), 0);
+    /// ```
+    pub fn get_or_insert(&self, key: K, value: V) -> Arc<V> {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shards[shard_idx].get_or_insert(key, value)
+    }
+
+    /// Get the value for the key, or compute it with `f` and insert it.
+    ///
+    /// `f` runs under the shard's write lock, before any eviction. If it panics, the panic
+    /// propagates, the lock is released and the shard is left unchanged: no entry for the
+    /// key, nothing evicted.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// let v = map.get_or_insert_with("expensive", || "computed".to_string());
+    /// assert_eq!(v.as_str(), "computed");
+    /// ```
+    pub fn get_or_insert_with<F>(&self, key: K, f: F) -> Arc<V>
+    where
+        F: FnOnce() -> V,
+    {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shards[shard_idx].get_or_insert_with(key, f)
+    }
+
+    /// Get the value for the key, or fill it with the fallible `f`, running `f` at most once
+    /// across concurrent callers for the same missing key. The robust cache-fill primitive.
+    ///
+    /// The first caller to miss runs `f` with no lock held; others that miss meanwhile block
+    /// until it finishes and then share its outcome: the stored value on success, or a clone
+    /// of the same error on failure. An error isn't cached, so the next call after a failed
+    /// fill runs `f` again. If the leading `f` panics, waiting callers start over and one of
+    /// them runs its own `f`.
+    ///
+    /// Callers coordinate per result type: a concurrent call for the same key with a
+    /// different `E` doesn't wait, and fills independently. If the key is inserted by other
+    /// means while `f` runs, that value is kept and returned.
+    ///
+    /// Calling this method from inside `f` for the same key with the same error type
+    /// deadlocks: the inner call joins the outer fill and waits for it to finish.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<&str, String> = ShardMap::new();
+    /// let err = map.get_or_try_insert_single_flight("user:1", || Err("backend down"));
+    /// assert_eq!(err, Err("backend down"));
+    /// let value = map.get_or_try_insert_single_flight("user:1", || Ok::<_, &str>("ann".into()));
+    /// assert_eq!(value.unwrap().as_str(), "ann");
+    /// ```
+    pub fn get_or_try_insert_single_flight<F, E>(&self, key: K, f: F) -> Result<Arc<V>, E>
+    where
+        K: Clone,
+        V: 'static,
+        F: FnOnce() -> Result<V, E>,
+        E: Clone + Send + Sync + 'static,
+    {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        let shard = &self.shards[shard_idx];
+        loop {
+            match shard
+                .flights()
+                .enter::<Result<Arc<V>, E>, _>(&key, || shard.get(&key))
+            {
+                Entered::Ready(value) => return Ok(value),
+                Entered::Follow(flight) => match flight.wait() {
+                    Some(result) => return result,
+                    // The leader panicked; race to lead again.
+                    None => continue,
+                },
+                Entered::Lead(lead) => {
+                    let result = f().map(|value| shard.get_or_insert_with(key, || value));
+                    lead.complete(result.clone());
+                    return result;
+                }
+                Entered::Alone => {
+                    return f().map(|value| shard.get_or_insert_with(key, || value));
+                }
+            }
+        }
+    }
+
+    /// Like [`get_or_insert_with`](Self::get_or_insert_with), also returning `true` exactly
+    /// when `f` ran and its value was inserted, e.g. to count cache fills.
+    ///
+    /// The check and the insert happen under one shard write lock, so among concurrent
+    /// callers for the same missing key exactly one sees `true`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// let (_, filled) = map.get_or_insert_with_status("page", || "rendered");
+    /// assert!(filled);
+    /// let (_, filled) = map.get_or_insert_with_status("page", || "rendered again");
+    /// assert!(!filled);
+    /// ```
+    pub fn get_or_insert_with_status<F>(&self, key: K, f: F) -> (Arc<V>, bool)
+    where
+        F: FnOnce() -> V,
+    {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shards[shard_idx].get_or_insert_with_status(key, f)
+    }
+
+    /// Get the value for the key or insert `value`, then apply `f` to the stored value, all
+    /// under one shard write lock, e.g. to create-then-initialize without a second `update`.
+    /// Returns the value after `f`.
+    ///
+    /// A new value is changed before it is inserted. A present one is changed in place like
+    /// [`update`](Self::update), cloning it first if its `Arc` is held elsewhere.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// let hits = map.get_or_insert_then("page", 0, |n| *n += 1);
+    /// assert_eq!(*hits, 1);
+    /// let hits = map.get_or_insert_then("page", 0, |n| *n += 1);
+    /// assert_eq!(*hits, 2);
+    /// ```
+    pub fn get_or_insert_then<F>(&self, key: K, value: V, f: F) -> Arc<V>
+    where
+        F: FnOnce(&mut V),
+        V: Clone,
+    {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shards[shard_idx].get_or_insert_then(key, value, f)
+    }
+
+    /// Ensure every key is present, inserting `make(&key)` for missing ones. Returns the
+    /// value for each key in input order.
+    ///
+    /// Keys are grouped by shard and each shard is locked once, so this is cheaper than
+    /// calling `get_or_insert_with` per key when warming a cache. `make` is only called for
+    /// keys that are absent; if a key appears twice, the second occurrence sees the first.
+    ///
+    /// `make` runs under the shard's write lock, so absent keys are known exactly. The batch
+    /// is not atomic: if `make` panics, the values already inserted (on earlier shards and
+    /// earlier in the same shard) stay in the map, and the rest of the batch is skipped.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 1);
+    /// let values = map.get_or_insert_many(vec!["a", "b"], |_| 0);
+    /// assert_eq!(*values[0], 1);
+    /// assert_eq!(*values[1], 0);
+    /// ```
+    pub fn get_or_insert_many<F>(&self, keys: Vec<K>, mut make: F) -> Vec<Arc<V>>
+    where
+        F: FnMut(&K) -> V,
+    {
+        let total = keys.len();
+        let mut buckets: Vec<Vec<(usize, K)>> =
+            (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (idx, key) in keys.into_iter().enumerate() {
+            let key = self.normalize(key);
+            let shard_idx = self.shard_index(&key);
+            buckets[shard_idx].push((idx, key));
+        }
+
+        let mut results: Vec<Option<Arc<V>>> = vec![None; total];
+        for (shard, items) in self.shards.iter().zip(buckets) {
+            if items.is_empty() {
+                continue;
+            }
+            for (idx, arc) in shard.get_or_insert_many(items, &mut make) {
+                results[idx] = Some(arc);
+            }
+        }
+        results
+            .into_iter()
+            .map(|r| r.expect("every key resolved"))
+            .collect()
+    }
+
+    /// Get the entry for `key`, holding its shard's write lock until the entry is dropped or
+    /// consumed, to inspect and then replace, remove or insert without routing twice.
+    ///
+    /// Other operations on the same shard block meanwhile, and calling back into the map for
+    /// a key on that shard deadlocks.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{Entry, ShardMap};
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("lease", 0);
+    /// if let Entry::Occupied(entry) = map.entry("lease") {
+    ///     if **entry.get() == 0 {
+    ///         entry.remove();
+    ///     }
+    /// }
+    /// match map.entry("lease") {
+    ///     Entry::Occupied(mut entry) => {
+    ///         entry.insert(1);
+    ///     }
+    ///     Entry::Vacant(entry) => {
+    ///         entry.insert(2);
+    ///     }
+    /// }
+    /// assert_eq!(*map.get(&"lease").unwrap(), 2);
+    /// ```
+    pub fn entry(&self, key: K) -> Entry<'_, K, V> {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        Entry::new(&self.shards[shard_idx], key)
+    }
+
+    /// Insert the key-value pair only if the key is not present.
+    /// Returns `Ok(arc)` with the inserted value, or `Err(arc)` with the existing value.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// assert!(map.try_insert("key", "first").is_ok());
+    /// assert!(map.try_insert("key", "second").is_err());
+    /// assert_eq!(*map.get(&"key").unwrap(), "first");
+    /// ```
+    pub fn try_insert(&self, key: K, value: V) -> Result<Arc<V>, Arc<V>> {
+        let key = self.normalize(key);
+        let hash = self.key_hash(&key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Insert, hash);
+        self.shards[shard_idx].try_insert(key, value)
+    }
+
+    /// Insert every item only if none of the keys exist yet: all of them are inserted, or
+    /// none are (e.g. claiming a group of resources together).
+    ///
+    /// The write locks of every involved shard are taken in shard-index order (like
+    /// [`with_many_mut`](Self::with_many_mut)) and held while the keys are checked and
+    /// inserted, so no other writer can interleave. Returns [`Error::KeyAlreadyExists`] if
+    /// any key is present or listed twice, and [`Error::ShardFull`] if the items don't fit
+    /// a bounded map with `OnFull::Reject`; in both cases nothing is inserted. With an
+    /// evicting bound, older entries may be evicted to make room.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{Error, ShardMap};
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("gpu:1", "taken");
+    /// let claim = vec![("gpu:0", "job"), ("gpu:1", "job")];
+    /// assert_eq!(map.try_insert_all(claim), Err(Error::KeyAlreadyExists));
+    /// assert!(!map.contains_key(&"gpu:0"));
+    /// ```
+    pub fn try_insert_all(&self, items: Vec<(K, V)>) -> Result<(), Error> {
+        let items: Vec<(K, V)> = match &self.normalizer {
+            Some(normalize) => items.into_iter().map(|(k, v)| (normalize(&k), v)).collect(),
+            None => items,
+        };
+        let mut seen = hashbrown::HashSet::with_capacity(items.len());
+        if !items.iter().all(|(key, _)| seen.insert(key)) {
+            return Err(Error::KeyAlreadyExists);
+        }
+        drop(seen);
+
+        let mut buckets: Vec<Vec<(K, V)>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (key, value) in items {
+            let shard_idx = self.shard_index(&key);
+            buckets[shard_idx].push((key, value));
+        }
+        // Lock in ascending shard order to avoid lock-order inversions.
+        let mut locked: Vec<_> = buckets
+            .into_iter()
+            .enumerate()
+            .filter(|(_, items)| !items.is_empty())
+            .map(|(idx, items)| (idx, self.shards[idx].write_lock(), items))
+            .collect();
+
+        for (idx, map, items) in &locked {
+            if items.iter().any(|(key, _)| map.contains_key(key)) {
+                return Err(Error::KeyAlreadyExists);
+            }
+            if !self.shards[*idx].has_room_for(map, items.len()) {
+                return Err(Error::ShardFull);
+            }
+        }
+        for (idx, map, items) in &mut locked {
+            for (key, value) in items.drain(..) {
+                self.shards[*idx].insert_locked(map, key, Arc::new(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Update a value using a closure, returning the new value if the key existed.
+    ///
+    /// Note: This requires `V: Clone` because if the value is shared (multiple
+    /// `Arc` references exist), it will clone the value before modifying it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("counter", 0);
+    ///
+    /// map.update(&"counter", |v| *v += 1);
+    /// assert_eq!(*map.get(&"counter").unwrap(), 1);
+    /// ```
+    pub fn update<F>(&self, key: &K, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&mut V),
+        V: Clone,
+    {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let hash = self.key_hash(key);
+        let shard_idx = self.routed_index(hash);
+        #[cfg(feature = "op-trace")]
+        self.shards[shard_idx].trace(OpKind::Update, hash);
+        self.shards[shard_idx].update(key, f)
+    }
+
+    /// Bulk-replace values, returning how many keys already existed (were overwritten).
+    ///
+    /// Meant for refreshes where the keys are expected to be present: items are grouped by
+    /// shard, each shard's write lock is taken once, and previous values are dropped rather
+    /// than returned. Keys that don't exist are still inserted; they just don't count. A key
+    /// listed twice ends with its last value.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 1);
+    /// let overwritten = map.overwrite_many([("a", 10), ("b", 20)]);
+    /// assert_eq!(overwritten, 1);
+    /// assert_eq!(*map.get(&"b").unwrap(), 20);
+    /// ```
+    pub fn overwrite_many<I>(&self, items: I) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut buckets: Vec<Vec<(K, V)>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (key, value) in items {
+            let key = self.normalize(key);
+            let shard_idx = self.shard_index(&key);
+            buckets[shard_idx].push((key, value));
+        }
+        self.shards
+            .iter()
+            .zip(buckets)
+            .filter(|(_, items)| !items.is_empty())
+            .map(|(shard, items)| shard.overwrite_many(items))
+            .sum()
+    }
+
+    /// Merge a stream of updates, combining with present values instead of overwriting them.
+    ///
+    /// New keys are inserted as given; for a key already present the stored value becomes
+    /// `resolve(key, existing, incoming)`. Items are grouped by shard and each shard's write
+    /// lock is taken once, so `resolve` must not call back into the map. A key listed twice
+    /// is resolved against its own earlier value. Returns how many items were resolved.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 5);
+    /// let resolved = map.merge_from([("a", 3), ("b", 7)], |_, old, new| new.max(*old));
+    /// assert_eq!(resolved, 1);
+    /// assert_eq!(*map.get(&"a").unwrap(), 5);
+    /// assert_eq!(*map.get(&"b").unwrap(), 7);
+    /// ```
+    pub fn merge_from<I, F>(&self, items: I, resolve: F) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: Fn(&K, &V, V) -> V,
+    {
+        let mut buckets: Vec<Vec<(K, V)>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (key, value) in items {
+            let key = self.normalize(key);
+            let shard_idx = self.shard_index(&key);
+            buckets[shard_idx].push((key, value));
+        }
+        self.shards
+            .iter()
+            .zip(buckets)
+            .filter(|(_, items)| !items.is_empty())
+            .map(|(shard, items)| shard.merge_many(items, &resolve))
+            .sum()
+    }
+
+    /// Insert items already grouped by shard, e.g. by an ETL producer that applies the
+    /// published routing ([`compute_shard`](Self::compute_shard) or
+    /// [`routing_descriptor`](Self::routing_descriptor)) itself.
+    ///
+    /// Each `(shard_idx, items)` group is inserted under that shard's write lock without
+    /// routing its keys, skipping the grouping pass of [`overwrite_many`](Self::overwrite_many)
+    /// (keys are still hashed by the shard's table). The grouping is trusted: a key placed in
+    /// a shard it doesn't route to won't be found by `get`. Returns
+    /// [`Error::InvalidShardIndex`] and inserts nothing if any index is out of range. Existing
+    /// keys are overwritten.
+    pub fn insert_presharded(&self, groups: Vec<(usize, Vec<(K, V)>)>) -> Result<(), Error> {
+        if groups.iter().any(|(idx, _)| *idx >= self.shards.len()) {
+            return Err(Error::InvalidShardIndex);
+        }
+        for (idx, items) in groups {
+            if !items.is_empty() {
+                let items = items
+                    .into_iter()
+                    .map(|(key, value)| (self.normalize(key), value))
+                    .collect();
+                self.shards[idx].overwrite_many(items);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a change set, e.g. one computed on another replica.
+    ///
+    /// Upserts and removals are grouped by shard, and each shard's changes are applied under
+    /// a single write lock: first its removals, then its upserts. A key that appears in both
+    /// therefore ends up present with its upserted value. Shards are updated one after
+    /// another, so the diff as a whole is not atomic.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{Diff, ShardMap};
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// map.apply_diff(Diff {
+    ///     upserts: vec![("b", 20), ("c", 3)],
+    ///     removals: vec!["a"],
+    /// });
+    /// assert!(!map.contains_key(&"a"));
+    /// assert_eq!(*map.get(&"b").unwrap(), 20);
+    /// assert_eq!(*map.get(&"c").unwrap(), 3);
+    /// ```
+    pub fn apply_diff(&self, diff: Diff<K, V>) {
+        let mut removals: Vec<Vec<K>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        let mut upserts: Vec<Vec<(K, V)>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for key in diff.removals {
+            let key = self.normalize(key);
+            let shard_idx = self.shard_index(&key);
+            removals[shard_idx].push(key);
+        }
+        for (key, value) in diff.upserts {
+            let key = self.normalize(key);
+            let shard_idx = self.shard_index(&key);
+            upserts[shard_idx].push((key, value));
+        }
+        for ((shard, removals), upserts) in self.shards.iter().zip(removals).zip(upserts) {
+            if removals.is_empty() && upserts.is_empty() {
+                continue;
+            }
+            shard.apply_changes(removals, upserts);
+        }
+    }
+
+    /// Apply `f` to each present key, locking every involved shard once. Absent keys are
+    /// skipped. Returns how many keys were updated.
+    ///
+    /// Keys are grouped by shard, so this is cheaper than calling [`update`](Self::update) per
+    /// key. Unlike [`with_many_mut`](Self::with_many_mut), shards are locked one at a time, so
+    /// the batch as a whole is not atomic. A key listed twice is updated twice. Shared values
+    /// are cloned before mutation, as with `update`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// let updated = map.update_many(&[&"a", &"b", &"missing"], |_, v| *v *= 10);
+    /// assert_eq!(updated, 2);
+    /// assert_eq!(*map.get(&"b").unwrap(), 20);
+    /// ```
+    pub fn update_many<F>(&self, keys: &[&K], mut f: F) -> usize
+    where
+        F: FnMut(&K, &mut V),
+        V: Clone,
+    {
+        let mut normalized = Vec::new();
+        let keys = self.normalize_all(keys, &mut normalized);
+        let mut buckets: Vec<Vec<&K>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for &key in &keys {
+            let hash = self.key_hash(key);
+            let shard_idx = self.routed_index(hash);
+            #[cfg(feature = "op-trace")]
+            self.shards[shard_idx].trace(OpKind::Update, hash);
+            buckets[shard_idx].push(key);
+        }
+        self.shards
+            .iter()
+            .zip(buckets)
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(shard, keys)| shard.update_many(&keys, &mut f))
+            .sum()
+    }
+
+    /// Mutate several distinct keys at once under their shard write locks.
+    ///
+    /// `f` receives one slot per key, in the order of `keys`: `Some(&mut V)` if the key is
+    /// present, `None` otherwise. Every needed shard's write lock is held for the duration of
+    /// `f`, acquired in shard-index order (once per shard) so concurrent calls can't deadlock.
+    /// Shared values are cloned before mutation, as with [`update`](Self::update).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` contains the same key twice, since that would alias a `&mut V`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("alice", 100);
+    /// map.insert("bob", 0);
+    /// map.with_many_mut(&[&"alice", &"bob"], |slots| {
+    ///     if let [Some(from), Some(to)] = slots {
+    ///         **from -= 30;
+    ///         **to += 30;
+    ///     }
+    /// });
+    /// assert_eq!(*map.get(&"bob").unwrap(), 30);
+    /// ```
+    pub fn with_many_mut<R, F>(&self, keys: &[&K], f: F) -> R
+    where
+        F: FnOnce(&mut [Option<&mut V>]) -> R,
+        V: Clone,
+    {
+        let mut normalized = Vec::new();
+        let keys = self.normalize_all(keys, &mut normalized);
+        let mut seen = hashbrown::HashSet::with_capacity(keys.len());
+        for key in &keys {
+            assert!(seen.insert(*key), "with_many_mut: duplicate key");
+        }
+
+        let shard_idxs: Vec<usize> = keys.iter().map(|k| self.shard_index(k)).collect();
+        let mut needed = shard_idxs.clone();
+        needed.sort_unstable();
+        needed.dedup();
+        // Lock in ascending shard order to avoid lock-order inversions.
+        let mut guards: Vec<_> = needed
+            .iter()
+            .map(|&idx| self.shards[idx].write_lock())
+            .collect();
+
+        let mut ptrs: Vec<Option<*mut V>> = Vec::with_capacity(keys.len());
+        for (key, shard_idx) in keys.iter().zip(&shard_idxs) {
+            let pos = needed.binary_search(shard_idx).unwrap();
+            let ptr = guards[pos].get_mut(*key).map(|arc| {
+                self.shards[*shard_idx].record_write();
+                Arc::make_mut(arc) as *mut V
+            });
+            ptrs.push(ptr);
+        }
+
+        // SAFETY: keys are pairwise distinct, so each pointer refers to a different value.
+        // Each value lives in its own `Arc` allocation, which no later `get_mut` on the same
+        // shard moves, and the write guards stay alive until after `f` returns.
+        let mut slots: Vec<Option<&mut V>> = ptrs
+            .into_iter()
+            .map(|p| p.map(|p| unsafe { &mut *p }))
+            .collect();
+        let result = f(&mut slots);
+        drop(slots);
+        for (key, shard_idx) in keys.iter().zip(&shard_idxs) {
+            let pos = needed.binary_search(shard_idx).unwrap();
+            if let Some(value) = guards[pos].get(*key) {
+                self.shards[*shard_idx].mirror().inserted(key, value);
+            }
+        }
+        drop(guards);
+        result
+    }
+
+    /// Rename a key to a new key, moving the value without copying.
+    ///
+    /// **Same shard:** The operation is atomic under that shard's lock: either
+    /// both the old key is removed and the new key is inserted, or neither happens.
+    ///
+    /// **Cross-shard:** Old and new keys map to different shards. This implementation
+    /// acquires both shard locks (old then new). The move is atomic from the caller's
+    /// view (all-or-nothing), but it is not a single lock — so don't assume the same
+    /// atomicity guarantees as within a single shard.
+    ///
+    /// Returns an error if the old key is missing or the new key already exists, or
+    /// `Error::ShardFull` if a cross-shard rename targets a full shard of a bounded map.
+    /// For cross-shard renames, `K: Clone` is required for conflict recovery.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("old_key", "value");
+    ///
+    /// map.rename(&"old_key", "new_key").unwrap();
+    /// assert!(map.get(&"old_key").is_none());
+    /// assert_eq!(*map.get(&"new_key").unwrap(), "value");
+    /// ```
+    pub fn rename(&self, old_key: &K, new_key: K) -> Result<(), Error>
+    where
+        K: Clone,
+    {
+        let mut normalized = None;
+        let old_key = self.normalize_ref(old_key, &mut normalized);
+        let new_key = self.normalize(new_key);
+        let old_shard_idx = self.shard_index(old_key);
+        let new_shard_idx = self.shard_index(&new_key);
+
+        // If both keys map to the same shard, use atomic rename
+        if old_shard_idx == new_shard_idx {
+            return self.shards[old_shard_idx].rename(old_key, new_key);
+        }
+
+        // Different shards: use cross-shard rename helper
+        // This requires K: Clone for conflict recovery
+        self.rename_cross_shard(old_key, new_key, old_shard_idx, new_shard_idx)
+    }
+
+    /// Move `key` from the shard it routes to into shard `to_shard`, for manual rebalancing
+    /// on a live map (e.g. together with a placement-hint router).
+    ///
+    /// Both shards are write-locked, in index order, for the move, so readers see the key in
+    /// exactly one of them. The key then lives where the router would not look: `get`,
+    /// `remove` and a later `migrate_key` only find it if routing is adjusted to send the key
+    /// to `to_shard` too (and `compact` moves it back otherwise). Migrating to the key's
+    /// current shard does nothing.
+    ///
+    /// Errors: [`Error::InvalidShardIndex`] if `to_shard` is out of range,
+    /// [`Error::KeyNotFound`] if the key is absent, [`Error::KeyAlreadyExists`] if `to_shard`
+    /// already holds it, and [`Error::ShardFull`] if `to_shard` is a full shard of a bounded
+    /// map with `OnFull::Reject`. The map is unchanged on error.
+    pub fn migrate_key(&self, key: &K, to_shard: usize) -> Result<(), Error>
+    where
+        K: Clone,
+    {
+        if to_shard >= self.shards.len() {
+            return Err(Error::InvalidShardIndex);
+        }
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let from_shard = self.shard_index(key);
+        if from_shard == to_shard {
+            return if self.shards[from_shard].contains_key(key) {
+                Ok(())
+            } else {
+                Err(Error::KeyNotFound)
+            };
+        }
+
+        // Lock in ascending shard order to avoid lock-order inversions.
+        let (mut from, mut to) = if from_shard < to_shard {
+            let from = self.shards[from_shard].write_lock();
+            (from, self.shards[to_shard].write_lock())
+        } else {
+            let to = self.shards[to_shard].write_lock();
+            (self.shards[from_shard].write_lock(), to)
+        };
+        if !from.contains_key(key) {
+            return Err(Error::KeyNotFound);
+        }
+        if to.contains_key(key) {
+            return Err(Error::KeyAlreadyExists);
+        }
+        if !self.shards[to_shard].has_room_for(&to, 1) {
+            return Err(Error::ShardFull);
+        }
+        let value = self.shards[from_shard]
+            .extract_locked(&mut from, key)
+            .expect("checked above");
+        self.shards[to_shard].insert_relocated_locked(&mut to, key.clone(), value);
+        Ok(())
+    }
+
+    /// Helper for cross-shard rename operations.
+    /// This handles the case where we need to lock both shards and ensure atomicity.
+    fn rename_cross_shard(
+        &self,
+        old_key: &K,
+        new_key: K,
+        old_shard_idx: usize,
+        new_shard_idx: usize,
+    ) -> Result<(), Error>
+    where
+        K: Clone,
+    {
+        // For cross-shard renames, we lock both shards in order to prevent deadlock
+        // We check the new shard first, then remove from old shard, then insert
+        let old_shard = &self.shards[old_shard_idx];
+        let new_shard = &self.shards[new_shard_idx];
+
+        // Check if new key already exists (this acquires a read lock)
+        if new_shard.contains_key(&new_key) {
+            return Err(Error::KeyAlreadyExists);
+        }
+
+        // Remove value from old shard
+        let value = old_shard.remove(old_key).ok_or(Error::KeyNotFound)?;
+
+        // Double-check new shard (it might have been inserted between our check and now)
+        // This is a race condition we need to handle
+        if new_shard.contains_key(&new_key) {
+            // Conflict: restore the value to old shard
+            old_shard.insert_arc(old_key.clone(), value);
+            return Err(Error::KeyAlreadyExists);
+        }
+
+        // Insert into new shard; if it is bounded and full, put the value back
+        if let Err(e) = new_shard.checked_insert_arc(new_key, value.clone()) {
+            old_shard.insert_arc(old_key.clone(), value);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// A read-only handle for code that must not mutate the map, enforced at compile time.
+    ///
+    /// [`ReadOnly`] offers lookups, snapshots and diagnostics but no inserting, removing,
+    /// updating or clearing methods. It is a zero-cost `Copy` wrapper around `&self`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::{ReadOnly, ShardMap};
+    ///
+    /// fn report(view: ReadOnly<'_, &str, u32>) -> usize {
+    ///     view.len()
+    /// }
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("a", 1);
+    /// assert_eq!(report(map.as_read_only()), 1);
+    /// ```
+    pub fn as_read_only(&self) -> ReadOnly<'_, K, V> {
+        ReadOnly::new(self)
+    }
+
+    /// Get the total number of entries across all shards.
+    ///
+    /// Note: This operation requires acquiring read locks on all shards, so it
+    /// may be slow for large numbers of shards. For better performance, use
+    /// `stats()` which provides more detailed information.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    /// Approximate number of entries, read without taking any lock.
+    ///
+    /// Each shard publishes its length when a write lock is released, so the result can lag
+    /// writes in progress and, under concurrent writes, never matched the map at any single
+    /// instant. Use it to size buffers or for monitoring; use [`len`](Self::len) when the
+    /// count must be exact.
+    pub fn len_hint(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len_hint()).sum()
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.is_empty())
+    }
+
+    /// Per-shard entry counts. Works without the `metrics` feature. Use for imbalance detection.
+    pub fn shard_loads(&self) -> Vec<usize> {
+        self.shards.iter().map(|s| s.len()).collect()
+    }
+
+    /// Per-shard load factor and an estimated average probe length, one entry per shard in
+    /// index order. Each shard is read-locked once; see [`ProbeStats`] for how the estimate is
+    /// derived.
+    pub fn probe_stats(&self) -> Vec<ProbeStats> {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let table = shard.read_lock();
+                ProbeStats::estimate(table.len(), table.capacity())
+            })
+            .collect()
+    }
+
+    /// Generate keys with `gen_key(0)`, `gen_key(1)`, ... until `n` of them land in `n`
+    /// distinct shards, and return those keys in shard-discovery order. Nothing is inserted.
+    ///
+    /// Meant for tests that need keys on different shards (cross-shard renames, lock
+    /// ordering) without hand-picked candidates that break when the hasher changes.
+    ///
+    /// Returns `None` without calling `gen_key` if `n` is greater than the shard count, since
+    /// no `n` keys can then be on distinct shards. Otherwise it gives up and returns `None`
+    /// after `64 * shard_count()` candidates, which is what happens when the router reaches
+    /// fewer than `n` shards (e.g. one that sends every key to shard 0 with `n >= 2`).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<String, u32> = ShardMap::with_shard_count(4)?;
+    /// let keys = map.pick_keys_in_distinct_shards(2, |i| format!("key_{}", i)).unwrap();
+    /// assert_ne!(map.shard_for_key(&keys[0]), map.shard_for_key(&keys[1]));
+    /// # Ok::<(), shardmap::Error>(())
+    /// ```
+    pub fn pick_keys_in_distinct_shards(
+        &self,
+        n: usize,
+        mut gen_key: impl FnMut(usize) -> K,
+    ) -> Option<Vec<K>> {
+        let shard_count = self.shards.len();
+        if n > shard_count {
+            return None;
+        }
+        let mut taken = vec![false; shard_count];
+        let mut keys = Vec::with_capacity(n);
+        for i in 0..shard_count.saturating_mul(64) {
+            if keys.len() == n {
+                break;
+            }
+            let key = gen_key(i);
+            let shard_idx = self.routed_index(self.routing_value(&key));
+            if let Some(slot) = taken.get_mut(shard_idx).filter(|taken| !**taken) {
+                *slot = true;
+                keys.push(key);
+            }
+        }
+        (keys.len() == n).then_some(keys)
+    }
+
+    /// Score how evenly this map's hasher and router spread keys: routes `gen_keys(0..n)`
+    /// without inserting anything and returns a value in `[0, 1]`, where 1.0 is perfectly
+    /// uniform and 0.0 sends every key to one shard.
+    ///
+    /// The score is `1 - chi2 / (n * (shard_count - 1))`, i.e. one minus the squared
+    /// Cramér's V of the per-shard counts against an even split. A well-mixed router on
+    /// random keys scores about `1 - 1/n`; measure a custom router with a sample shaped like
+    /// production keys before deploying it. Returns 1.0 for a single shard or `n == 0`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the router returns an index outside `0..shard_count()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map: ShardMap<u64, ()> = ShardMap::new();
+    /// let score = map.router_uniformity(|i| i as u64, 10_000);
+    /// assert!(score > 0.99);
+    /// ```
+    pub fn router_uniformity<F>(&self, mut gen_keys: F, n: usize) -> f64
+    where
+        F: FnMut(usize) -> K,
+    {
+        let shard_count = self.shards.len();
+        if shard_count == 1 || n == 0 {
+            return 1.0;
+        }
+        let mut counts = vec![0u64; shard_count];
+        for i in 0..n {
+            let idx = self.routed_index(self.routing_value(&gen_keys(i)));
+            assert!(
+                idx < shard_count,
+                "router_uniformity: router returned an out-of-range shard index"
+            );
+            counts[idx] += 1;
+        }
+        let expected = n as f64 / shard_count as f64;
+        let chi2: f64 = counts
+            .iter()
+            .map(|&observed| {
+                let diff = observed as f64 - expected;
+                diff * diff / expected
+            })
+            .sum();
+        1.0 - chi2 / (n as f64 * (shard_count - 1) as f64)
+    }
+
+    /// Whether sharding is defeated for this map, so every entry lands on one hot shard.
+    ///
+    /// True if `K` is zero-sized (all keys hash alike and route to one shard), or if the map
+    /// has more than one shard, at least `shard_count()` entries, and all of them sit in a
+    /// single shard, which points at a constant `Hash` impl or a broken custom router. Cheap
+    /// enough to assert in tests or log at startup; a single-shard map is never degenerate
+    /// unless its key is zero-sized.
+    pub fn is_degenerate(&self) -> bool {
+        if std::mem::size_of::<K>() == 0 {
+            return true;
+        }
+        let loads = self.shard_loads();
+        let total: usize = loads.iter().sum();
+        loads.len() > 1 && total >= loads.len() && loads.contains(&total)
+    }
+
+    /// Per-shard growth rate in entries per second since `prev`, an earlier
+    /// [`shard_loads`](Self::shard_loads) snapshot taken `elapsed` ago. Feeds auto-scaling
+    /// triggers that care about how fast shards fill rather than how full they are.
+    ///
+    /// Shards that shrank get a negative rate. A zero `elapsed` gives infinite (or NaN for
+    /// unchanged shards) rates. Returns [`Error::ShardCountMismatch`] if `prev` doesn't have
+    /// one entry per shard.
+    pub fn load_rates(
+        &self,
+        prev: &[usize],
+        elapsed: std::time::Duration,
+    ) -> Result<Vec<f64>, Error> {
+        if prev.len() != self.shards.len() {
+            return Err(Error::ShardCountMismatch);
+        }
+        let secs = elapsed.as_secs_f64();
+        Ok(self
+            .shard_loads()
+            .into_iter()
+            .zip(prev)
+            .map(|(now, &then)| (now as f64 - then as f64) / secs)
+            .collect())
+    }
+
+    /// Assert the map's internal consistency. Intended for tests and fuzz targets.
+    ///
+    /// Checks that `len()` matches the sum of `shard_loads()`, that the router only returns
+    /// indices below `shard_count()`, and that every key is stored in the shard it routes to
+    /// (catching routing bugs and misbehaving custom routers). Only meaningful while no other
+    /// thread is writing. Available in the crate's own tests and with the `invariant-checks`
+    /// feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a description of the first violated invariant.
+    #[cfg(any(test, feature = "invariant-checks"))]
+    pub fn check_invariants(&self) {
+        let loads = self.shard_loads();
+        assert_eq!(
+            self.len(),
+            loads.iter().sum::<usize>(),
+            "len() does not match the sum of shard_loads()"
+        );
+        let shard_count = self.shard_count();
+        for (idx, shard) in self.shards.iter().enumerate() {
+            let map = shard.read_lock();
+            for key in map.keys() {
+                let routed = self.shard_index(key);
+                assert!(
+                    routed < shard_count,
+                    "router returned shard {} but there are only {} shards",
+                    routed,
+                    shard_count
+                );
+                assert_eq!(
+                    routed, idx,
+                    "key stored in shard {} but routes to shard {}",
+                    idx, routed
+                );
+            }
+        }
+    }
+
+    /// Pass the current [`shard_loads`](Self::shard_loads) to the custom router's
+    /// [`Rebalancer`](crate::Rebalancer) hook, if it has one. Returns whether a rebalancer was
+    /// advised.
+    ///
+    /// Call this periodically to let a load-aware router adjust where *new* keys go. It does
+    /// not move existing entries: a key stays in the shard it was stored in, and lookups go
+    /// through the same router, so a router must keep routing already-seen hashes the same way.
+    /// The halves from [`split_shards`](Self::split_shards) have no rebalancer.
+    pub fn feed_loads_to_router(&self) -> bool {
+        match self.router.as_rebalancer() {
+            Some(rebalancer) => {
+                rebalancer.advise(&self.shard_loads());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How many shards are exclusively (write-) locked at this instant. Requires the
+    /// `lock-introspect` feature.
+    ///
+    /// A cheap, non-blocking sample for spotting write contention live. It is racy: locks can
+    /// be taken or released while the shards are checked, so treat it as an estimate and
+    /// sample it repeatedly rather than acting on a single reading.
+    #[cfg(feature = "lock-introspect")]
+    pub fn locked_shard_count(&self) -> usize {
+        self.shards.iter().filter(|s| s.is_write_locked()).count()
+    }
+
+    /// Per-shard count of `get`/`insert`/`remove` calls, hits and misses alike. Requires the
+    /// `metrics` feature.
+    ///
+    /// Unlike [`shard_loads`](Self::shard_loads), which counts entries, this samples where live
+    /// traffic lands, so it reveals hot keys even when entries are evenly distributed.
+    #[cfg(feature = "metrics")]
+    pub fn access_distribution(&self) -> Vec<u64> {
+        self.shards.iter().map(|s| s.touches()).collect()
+    }
+
+    /// Count entries per group, where `key_group` maps each key to its group (e.g. the
+    /// tenant prefix of `"tenant:id"` keys). Groups with no entries are absent.
+    ///
+    /// A diagnostic O(n) scan: each shard is tallied under its read lock, one shard at a time.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("acme:1", ());
+    /// map.insert("acme:2", ());
+    /// map.insert("globex:1", ());
+    /// let counts = map.count_by(|k| k.split(':').next().unwrap().to_string());
+    /// assert_eq!(counts["acme"], 2);
+    /// assert_eq!(counts["globex"], 1);
+    /// ```
+    pub fn count_by<F, G>(&self, key_group: F) -> std::collections::HashMap<G, usize>
+    where
+        F: Fn(&K) -> G,
+        G: Hash + Eq,
+    {
+        let mut counts = std::collections::HashMap::new();
+        for shard in self.shards.iter() {
+            for key in shard.read_lock().keys() {
+                *counts.entry(key_group(key)).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Count entries for which `f(key, value)` returns `true`.
+    ///
+    /// Each shard is scanned under its read lock, one shard at a time; nothing is cloned or
+    /// collected, unlike filtering [`iter_snapshot`](Self::iter_snapshot). Entries written concurrently
+    /// to shards not yet scanned may or may not be counted.
+    pub fn count_where<F>(&self, f: F) -> usize
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.shards
+            .iter()
+            .map(|shard| shard.read_lock().iter().filter(|(k, v)| f(k, v)).count())
+            .sum()
+    }
+
+    /// Structured diagnostics snapshot: per-shard stats, total operations, and raw `max_load_ratio` for you to interpret.
+    pub fn diagnostics(&self) -> Diagnostics {
+        let shards: Vec<ShardDiagnostics> = self
+            .shards
+            .iter()
+            .map(|s| s.diagnostics_snapshot())
+            .collect();
+        let total_entries: usize = shards.iter().map(|s| s.entries).sum();
+        let n = self.shards.len() as f64;
+        let avg_load_per_shard = if n > 0.0 {
+            total_entries as f64 / n
+        } else {
+            0.0
+        };
+        let max_load = shards.iter().map(|s| s.entries).max().unwrap_or(0) as f64;
+        let max_load_ratio = if avg_load_per_shard > 0.0 {
+            max_load / avg_load_per_shard
+        } else {
+            1.0
+        };
+        let total_operations: u64 = shards.iter().map(|s| s.reads + s.writes + s.removes).sum();
+
+        Diagnostics {
+            total_entries,
+            shards,
+            total_operations,
+            avg_load_per_shard,
+            max_load_ratio,
+        }
+    }
+
+    /// Suggest a shard count based on observed load and lock traffic. Advisory only; requires
+    /// the `metrics` feature.
+    ///
+    /// The heuristic compares the busiest shard with the average, for both entry count
+    /// (`max_load_ratio` from [`diagnostics`](Self::diagnostics)) and lock acquisitions. If
+    /// either ratio is at least 2.0 (traffic or data concentrated on a few shards), it
+    /// suggests doubling the shard count; otherwise it returns the current count. With no
+    /// recorded lock traffic it also returns the current count. More shards spread many warm
+    /// keys, but can't help when a single key is hot.
+    ///
+    /// The map can't change its own shard count; rebuild a new one (e.g. with
+    /// [`with_capacity_and_shards`](Self::with_capacity_and_shards)) if you act on it.
+    #[cfg(feature = "metrics")]
+    pub fn suggest_shard_count(&self) -> usize {
+        const SKEW_THRESHOLD: f64 = 2.0;
+        let diag = self.diagnostics();
+        let current = self.shards.len();
+        let locks: Vec<u64> = diag.shards.iter().map(|s| s.lock_acquisitions).collect();
+        let total_locks: u64 = locks.iter().sum();
+        if total_locks == 0 {
+            return current;
+        }
+        let avg_locks = total_locks as f64 / current as f64;
+        let max_locks = locks.iter().copied().max().unwrap_or(0) as f64;
+        let lock_ratio = max_locks / avg_locks;
+        if lock_ratio >= SKEW_THRESHOLD || diag.max_load_ratio >= SKEW_THRESHOLD {
+            current.saturating_mul(2)
+        } else {
+            current
+        }
+    }
+
+    /// Diagnostic dump of every current key with its routing hash and shard index.
+    ///
+    /// Use to spot-check how real keys distribute (e.g. why several keys collide into one
+    /// shard). This is O(n), allocates, and takes each shard's read lock in turn; don't call
+    /// it on a hot path.
+    pub fn debug_routing(&self) -> Vec<(K, u64, usize)>
+    where
+        K: Clone,
+    {
+        let mut out = Vec::with_capacity(self.len());
+        for shard in self.shards.iter() {
+            let map = shard.read_lock();
+            for key in map.keys() {
+                let hash = self.key_hash(key);
+                out.push((key.clone(), hash, self.routed_index(hash)));
+            }
+        }
+        out
+    }
+
+    /// The last `limit` operations recorded on shard `shard_idx`, oldest first, as
+    /// `(kind, key hash)`. Each shard keeps at most [`TRACE_CAPACITY`](crate::trace::TRACE_CAPACITY)
+    /// entries. Requires the `op-trace` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shard_idx` is out of range.
+    #[cfg(feature = "op-trace")]
+    pub fn recent_ops(&self, shard_idx: usize, limit: usize) -> Vec<(OpKind, u64)> {
+        self.shards[shard_idx].recent_ops(limit)
+    }
+
+    /// Subscribe to per-shard entry counts instead of polling [`shard_loads`](Self::shard_loads).
+    ///
+    /// Spawns a background task on the current tokio runtime that samples `shard_loads()`
+    /// every `interval` and publishes it when it changes. The receiver starts with the current
+    /// loads. The task holds only a weak reference to the shards, so it stops once the map is
+    /// dropped (the channel then closes) or every receiver is dropped. A detached
+    /// [`iter_concurrent`](Self::iter_concurrent) iterator shares the shards and keeps the
+    /// task alive until it is dropped too. Requires the `watch` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside a tokio runtime.
+    #[cfg(feature = "watch")]
+    pub fn watch_loads(
+        &self,
+        interval: std::time::Duration,
+    ) -> tokio::sync::watch::Receiver<Vec<usize>>
+    where
+        K: 'static,
+        V: 'static,
+    {
+        let (tx, rx) = tokio::sync::watch::channel(self.shard_loads());
+        let shards = Arc::downgrade(&self.shards);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if tx.is_closed() {
+                    break;
+                }
+                let Some(shards) = shards.upgrade() else {
+                    break;
+                };
+                let loads: Vec<usize> = shards.iter().map(|s| s.len()).collect();
+                drop(shards);
+                tx.send_if_modified(|current| {
+                    if *current == loads {
+                        return false;
+                    }
+                    *current = loads;
+                    true
+                });
+            }
+        });
+        rx
+    }
+
+    /// Subscribe to changes of one key's value: the receiver starts with the current value
+    /// (`None` if absent) and sees every later insert, update or removal of the key,
+    /// including eviction, `clear` and bulk operations. Requires the `watch` feature.
+    ///
+    /// Watches are kept per shard, next to the shard's table. Once a shard has any watch,
+    /// every write to it checks all of that shard's watched keys (one lookup each, under the
+    /// write lock) and publishes those whose stored `Arc` changed, so keep watches few per
+    /// shard on write-heavy maps. A watch is dropped on the first write after all its
+    /// receivers are gone. Rapid changes may be coalesced: receivers see the latest value,
+    /// not every intermediate one. Operations that take `&mut self` and bypass the locks
+    /// (`rebuild`, `set_routing`, `compact`) don't notify.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// let mut rx = map.watch_key(&"config");
+    /// assert!(rx.borrow().is_none());
+    ///
+    /// map.insert("config", 2);
+    /// rx.changed().await.unwrap();
+    /// assert_eq!(rx.borrow().as_deref(), Some(&2));
+    /// # }
+    /// ```
+    #[cfg(feature = "watch")]
+    pub fn watch_key(&self, key: &K) -> tokio::sync::watch::Receiver<Option<Arc<V>>>
+    where
+        K: Clone,
+    {
+        let mut normalized = None;
+        let key = self.normalize_ref(key, &mut normalized);
+        let shard_idx = self.shard_index(key);
+        self.shards[shard_idx].watch_key(key.clone())
+    }
+
+    /// Snapshot the current keys into a [`BloomFilter`](crate::BloomFilter) with roughly
+    /// `false_positive_rate` false positives, so a hot read path can skip definite misses
+    /// before taking a shard lock.
+    ///
+    /// Keys are hashed with the map's hasher while each shard is read-locked in turn, and
+    /// lookups are normalized with the map's key normalizer like any other keyed operation.
+    /// The filter is a point-in-time snapshot and goes stale: it never learns of later
+    /// inserts, so a "definitely absent" answer only holds for keys that were absent at build
+    /// time. Rebuild it periodically, or only use it for key sets that don't grow.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < false_positive_rate < 1`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.insert("present", 1);
+    /// let bloom = map.build_bloom(0.01);
+    /// assert!(bloom.might_contain(&"present"));
+    /// ```
+    #[cfg(feature = "bloom")]
+    pub fn build_bloom(&self, false_positive_rate: f64) -> crate::BloomFilter<K> {
+        let mut bloom = crate::BloomFilter::with_rate(
+            self.len(),
+            false_positive_rate,
+            Arc::clone(&self.hash),
+            self.normalizer.clone(),
+        );
+        for shard in self.shards.iter() {
+            for key in shard.read_lock().keys() {
+                bloom.insert(key);
+            }
+        }
+        bloom
+    }
+
+    /// Get detailed statistics about the map and its shards.
+    pub fn stats(&self) -> Stats {
+        let shard_sizes = self.shard_loads();
+        let operations = self.operations_snapshot();
+        let size: usize = shard_sizes.iter().sum();
+
+        Stats {
+            size,
+            shard_sizes,
+            operations,
+        }
     }
 
-    /// Shrink each shard to fit its current length. Reduces memory use after removals.
-    pub fn shrink_to_fit(&self) {
-        for shard in &self.shards {
-            shard.shrink_to_fit();
-        }
+    /// Per-shard operation counters only, the `operations` half of [`stats`](Self::stats).
+    ///
+    /// Reads each shard's atomic counters without taking any lock, so it is cheaper than
+    /// `stats()` (which read-locks every shard for its size) for metrics that only track
+    /// operations. All zero without the `metrics` feature.
+    pub fn operations_snapshot(&self) -> Vec<ShardOps> {
+        self.shards.iter().map(|shard| shard.stats()).collect()
     }
 
-    /// Get the value for the key, or insert the value and return a new `Arc<V>`.
+    /// Capture per-shard loads and operation counters, to compare against a later snapshot
+    /// with [`DiagnosticsSnapshot::delta`] ("what happened since the last check").
+    ///
+    /// Shards are read one at a time, so under concurrent writes the snapshot is not a single
+    /// point in time across shards.
     ///
     /// # Example
     ///
@@ -258,17 +3295,30 @@ where
     /// use shardmap::ShardMap;
     ///
     /// let map = ShardMap::new();
-    /// let v = map.get_or_insert("counter", 0);
-    /// assert_eq!(*v, 0);
-    /// map.get_or_insert("counter", 99); // no-op, already present
-    /// assert_eq!(*map.get(&"counter").unwrap(), 0);
+    /// let before = map.diagnostics_snapshot();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// let delta = map.diagnostics_snapshot().delta(&before);
+    /// assert_eq!(delta.entry_change(), 2);
     /// ```
-    pub fn get_or_insert(&self, key: K, value: V) -> Arc<V> {
-        let shard_idx = self.shard_index(&key);
-        self.shards[shard_idx].get_or_insert(key, value)
+    pub fn diagnostics_snapshot(&self) -> DiagnosticsSnapshot {
+        DiagnosticsSnapshot {
+            taken_at: std::time::Instant::now(),
+            loads: self.shard_loads(),
+            operations: self.shards.iter().map(|s| s.stats()).collect(),
+        }
     }
 
-    /// Get the value for the key, or compute it with `f` and insert it.
+    /// Run `f` with every shard read-locked, giving it a globally consistent view: no write
+    /// can land anywhere in the map until `f` returns.
+    ///
+    /// This is stronger than [`iter_snapshot`](Self::iter_snapshot), which is only consistent
+    /// per shard. Read locks are acquired in shard-index order, the same order multi-shard
+    /// writers use, so it can't deadlock with them. Reads inside `f` (`get`, `len`,
+    /// iteration, ...) work as usual.
+    ///
+    /// **Cost:** every writer on every shard blocks for the whole duration of `f`. Keep `f`
+    /// short. `f` must not write to this map: that would deadlock on the held read locks.
     ///
     /// # Example
     ///
@@ -276,19 +3326,25 @@ where
     /// use shardmap::ShardMap;
     ///
     /// let map = ShardMap::new();
-    /// let v = map.get_or_insert_with("expensive", || "computed".to_string());
-    /// assert_eq!(v.as_str(), "computed");
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// let (len, sum) = map.freeze_for_read(|m| (m.len(), m.iter_snapshot().map(|(_, v)| *v).sum::<i32>()));
+    /// assert_eq!((len, sum), (2, 3));
     /// ```
-    pub fn get_or_insert_with<F>(&self, key: K, f: F) -> Arc<V>
+    pub fn freeze_for_read<R, F>(&self, f: F) -> R
     where
-        F: FnOnce() -> V,
+        F: FnOnce(&ShardMap<K, V>) -> R,
     {
-        let shard_idx = self.shard_index(&key);
-        self.shards[shard_idx].get_or_insert_with(key, f)
+        let _guards: Vec<_> = self.shards.iter().map(|s| s.read_lock()).collect();
+        let _scope = GlobalReadScope::enter();
+        f(self)
     }
 
-    /// Insert the key-value pair only if the key is not present.
-    /// Returns `Ok(arc)` with the inserted value, or `Err(arc)` with the existing value.
+    /// Create a snapshot-based iterator over all key-value pairs.
+    ///
+    /// This iterator captures the current state of the map into a vector,
+    /// then iterates over it. It won't see concurrent modifications made
+    /// after the snapshot is taken, but provides a consistent view.
     ///
     /// # Example
     ///
@@ -296,19 +3352,32 @@ where
     /// use shardmap::ShardMap;
     ///
     /// let map = ShardMap::new();
-    /// assert!(map.try_insert("key", "first").is_ok());
-    /// assert!(map.try_insert("key", "second").is_err());
-    /// assert_eq!(*map.get(&"key").unwrap(), "first");
+    /// map.insert("key1", "value1");
+    /// map.insert("key2", "value2");
+    ///
+    /// let mut count = 0;
+    /// for (_key, _value) in map.iter_snapshot() {
+    ///     count += 1;
+    /// }
+    /// assert_eq!(count, 2);
     /// ```
-    pub fn try_insert(&self, key: K, value: V) -> Result<Arc<V>, Arc<V>> {
-        let shard_idx = self.shard_index(&key);
-        self.shards[shard_idx].try_insert(key, value)
+    pub fn iter_snapshot(&self) -> crate::iter::SnapshotIter<K, V>
+    where
+        K: Clone,
+    {
+        crate::iter::SnapshotIter::new(&self.shards)
     }
 
-    /// Update a value using a closure, returning the new value if the key existed.
+    /// Iterate over the map's entries; the recommended default, same as
+    /// [`iter_snapshot`](Self::iter_snapshot).
     ///
-    /// Note: This requires `V: Clone` because if the value is shared (multiple
-    /// `Arc` references exist), it will clone the value before modifying it.
+    /// Entries are copied (keys cloned, values as `Arc`s) shard by shard under each shard's
+    /// read lock, and iteration then runs with no lock held, so the loop body may freely call
+    /// back into the map. The result is a point-in-time copy of each shard: writes made
+    /// after a shard was copied are not seen. For a live walk that may observe concurrent
+    /// writes but copies nothing up front, use [`iter_concurrent`](Self::iter_concurrent);
+    /// for one consistent view of every shard at once, use
+    /// [`freeze_for_read`](Self::freeze_for_read).
     ///
     /// # Example
     ///
@@ -316,168 +3385,185 @@ where
     /// use shardmap::ShardMap;
     ///
     /// let map = ShardMap::new();
-    /// map.insert("counter", 0);
-    ///
-    /// map.update(&"counter", |v| *v += 1);
-    /// assert_eq!(*map.get(&"counter").unwrap(), 1);
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// let total: i32 = map.iter().map(|(_, v)| *v).sum();
+    /// assert_eq!(total, 3);
     /// ```
-    pub fn update<F>(&self, key: &K, f: F) -> Option<Arc<V>>
+    pub fn iter(&self) -> crate::iter::SnapshotIter<K, V>
     where
-        F: FnOnce(&mut V),
-        V: Clone,
+        K: Clone,
     {
-        let shard_idx = self.shard_index(key);
-        self.shards[shard_idx].update(key, f)
+        self.iter_snapshot()
     }
 
-    /// Rename a key to a new key, moving the value without copying.
+    /// Consume the map and return every entry sorted by key, e.g. for a deterministic final
+    /// export at teardown.
     ///
-    /// **Same shard:** The operation is atomic under that shard's lock: either
-    /// both the old key is removed and the new key is inserted, or neither happens.
+    /// Entries are moved out of the shards, so no locks are taken and no values are cloned.
+    /// A write-through store, if any, is not notified.
+    pub fn into_sorted_vec(mut self) -> Vec<(K, Arc<V>)>
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<(K, Arc<V>)> = Vec::with_capacity(self.len());
+        for shard in self.take_shards() {
+            entries.extend(shard.into_entries());
+        }
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Consume the map and merge every shard into one `std::collections::HashMap`, e.g. when
+    /// handing the data to single-threaded code.
     ///
-    /// **Cross-shard:** Old and new keys map to different shards. This implementation
-    /// acquires both shard locks (old then new). The move is atomic from the caller's
-    /// view (all-or-nothing), but it is not a single lock — so don't assume the same
-    /// atomicity guarantees as within a single shard.
+    /// Entries are moved out of the shards, so no locks are taken and neither keys nor values
+    /// are cloned. A write-through store, if any, is not notified.
+    pub fn into_inner_hashmap(mut self) -> std::collections::HashMap<K, Arc<V>> {
+        let mut merged = std::collections::HashMap::with_capacity(self.len());
+        for shard in self.take_shards() {
+            merged.extend(shard.into_entries());
+        }
+        merged
+    }
+
+    /// Consume the map and split it into two maps: the first owns shards `[0, pivot)`, the
+    /// second `[pivot, shard_count)`, e.g. to hand half of the shards to another process.
     ///
-    /// Returns an error if the old key is missing or the new key already exists.
-    /// For cross-shard renames, `K: Clone` is required for conflict recovery.
+    /// Shards are moved, not rehashed, so each entry stays in its shard and keeps its
+    /// eviction order. Each half keeps the hasher, normalizer and hooks, and routes with the
+    /// original router over the original shard count, shifted into its own range: every key
+    /// of that half is found where it was. Keys that belong to the other half route to
+    /// `original_index % half_len` instead, so writing them to the wrong half still works
+    /// but puts them where the unsplit layout wouldn't. The halves report
+    /// [`RoutingKind::Custom`](crate::RoutingKind::Custom), may have a shard count that isn't
+    /// a power of two, and can be split again. Keep them on custom routing (the split router,
+    /// or e.g. a modulo router through [`set_routing`](Self::set_routing)): default routing
+    /// masks the hash and would leave shards unused on such counts, so `set_routing` refuses
+    /// it with [`Error::InvalidShardCount`].
+    ///
+    /// A [`Rebalancer`](crate::Rebalancer) on the original router is not reachable from the
+    /// halves: it expects loads for every original shard, and each half only knows its own.
+    /// [`feed_loads_to_router`](Self::feed_loads_to_router) returns `false` on them; advise
+    /// the router directly with loads gathered from both halves if it still needs feedback.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < pivot < shard_count()`.
     ///
     /// # Example
     ///
     /// ```rust
     /// use shardmap::ShardMap;
     ///
-    /// let map = ShardMap::new();
-    /// map.insert("old_key", "value");
-    ///
-    /// map.rename(&"old_key", "new_key").unwrap();
-    /// assert!(map.get(&"old_key").is_none());
-    /// assert_eq!(*map.get(&"new_key").unwrap(), "value");
+    /// let map = ShardMap::with_shard_count(4).unwrap();
+    /// for i in 0..100 {
+    ///     map.insert(i, i);
+    /// }
+    /// let (low, high) = map.split_shards(1);
+    /// assert_eq!((low.shard_count(), high.shard_count()), (1, 3));
+    /// assert_eq!(low.len() + high.len(), 100);
     /// ```
-    pub fn rename(&self, old_key: &K, new_key: K) -> Result<(), Error>
-    where
-        K: Clone,
-    {
-        let old_shard_idx = self.shard_index(old_key);
-        let new_shard_idx = self.shard_index(&new_key);
-
-        // If both keys map to the same shard, use atomic rename
-        if old_shard_idx == new_shard_idx {
-            return self.shards[old_shard_idx].rename(old_key, new_key);
-        }
-
-        // Different shards: use cross-shard rename helper
-        // This requires K: Clone for conflict recovery
-        self.rename_cross_shard(old_key, new_key, old_shard_idx, new_shard_idx)
+    pub fn split_shards(mut self, pivot: usize) -> (Self, Self) {
+        let total = self.shards.len();
+        assert!(
+            pivot > 0 && pivot < total,
+            "split pivot {} must be in 1..{}",
+            pivot,
+            total
+        );
+        let mut lower = self.take_shards();
+        let upper = lower.split_off(pivot);
+        let half = |shards: Vec<Shard<K, V>>, start: usize| Self {
+            shards: shards.into(),
+            hash: self.hash.clone(),
+            router: Router::Custom(Arc::new(SplitRouter {
+                inner: self.router.clone(),
+                total,
+                start,
+            })),
+            normalizer: self.normalizer.clone(),
+            read_through: self.read_through.clone(),
+            #[cfg(feature = "metrics")]
+            write_baseline: parking_lot::Mutex::new(None),
+        };
+        (half(lower, 0), half(upper, pivot))
     }
 
-    /// Helper for cross-shard rename operations.
-    /// This handles the case where we need to lock both shards and ensure atomicity.
-    fn rename_cross_shard(
-        &self,
-        old_key: &K,
-        new_key: K,
-        old_shard_idx: usize,
-        new_shard_idx: usize,
-    ) -> Result<(), Error>
+    /// Remove and return every entry for which `f` returns true, one shard write lock at a
+    /// time. Unlike [`retain_collecting`](Self::retain_collecting), `f` sees the stored `Arc`
+    /// read-only, so `V: Clone` isn't required.
+    pub fn drain_filter<F>(&self, mut f: F) -> Vec<(K, Arc<V>)>
     where
-        K: Clone,
+        F: FnMut(&K, &Arc<V>) -> bool,
     {
-        // For cross-shard renames, we lock both shards in order to prevent deadlock
-        // We check the new shard first, then remove from old shard, then insert
-        let old_shard = &self.shards[old_shard_idx];
-        let new_shard = &self.shards[new_shard_idx];
-
-        // Check if new key already exists (this acquires a read lock)
-        if new_shard.contains_key(&new_key) {
-            return Err(Error::KeyAlreadyExists);
-        }
-
-        // Remove value from old shard
-        let value = old_shard.remove(old_key).ok_or(Error::KeyNotFound)?;
-
-        // Double-check new shard (it might have been inserted between our check and now)
-        // This is a race condition we need to handle
-        if new_shard.contains_key(&new_key) {
-            // Conflict: restore the value to old shard
-            old_shard.insert_arc(old_key.clone(), value);
-            return Err(Error::KeyAlreadyExists);
+        let mut removed = Vec::new();
+        for shard in self.shards.iter() {
+            removed.extend(shard.drain_where(&mut f));
         }
-
-        // Insert into new shard
-        new_shard.insert_arc(new_key, value);
-        Ok(())
+        removed
     }
 
-    /// Get the total number of entries across all shards.
+    /// [`drain_filter`](Self::drain_filter) with shards drained in parallel on the rayon
+    /// pool, for removal-heavy passes over large maps. Requires the `rayon` feature.
     ///
-    /// Note: This operation requires acquiring read locks on all shards, so it
-    /// may be slow for large numbers of shards. For better performance, use
-    /// `stats()` which provides more detailed information.
-    pub fn len(&self) -> usize {
-        self.shards.iter().map(|shard| shard.len()).sum()
-    }
-
-    /// Check if the map is empty.
-    pub fn is_empty(&self) -> bool {
-        self.shards.iter().all(|shard| shard.is_empty())
-    }
-
-    /// Per-shard entry counts. Works without the `metrics` feature. Use for imbalance detection.
-    pub fn shard_loads(&self) -> Vec<usize> {
-        self.shards.iter().map(|s| s.len()).collect()
-    }
+    /// Each shard is drained under its own write lock by one task; results are concatenated
+    /// in shard order. Every shard is briefly write-locked concurrently with the others, so
+    /// expect more write contention than the serial version while it runs.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use shardmap::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// for i in 0..100 {
+    ///     map.insert(i, i);
+    /// }
+    /// let removed = map.par_drain_filter(|_, v| **v % 2 == 0);
+    /// assert_eq!(removed.len(), 50);
+    /// assert_eq!(map.len(), 50);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_drain_filter<F>(&self, f: F) -> Vec<(K, Arc<V>)>
+    where
+        F: Fn(&K, &Arc<V>) -> bool + Sync,
+    {
+        use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-    /// Structured diagnostics snapshot: per-shard stats, total operations, and raw `max_load_ratio` for you to interpret.
-    pub fn diagnostics(&self) -> Diagnostics {
-        let shards: Vec<ShardDiagnostics> = self
+        let drained: Vec<Vec<(K, Arc<V>)>> = self
             .shards
-            .iter()
-            .map(|s| s.diagnostics_snapshot())
+            .par_iter()
+            .map(|shard| shard.drain_where(&f))
             .collect();
-        let total_entries: usize = shards.iter().map(|s| s.entries).sum();
-        let n = self.shards.len() as f64;
-        let avg_load_per_shard = if n > 0.0 {
-            total_entries as f64 / n
-        } else {
-            0.0
-        };
-        let max_load = shards.iter().map(|s| s.entries).max().unwrap_or(0) as f64;
-        let max_load_ratio = if avg_load_per_shard > 0.0 {
-            max_load / avg_load_per_shard
-        } else {
-            1.0
-        };
-        let total_operations: u64 = shards.iter().map(|s| s.reads + s.writes + s.removes).sum();
-
-        Diagnostics {
-            total_entries,
-            shards,
-            total_operations,
-            avg_load_per_shard,
-            max_load_ratio,
-        }
+        drained.into_iter().flatten().collect()
     }
 
-    /// Get detailed statistics about the map and its shards.
-    pub fn stats(&self) -> Stats {
-        let shard_sizes = self.shard_loads();
-        let operations: Vec<ShardOps> = self.shards.iter().map(|s| s.stats()).collect();
-        let size: usize = shard_sizes.iter().sum();
+    /// Consume the map into a parallel iterator over its entries, e.g. for a final parallel
+    /// flush to storage. Requires the `rayon` feature.
+    ///
+    /// Each shard's table is moved out whole (no locks, no clones) and shards are processed
+    /// in parallel; entries within a shard are yielded sequentially. A write-through store,
+    /// if any, is not notified.
+    #[cfg(feature = "rayon")]
+    pub fn into_par_iter(mut self) -> impl rayon::iter::ParallelIterator<Item = (K, Arc<V>)> {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
-        Stats {
-            size,
-            shard_sizes,
-            operations,
-        }
+        self.take_shards()
+            .into_par_iter()
+            .flat_map_iter(|shard| shard.into_entries())
     }
 
-    /// Create a snapshot-based iterator over all key-value pairs.
+    /// Snapshot the map in chunks of at most `chunk_size` entries, e.g. for streaming export.
     ///
-    /// This iterator captures the current state of the map into a vector,
-    /// then iterates over it. It won't see concurrent modifications made
-    /// after the snapshot is taken, but provides a consistent view.
+    /// Unlike [`iter_snapshot`](Self::iter_snapshot), which copies every entry up front, this
+    /// snapshots shard by shard as chunks are consumed, bounding peak memory to roughly
+    /// `chunk_size` entries plus one shard. Concatenated, the chunks hold every entry once
+    /// if there are no concurrent writes; each shard is consistent, the map as a whole is not.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
     ///
     /// # Example
     ///
@@ -485,20 +3571,17 @@ where
     /// use shardmap::ShardMap;
     ///
     /// let map = ShardMap::new();
-    /// map.insert("key1", "value1");
-    /// map.insert("key2", "value2");
-    ///
-    /// let mut count = 0;
-    /// for (_key, _value) in map.iter_snapshot() {
-    ///     count += 1;
+    /// for i in 0..10 {
+    ///     map.insert(i, i);
     /// }
-    /// assert_eq!(count, 2);
+    /// let sizes: Vec<usize> = map.snapshot_chunks(4).map(|chunk| chunk.len()).collect();
+    /// assert_eq!(sizes, vec![4, 4, 2]);
     /// ```
-    pub fn iter_snapshot(&self) -> crate::iter::SnapshotIter<K, V>
+    pub fn snapshot_chunks(&self, chunk_size: usize) -> crate::iter::SnapshotChunks<'_, K, V>
     where
         K: Clone,
     {
-        crate::iter::SnapshotIter::new(&self.shards)
+        crate::iter::SnapshotChunks::new(&self.shards, chunk_size)
     }
 
     /// Create a concurrent-safe iterator over all key-value pairs.
@@ -507,6 +3590,10 @@ where
     /// see concurrent modifications. However, it may see partial updates if
     /// entries are moved between shards during iteration.
     ///
+    /// The iterator shares the shard storage instead of borrowing the map, so it can outlive
+    /// the `&self` borrow (e.g. be moved into a spawned task) and keeps working if the map
+    /// is dropped meanwhile.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -522,11 +3609,46 @@ where
     /// }
     /// assert_eq!(count, 2);
     /// ```
-    pub fn iter_concurrent(&self) -> crate::iter::ConcurrentIter<'_, K, V>
+    pub fn iter_concurrent(&self) -> crate::iter::ConcurrentIter<K, V>
     where
         K: Clone,
     {
-        crate::iter::ConcurrentIter::new(&self.shards)
+        crate::iter::ConcurrentIter::new(Arc::clone(&self.shards))
+    }
+}
+
+/// Set-style API for maps used as concurrent sets.
+///
+/// # Example
+///
+/// ```rust
+/// use shardmap::ShardMap;
+///
+/// let seen: ShardMap<&str, ()> = ShardMap::new();
+/// assert!(seen.set_insert("a"));
+/// assert!(!seen.set_insert("a"));
+/// assert!(seen.set_contains(&"a"));
+/// assert!(seen.set_remove(&"a"));
+/// assert!(!seen.set_remove(&"a"));
+/// ```
+impl<K> ShardMap<K, ()>
+where
+    K: Hash + Eq + Send + Sync,
+{
+    /// Add `key`, returning `true` if it was not already present. Wraps
+    /// [`try_insert`](Self::try_insert).
+    pub fn set_insert(&self, key: K) -> bool {
+        self.try_insert(key, ()).is_ok()
+    }
+
+    /// Whether `key` is in the set. Same as [`contains_key`](Self::contains_key).
+    pub fn set_contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+
+    /// Remove `key`, returning `true` if it was present.
+    pub fn set_remove(&self, key: &K) -> bool {
+        self.remove(key).is_some()
     }
 }
 
@@ -539,3 +3661,134 @@ where
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_invariants_passes() {
+        let map = ShardMap::new();
+        for i in 0..200 {
+            map.insert(i, i);
+        }
+        map.remove(&7);
+        map.check_invariants();
+    }
+
+    #[test]
+    fn test_compact_moves_misplaced_entries() {
+        let mut map: ShardMap<u32, u32> = ShardMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        // Misplace a few entries by hand.
+        for key in [3, 14, 15] {
+            let home = map.shard_for_key(&key);
+            let value = map.shards[home].remove(&key).unwrap();
+            map.shards[(home + 1) % map.shard_count()].insert_arc(key, value);
+        }
+        assert!(map.get(&14).is_none());
+
+        assert_eq!(map.compact(), Ok(3));
+        map.check_invariants();
+        assert_eq!(*map.get(&14).unwrap(), 14);
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.compact(), Ok(0));
+    }
+
+    #[test]
+    fn test_compact_respects_bound() {
+        use crate::OnFull;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let skewed = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&skewed);
+        let mut map: ShardMap<u32, u32> = crate::ShardMapBuilder::new()
+            .shard_count(4)
+            .unwrap()
+            .max_entries_per_shard(2)
+            .on_full(OnFull::Reject)
+            .routing_fn(move |hash, shard_count| {
+                if flag.load(Ordering::SeqCst) {
+                    0
+                } else {
+                    (hash % shard_count as u64) as usize
+                }
+            })
+            .build()
+            .unwrap();
+        let mut key = 0;
+        while map.len() < 8 {
+            let _ = map.checked_insert(key, key);
+            key += 1;
+        }
+        assert_eq!(map.shard_loads(), [2, 2, 2, 2]);
+
+        skewed.store(true, Ordering::SeqCst);
+        assert_eq!(map.compact(), Err(Error::ShardFull));
+        assert_eq!(map.shard_loads(), [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_single_flight_runs_factory_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Barrier;
+
+        let map: ShardMap<&str, String> = ShardMap::new();
+        let flights = || map.shards[map.shard_index(&"key")].flights();
+        let runs = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+        let fill = |outcome: Result<&'static str, &'static str>| {
+            // The leader's factory waits until the other seven callers have joined its
+            // flight, so none of them can arrive after it finishes and fill again.
+            let target = flights().followed() + 7;
+            std::thread::scope(|s| {
+                let handles: Vec<_> = (0..8)
+                    .map(|_| {
+                        s.spawn(|| {
+                            barrier.wait();
+                            map.get_or_try_insert_single_flight("key", || {
+                                runs.fetch_add(1, Ordering::SeqCst);
+                                while flights().followed() < target {
+                                    std::thread::yield_now();
+                                }
+                                outcome.map(String::from).map_err(String::from)
+                            })
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        // A failing fill runs once; every caller sees its error and nothing is cached.
+        let results = fill(Err("backend down"));
+        assert_eq!(runs.swap(0, Ordering::SeqCst), 1);
+        assert!(results
+            .iter()
+            .all(|r| r == &Err("backend down".to_string())));
+        assert!(!map.contains_key(&"key"));
+
+        // The next fill runs again, once, and every caller gets the same stored Arc.
+        let results = fill(Ok("value"));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        let stored = map.get(&"key").unwrap();
+        assert_eq!(stored.as_str(), "value");
+        for result in &results {
+            assert!(Arc::ptr_eq(result.as_ref().unwrap(), &stored));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "routes to shard")]
+    fn test_check_invariants_catches_misrouted_key() {
+        let map: ShardMap<u32, u32> = ShardMap::new();
+        let wrong = (map.shard_for_key(&1) + 1) % map.shard_count();
+        map.shards[wrong].insert_arc(1, Arc::new(1));
+        map.check_invariants();
+    }
+}
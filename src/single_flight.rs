@@ -0,0 +1,148 @@
+use hashbrown::HashMap;
+use parking_lot::{Condvar, Mutex};
+use std::any::Any;
+use std::hash::Hash;
+use std::sync::Arc;
+
+type Erased = Arc<dyn Any + Send + Sync>;
+
+/// In-progress fills for one shard, keyed by the key being filled.
+///
+/// Each flight is stored type-erased because its result type depends on the caller's error
+/// type; a caller whose result type doesn't match the running flight gets
+/// [`Entered::Alone`].
+pub(crate) struct Flights<K> {
+    inflight: Mutex<HashMap<K, Erased>>,
+    /// How many callers have joined a running flight, so tests can hold a leader until
+    /// every follower is waiting.
+    #[cfg(test)]
+    followed: std::sync::atomic::AtomicUsize,
+}
+
+/// What [`Flights::enter`] found for a key.
+pub(crate) enum Entered<'a, K: Hash + Eq, T, R> {
+    /// The value was already present.
+    Ready(R),
+    /// Another caller is filling the key; wait on its flight.
+    Follow(Arc<Flight<T>>),
+    /// This caller fills the key and must report through the handle.
+    Lead(Lead<'a, K, T>),
+    /// A flight with a different result type is running; fill without coordination.
+    Alone,
+}
+
+enum FlightState<T> {
+    Pending,
+    Done(T),
+    /// The leader panicked; followers start over.
+    Abandoned,
+}
+
+/// One fill in progress, shared by its leader and followers.
+pub(crate) struct Flight<T> {
+    state: Mutex<FlightState<T>>,
+    done: Condvar,
+}
+
+impl<T: Clone> Flight<T> {
+    /// Block until the leader finishes. `None` if it panicked.
+    pub fn wait(&self) -> Option<T> {
+        let mut state = self.state.lock();
+        loop {
+            match &*state {
+                FlightState::Pending => self.done.wait(&mut state),
+                FlightState::Done(result) => return Some(result.clone()),
+                FlightState::Abandoned => return None,
+            }
+        }
+    }
+}
+
+/// The leader's side of a flight. Dropping it without [`complete`](Self::complete), e.g.
+/// while unwinding from a panicking factory, abandons the flight so followers retry.
+pub(crate) struct Lead<'a, K: Hash + Eq, T> {
+    flights: &'a Flights<K>,
+    key: K,
+    flight: Arc<Flight<T>>,
+    finished: bool,
+}
+
+impl<K: Hash + Eq, T> Lead<'_, K, T> {
+    /// Publish `result` to the followers and retire the flight.
+    pub fn complete(mut self, result: T) {
+        self.finish(FlightState::Done(result));
+    }
+
+    fn finish(&mut self, outcome: FlightState<T>) {
+        self.finished = true;
+        {
+            let mut inflight = self.flights.inflight.lock();
+            let ours = inflight.get(&self.key).is_some_and(|f| {
+                Arc::as_ptr(f) as *const () == Arc::as_ptr(&self.flight) as *const ()
+            });
+            if ours {
+                inflight.remove(&self.key);
+            }
+        }
+        *self.flight.state.lock() = outcome;
+        self.flight.done.notify_all();
+    }
+}
+
+impl<K: Hash + Eq, T> Drop for Lead<'_, K, T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish(FlightState::Abandoned);
+        }
+    }
+}
+
+impl<K: Hash + Eq> Flights<K> {
+    pub fn new() -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new()),
+            #[cfg(test)]
+            followed: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn followed(&self) -> usize {
+        self.followed.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Join the flight for `key`, or start one. `present` looks the key up in the table and
+    /// runs under the flights lock, so a leader can't finish between the lookup and joining.
+    pub fn enter<T, R>(&self, key: &K, present: impl FnOnce() -> Option<R>) -> Entered<'_, K, T, R>
+    where
+        K: Clone,
+        T: Send + Sync + 'static,
+    {
+        let mut inflight = self.inflight.lock();
+        if let Some(value) = present() {
+            return Entered::Ready(value);
+        }
+        if let Some(running) = inflight.get(key) {
+            return match Arc::clone(running).downcast::<Flight<T>>() {
+                Ok(flight) => {
+                    #[cfg(test)]
+                    self.followed
+                        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Entered::Follow(flight)
+                }
+                Err(_) => Entered::Alone,
+            };
+        }
+        let flight = Arc::new(Flight {
+            state: Mutex::new(FlightState::Pending),
+            done: Condvar::new(),
+        });
+        inflight.insert(key.clone(), Arc::clone(&flight) as Erased);
+        Entered::Lead(Lead {
+            flights: self,
+            key: key.clone(),
+            flight,
+            finished: false,
+        })
+    }
+}
@@ -2,9 +2,14 @@
 
 #[cfg(feature = "metrics")]
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 /// Per-shard operation statistics.
-#[derive(Debug, Clone, Default)]
+///
+/// New counters may be added in minor releases, so this can't be built with a struct
+/// literal outside the crate; start from `ShardOps::default()` instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct ShardOps {
     /// Number of read operations on this shard.
     pub reads: u64,
@@ -16,6 +21,10 @@ pub struct ShardOps {
     pub lock_acquisitions: u64,
     /// Cumulative lock wait time in nanoseconds (0 when lock-timing disabled).
     pub lock_wait_nanos: u64,
+    /// `get` calls that found the key (0 when metrics disabled).
+    pub get_hits: u64,
+    /// `get` calls that did not find the key (0 when metrics disabled).
+    pub get_misses: u64,
 }
 
 /// Thread-safe statistics tracker for a single shard.
@@ -25,6 +34,9 @@ pub(crate) struct ShardStats {
     writes: AtomicU64,
     removes: AtomicU64,
     lock_acquisitions: AtomicU64,
+    touches: AtomicU64,
+    get_hits: AtomicU64,
+    get_misses: AtomicU64,
     #[cfg(feature = "lock-timing")]
     lock_wait_nanos: AtomicU64,
 }
@@ -37,6 +49,9 @@ impl ShardStats {
             writes: AtomicU64::new(0),
             removes: AtomicU64::new(0),
             lock_acquisitions: AtomicU64::new(0),
+            touches: AtomicU64::new(0),
+            get_hits: AtomicU64::new(0),
+            get_misses: AtomicU64::new(0),
             #[cfg(feature = "lock-timing")]
             lock_wait_nanos: AtomicU64::new(0),
         }
@@ -62,6 +77,27 @@ impl ShardStats {
         self.lock_acquisitions.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// Count a `get`/`insert`/`remove` routed to this shard, hit or miss.
+    #[inline]
+    pub fn record_touch(&self) {
+        self.touches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn touches(&self) -> u64 {
+        self.touches.load(Ordering::Relaxed)
+    }
+
+    /// Count a `get` as a hit or a miss.
+    #[inline]
+    pub fn record_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.get_hits
+        } else {
+            &self.get_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
     #[cfg(feature = "lock-timing")]
     #[inline]
     pub fn record_lock_wait(&self, nanos: u64) {
@@ -78,6 +114,8 @@ impl ShardStats {
             lock_wait_nanos: self.lock_wait_nanos.load(Ordering::Relaxed),
             #[cfg(not(feature = "lock-timing"))]
             lock_wait_nanos: 0,
+            get_hits: self.get_hits.load(Ordering::Relaxed),
+            get_misses: self.get_misses.load(Ordering::Relaxed),
         }
     }
 }
@@ -111,6 +149,12 @@ impl ShardStats {
     #[inline]
     pub fn record_lock_acquisition(&self) {}
 
+    #[inline]
+    pub fn record_touch(&self) {}
+
+    #[inline]
+    pub fn record_lookup(&self, _hit: bool) {}
+
     #[cfg(feature = "lock-timing")]
     #[inline]
     pub fn record_lock_wait(&self, _nanos: u64) {}
@@ -139,10 +183,16 @@ pub struct Stats {
 }
 
 /// Per-shard diagnostics snapshot.
+///
+/// New fields may be added in minor releases; read it, don't build it.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct ShardDiagnostics {
     /// Number of entries in this shard.
     pub entries: usize,
+    /// Entries this shard's table can hold without reallocating, read under the same lock as
+    /// `entries`. Far above `entries` means a bloated shard `shrink_to_fit` would reclaim.
+    pub capacity: usize,
     /// Read operations (0 when metrics disabled).
     pub reads: u64,
     /// Write operations (0 when metrics disabled).
@@ -153,6 +203,10 @@ pub struct ShardDiagnostics {
     pub lock_acquisitions: u64,
     /// Cumulative lock wait time in nanoseconds (0 when lock-timing disabled).
     pub lock_wait_nanos: u64,
+    /// `get` hits (0 when metrics disabled).
+    pub get_hits: u64,
+    /// `get` misses (0 when metrics disabled).
+    pub get_misses: u64,
 }
 
 /// Structured snapshot for performance introspection.
@@ -169,3 +223,149 @@ pub struct Diagnostics {
     /// Max load / avg load ratio. User interprets (e.g. threshold 2.0 for imbalance).
     pub max_load_ratio: f64,
 }
+
+impl Diagnostics {
+    /// Fraction of `get` calls that found their key, across all shards.
+    ///
+    /// Returns `None` if no `get` was recorded (always the case without the `metrics` feature).
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let hits: u64 = self.shards.iter().map(|s| s.get_hits).sum();
+        let misses: u64 = self.shards.iter().map(|s| s.get_misses).sum();
+        let lookups = hits + misses;
+        if lookups == 0 {
+            None
+        } else {
+            Some(hits as f64 / lookups as f64)
+        }
+    }
+}
+
+/// Estimated hash-table probe behaviour of one shard, from
+/// [`ShardMap::probe_stats`](crate::ShardMap::probe_stats).
+///
+/// Nothing here is measured: the probe length is derived from the load factor alone. Use it
+/// to spot shards that are unusually full, not as a benchmark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeStats {
+    /// Number of entries in this shard.
+    pub len: usize,
+    /// Entries the shard's table can hold before it next grows.
+    pub capacity: usize,
+    /// `len / capacity`, in `[0, 1]` (0 for an unallocated table).
+    pub load_factor: f64,
+    /// Estimated average number of probe groups a successful lookup visits (at least 1).
+    ///
+    /// Uses the linear-probing estimate `(1 + 1 / (1 - a)) / 2`, with `a` the fraction of
+    /// buckets in use. The table keeps one bucket in eight free, so `a` is `load_factor * 7/8`
+    /// and the estimate stays finite (at most 4.5). SwissTable probes whole groups of buckets
+    /// at a time, so real probe counts are usually lower.
+    pub estimated_probe_length: f64,
+}
+
+impl ProbeStats {
+    pub(crate) fn estimate(len: usize, capacity: usize) -> Self {
+        let load_factor = if capacity == 0 {
+            0.0
+        } else {
+            (len as f64 / capacity as f64).min(1.0)
+        };
+        let occupied = load_factor * 7.0 / 8.0;
+        Self {
+            len,
+            capacity,
+            load_factor,
+            estimated_probe_length: (1.0 + 1.0 / (1.0 - occupied)) / 2.0,
+        }
+    }
+}
+
+/// Per-shard loads and operation counters at one instant, from
+/// [`ShardMap::diagnostics_snapshot`](crate::ShardMap::diagnostics_snapshot). Compare two
+/// with [`delta`](Self::delta).
+#[derive(Debug, Clone)]
+pub struct DiagnosticsSnapshot {
+    /// When the snapshot was taken.
+    pub taken_at: Instant,
+    /// Number of entries in each shard.
+    pub loads: Vec<usize>,
+    /// Operation counts for each shard (all zero without the `metrics` feature).
+    pub operations: Vec<ShardOps>,
+}
+
+impl DiagnosticsSnapshot {
+    /// What changed between `earlier` and this snapshot.
+    ///
+    /// Operation counters only grow, so their deltas saturate at zero if `earlier` is
+    /// actually the later snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the snapshots have different shard counts.
+    pub fn delta(&self, earlier: &DiagnosticsSnapshot) -> DiagnosticsDelta {
+        assert_eq!(
+            self.loads.len(),
+            earlier.loads.len(),
+            "snapshots have different shard counts"
+        );
+        DiagnosticsDelta {
+            elapsed: self.taken_at.saturating_duration_since(earlier.taken_at),
+            load_changes: self
+                .loads
+                .iter()
+                .zip(&earlier.loads)
+                .map(|(&now, &then)| now as i64 - then as i64)
+                .collect(),
+            operations: self
+                .operations
+                .iter()
+                .zip(&earlier.operations)
+                .map(|(now, then)| ShardOps {
+                    reads: now.reads.saturating_sub(then.reads),
+                    writes: now.writes.saturating_sub(then.writes),
+                    removes: now.removes.saturating_sub(then.removes),
+                    lock_acquisitions: now.lock_acquisitions.saturating_sub(then.lock_acquisitions),
+                    lock_wait_nanos: now.lock_wait_nanos.saturating_sub(then.lock_wait_nanos),
+                    get_hits: now.get_hits.saturating_sub(then.get_hits),
+                    get_misses: now.get_misses.saturating_sub(then.get_misses),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// What happened between two [`DiagnosticsSnapshot`]s.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsDelta {
+    /// Time between the two snapshots.
+    pub elapsed: Duration,
+    /// Change in entry count for each shard (negative for shards that shrank).
+    pub load_changes: Vec<i64>,
+    /// Operations performed on each shard (all zero without the `metrics` feature).
+    pub operations: Vec<ShardOps>,
+}
+
+impl DiagnosticsDelta {
+    /// Net change in total entries.
+    pub fn entry_change(&self) -> i64 {
+        self.load_changes.iter().sum()
+    }
+
+    /// Per-shard change in entries per second. Infinite or NaN if `elapsed` is zero.
+    pub fn load_rates(&self) -> Vec<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        self.load_changes
+            .iter()
+            .map(|&change| change as f64 / secs)
+            .collect()
+    }
+
+    /// Reads, writes and removes per second across all shards.
+    pub fn ops_per_sec(&self) -> f64 {
+        let ops: u64 = self
+            .operations
+            .iter()
+            .map(|o| o.reads + o.writes + o.removes)
+            .sum();
+        ops as f64 / self.elapsed.as_secs_f64()
+    }
+}
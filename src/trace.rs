@@ -0,0 +1,54 @@
+//! Per-shard recent-operation tracing (`op-trace` feature).
+//!
+//! Each shard keeps a small ring buffer of its last [`TRACE_CAPACITY`] operations as
+//! `(OpKind, key hash)`. Recording takes a per-shard mutex on every traced operation, so this
+//! is a debugging aid, not something to leave on in hot production paths. Memory cost is about
+//! `TRACE_CAPACITY * 16` bytes per shard.
+
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+
+/// Number of operations each shard remembers.
+pub const TRACE_CAPACITY: usize = 64;
+
+/// Kind of a traced operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    /// `get`, `get_by_hash`.
+    Get,
+    /// `insert`, `insert_by_hash`, `get_or_insert*`, `try_insert`, `entry`.
+    Insert,
+    /// `remove`, `remove_by_hash`.
+    Remove,
+    /// `update`.
+    Update,
+}
+
+/// Bounded ring buffer of recent operations for one shard.
+pub(crate) struct OpTrace {
+    ops: Mutex<VecDeque<(OpKind, u64)>>,
+}
+
+impl OpTrace {
+    pub fn new() -> Self {
+        Self {
+            ops: Mutex::new(VecDeque::with_capacity(TRACE_CAPACITY)),
+        }
+    }
+
+    #[inline]
+    pub fn record(&self, kind: OpKind, key_hash: u64) {
+        let mut ops = self.ops.lock();
+        if ops.len() == TRACE_CAPACITY {
+            ops.pop_front();
+        }
+        ops.push_back((kind, key_hash));
+    }
+
+    /// The last `limit` operations, oldest first.
+    pub fn recent(&self, limit: usize) -> Vec<(OpKind, u64)> {
+        let ops = self.ops.lock();
+        let skip = ops.len().saturating_sub(limit);
+        ops.iter().skip(skip).copied().collect()
+    }
+}
@@ -0,0 +1,74 @@
+use crate::shard::Table;
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Per-key change subscriptions for one shard.
+///
+/// Every write to the shard calls [`notify`](Self::notify) before releasing its lock, which
+/// compares each watched key's stored `Arc` with the last one published (by pointer) and
+/// publishes on change. The `active` flag keeps that to one atomic load while nobody watches.
+pub(crate) struct KeyWatchers<K, V> {
+    active: AtomicBool,
+    senders: Mutex<HashMap<K, watch::Sender<Option<Arc<V>>>>>,
+}
+
+impl<K, V> KeyWatchers<K, V>
+where
+    K: Hash + Eq,
+{
+    pub fn new() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            senders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Watch `key`, whose value is currently `current`. Call with the shard lock held so no
+    /// write can slip in between reading `current` and registering.
+    pub fn subscribe(&self, key: K, current: Option<Arc<V>>) -> watch::Receiver<Option<Arc<V>>> {
+        let mut senders = self.senders.lock();
+        let receiver = match senders.entry(key) {
+            hashbrown::hash_map::Entry::Occupied(entry) => entry.get().subscribe(),
+            hashbrown::hash_map::Entry::Vacant(entry) => {
+                let (tx, rx) = watch::channel(current);
+                entry.insert(tx);
+                rx
+            }
+        };
+        self.active.store(true, Ordering::Release);
+        receiver
+    }
+
+    /// Publish watched keys whose value changed in `map`, and drop watches nobody listens to.
+    pub fn notify(&self, map: &Table<K, V>) {
+        if !self.active.load(Ordering::Acquire) {
+            return;
+        }
+        let mut senders = self.senders.lock();
+        senders.retain(|key, tx| {
+            if tx.is_closed() {
+                return false;
+            }
+            let current = map.get(key).map(|slot| &**slot);
+            tx.send_if_modified(|published| {
+                let unchanged = match (published.as_ref(), current) {
+                    (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                    (None, None) => true,
+                    _ => false,
+                };
+                if !unchanged {
+                    *published = current.cloned();
+                }
+                !unchanged
+            });
+            true
+        });
+        if senders.is_empty() {
+            self.active.store(false, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Write-through hooks for mirroring map writes to a backing store.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// A backing store that mirrors writes made through a [`ShardMap`](crate::ShardMap).
+///
+/// Register one with [`ShardMapBuilder::write_through`](crate::ShardMapBuilder::write_through).
+/// The in-memory map is always updated first; the store is told afterwards.
+pub trait WriteThrough<K, V>: Send + Sync {
+    /// Called after `key` was inserted or its value replaced or changed, with the new value.
+    fn on_insert(&self, key: &K, value: &V);
+
+    /// Called after `key` was removed, including eviction from a bounded shard.
+    fn on_remove(&self, key: &K);
+}
+
+/// When a [`WriteThrough`] store is called relative to the shard's write lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteThroughMode {
+    /// Call the store after the shard lock is released, so a slow store doesn't block other
+    /// operations on the shard. Concurrent writes to the same key may reach the store in a
+    /// different order than they were applied to the map.
+    #[default]
+    AfterLock,
+    /// Call the store while the shard's write lock is held. The store sees writes to a
+    /// shard in exactly the order they were applied, but it blocks that shard meanwhile and
+    /// must not call back into the map.
+    InLock,
+}
+
+/// A registered store plus what the map needs to call it.
+pub(crate) struct WriteThroughHook<K, V> {
+    pub(crate) store: Arc<dyn WriteThrough<K, V>>,
+    pub(crate) mode: WriteThroughMode,
+    /// `K::clone`, captured where `K: Clone` is known, for reporting writes after the lock.
+    pub(crate) clone_key: fn(&K) -> K,
+}
+
+impl<K, V> Clone for WriteThroughHook<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            store: Arc::clone(&self.store),
+            mode: self.mode,
+            clone_key: self.clone_key,
+        }
+    }
+}
+
+/// A write queued under a shard's lock, reported once the lock is released.
+pub(crate) enum Pending<K, V> {
+    Insert(K, Arc<V>),
+    Remove(K),
+}
+
+/// A shard's link to the map's write-through store, if it has one.
+///
+/// Every write path in the shard reports through [`inserted`](Self::inserted) and
+/// [`removed`](Self::removed) with the write lock held. With `InLock` the store is called
+/// right there; with `AfterLock` the write is queued, and the write guard hands the queue to
+/// [`report`](Self::report) once it has released the lock.
+pub(crate) struct Mirror<K, V> {
+    hook: Option<WriteThroughHook<K, V>>,
+    /// Writes queued in `AfterLock` mode. Only pushed to and drained under the shard's write
+    /// lock, so a drained batch holds exactly one lock holder's writes.
+    pending: Mutex<Vec<Pending<K, V>>>,
+}
+
+impl<K, V> Mirror<K, V> {
+    pub(crate) fn new(hook: Option<WriteThroughHook<K, V>>) -> Self {
+        Self {
+            hook,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn hook(&self) -> Option<&WriteThroughHook<K, V>> {
+        self.hook.as_ref()
+    }
+
+    /// Whether writes are reported at all.
+    #[inline]
+    pub(crate) fn is_active(&self) -> bool {
+        self.hook.is_some()
+    }
+
+    /// Report that `key` now holds `value`.
+    #[inline]
+    pub(crate) fn inserted(&self, key: &K, value: &Arc<V>) {
+        match &self.hook {
+            None => {}
+            Some(hook) if hook.mode == WriteThroughMode::InLock => hook.store.on_insert(key, value),
+            Some(hook) => self
+                .pending
+                .lock()
+                .push(Pending::Insert((hook.clone_key)(key), Arc::clone(value))),
+        }
+    }
+
+    /// Report that `key` was removed.
+    #[inline]
+    pub(crate) fn removed(&self, key: &K) {
+        match &self.hook {
+            None => {}
+            Some(hook) if hook.mode == WriteThroughMode::InLock => hook.store.on_remove(key),
+            Some(hook) => self
+                .pending
+                .lock()
+                .push(Pending::Remove((hook.clone_key)(key))),
+        }
+    }
+
+    /// Drain the queued writes. Call with the write lock still held.
+    #[inline]
+    pub(crate) fn take_pending(&self) -> Vec<Pending<K, V>> {
+        match &self.hook {
+            Some(hook) if hook.mode == WriteThroughMode::AfterLock => {
+                std::mem::take(&mut *self.pending.lock())
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Call the store for writes drained by [`take_pending`](Self::take_pending), in the
+    /// order they were made.
+    pub(crate) fn report(&self, pending: Vec<Pending<K, V>>) {
+        let Some(hook) = &self.hook else {
+            return;
+        };
+        for write in pending {
+            match write {
+                Pending::Insert(key, value) => hook.store.on_insert(&key, &value),
+                Pending::Remove(key) => hook.store.on_remove(&key),
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use shardmap::{DefaultRouter, Error, RoutingConfig, ShardMap, ShardMapBuilder, ShardRouter};
+use shardmap::{
+    DefaultRouter, Error, OnFull, RoutingConfig, ShardMap, ShardMapBuilder, ShardRouter,
+};
+use std::sync::Arc;
 
 #[test]
 fn test_basic_insert_get() {
@@ -47,33 +50,21 @@ fn test_rename_same_shard() {
 
 #[test]
 fn test_rename_cross_shard() {
-    // With 2 shards, use shard_for_key to pick two keys in different shards, then rename.
+    // With 2 shards, pick two keys in different shards, then rename one to the other.
     let map = ShardMapBuilder::new()
         .shard_count(2)
         .unwrap()
         .build::<String, &str>()
         .unwrap();
-    let mut a = None;
-    let mut b = None;
-    for s in ["x", "y", "key_0", "key_1", "key_1000"] {
-        let k = s.to_string();
-        map.insert(k.clone(), "v");
-        let shard = map.shard_for_key(&k);
-        if a.is_none() {
-            a = Some((k, shard));
-        } else if let Some((_, sa)) = a {
-            if shard != sa {
-                b = Some((k, shard));
-                break;
-            }
-        }
-    }
-    if let (Some((old, _)), Some((new, _))) = (a, b) {
-        map.remove(&new);
-        map.rename(&old, new.clone()).unwrap();
-        assert!(map.get(&old).is_none());
-        assert_eq!(*map.get(&new).unwrap(), "v");
-    }
+    let keys = map
+        .pick_keys_in_distinct_shards(2, |i| format!("key_{}", i))
+        .unwrap();
+    let (old, new) = (keys[0].clone(), keys[1].clone());
+    assert_ne!(map.shard_for_key(&old), map.shard_for_key(&new));
+    map.insert(old.clone(), "v");
+    map.rename(&old, new.clone()).unwrap();
+    assert!(map.get(&old).is_none());
+    assert_eq!(*map.get(&new).unwrap(), "v");
 }
 
 #[test]
@@ -155,6 +146,18 @@ fn test_diagnostics() {
     assert_eq!(diag.shards.len(), 16);
     assert!(diag.max_load_ratio >= 1.0);
     assert!(diag.avg_load_per_shard >= 0.0);
+    for shard in &diag.shards {
+        assert!(shard.capacity >= shard.entries);
+    }
+
+    let map: ShardMap<u32, u32> = ShardMap::with_capacity_and_shards(1024, 4).unwrap();
+    map.insert(1, 1);
+    let diag = map.diagnostics();
+    assert_eq!(diag.shards.len(), 4);
+    for shard in &diag.shards {
+        assert!(shard.capacity >= 256);
+        assert!(shard.capacity >= shard.entries);
+    }
 }
 
 #[test]
@@ -278,3 +281,3187 @@ fn test_default_router() {
         DefaultRouter.route(map.hash_for_key(&"k"), 8)
     );
 }
+
+#[test]
+fn test_routing_value_and_routed_index() {
+    let map = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .build::<String, i32>()
+        .unwrap();
+    for i in 0..100 {
+        let k = format!("key_{}", i);
+        assert_eq!(map.routing_value(&k), map.hash_for_key(&k));
+        assert_eq!(
+            map.routed_index(map.routing_value(&k)),
+            map.shard_for_key(&k)
+        );
+    }
+
+    struct AllToLast;
+    impl ShardRouter for AllToLast {
+        fn route(&self, _key_hash: u64, shard_count: usize) -> usize {
+            shard_count - 1
+        }
+    }
+    let custom = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .routing(RoutingConfig::Custom(Box::new(AllToLast)))
+        .build::<&str, i32>()
+        .unwrap();
+    let v = custom.routing_value(&"k");
+    assert_eq!(custom.routed_index(v), 3);
+    assert_eq!(custom.routed_index(v), custom.shard_for_key(&"k"));
+}
+
+#[test]
+fn test_get_or_insert_many() {
+    let map = ShardMap::new();
+    map.insert("a".to_string(), 1);
+    map.insert("c".to_string(), 3);
+
+    let mut made = Vec::new();
+    let keys: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
+    let values = map.get_or_insert_many(keys, |k| {
+        made.push(k.clone());
+        100
+    });
+
+    assert_eq!(
+        values.iter().map(|v| **v).collect::<Vec<_>>(),
+        vec![1, 100, 3, 100]
+    );
+    made.sort();
+    assert_eq!(made, vec!["b".to_string(), "d".to_string()]);
+    assert_eq!(map.len(), 4);
+    assert_eq!(*map.get(&"d".to_string()).unwrap(), 100);
+}
+
+#[test]
+fn test_rebuild() {
+    let mut map = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .build::<String, i32>()
+        .unwrap();
+    for i in 0..50 {
+        map.insert(format!("old_{}", i), i);
+    }
+
+    let fresh: Vec<(String, usize)> = (0..50)
+        .map(|i| {
+            let k = format!("new_{}", i);
+            let shard = map.shard_for_key(&k);
+            (k, shard)
+        })
+        .collect();
+
+    map.rebuild(|idx, entries| {
+        if idx % 2 == 1 {
+            entries.clear();
+        } else {
+            for (k, shard) in &fresh {
+                if *shard == idx {
+                    entries.insert(k.clone(), Arc::new(-1));
+                }
+            }
+        }
+    });
+
+    let loads = map.shard_loads();
+    assert_eq!(loads[1], 0);
+    assert_eq!(loads[3], 0);
+    for (k, shard) in &fresh {
+        if shard.is_multiple_of(2) {
+            assert_eq!(*map.get(k).unwrap(), -1);
+        } else {
+            assert!(map.get(k).is_none());
+        }
+    }
+    for i in 0..50 {
+        let k = format!("old_{}", i);
+        let present = map.get(&k).is_some();
+        assert_eq!(present, map.shard_for_key(&k).is_multiple_of(2));
+    }
+}
+
+#[test]
+fn test_debug_routing() {
+    let map = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .build::<String, i32>()
+        .unwrap();
+    for i in 0..40 {
+        map.insert(format!("key_{}", i), i);
+    }
+    let dump = map.debug_routing();
+    assert_eq!(dump.len(), 40);
+    for (k, hash, shard) in &dump {
+        assert_eq!(*hash, map.hash_for_key(k));
+        assert_eq!(*shard, map.shard_for_key(k));
+    }
+}
+
+#[test]
+fn test_freeze_reads() {
+    let map = Arc::new(ShardMap::new());
+    for i in 0..100 {
+        map.insert(i, i * 2);
+    }
+    map.freeze_reads();
+    assert!(map.reads_frozen());
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let map = Arc::clone(&map);
+            std::thread::spawn(move || {
+                for i in 0..100 {
+                    assert_eq!(*map.get(&i).unwrap(), i * 2);
+                    assert!(map.contains_key(&i));
+                }
+                assert!(map.get(&1000).is_none());
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    // SAFETY: all reader threads have been joined.
+    unsafe { map.unfreeze_reads() };
+    assert!(!map.reads_frozen());
+    map.insert(1000, 0);
+    assert_eq!(*map.get(&1000).unwrap(), 0);
+}
+
+#[test]
+#[should_panic(expected = "reads are frozen")]
+fn test_write_while_frozen_panics() {
+    let map = ShardMap::new();
+    map.insert("k", 1);
+    map.freeze_reads();
+    map.insert("k", 2);
+}
+
+#[test]
+fn test_partition() {
+    let map = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .build::<u32, u32>()
+        .unwrap();
+    for i in 0..100 {
+        map.insert(i, i);
+    }
+    let (even, odd) = map.partition(|_, v| v % 2 == 0);
+    assert_eq!(even.len(), 50);
+    assert_eq!(odd.len(), 50);
+    assert_eq!(even.shard_loads().len(), 8);
+    for i in 0..100 {
+        if i % 2 == 0 {
+            assert_eq!(*even.get(&i).unwrap(), i);
+            assert!(odd.get(&i).is_none());
+            assert!(Arc::ptr_eq(&even.get(&i).unwrap(), &map.get(&i).unwrap()));
+        } else {
+            assert_eq!(*odd.get(&i).unwrap(), i);
+            assert!(even.get(&i).is_none());
+        }
+    }
+    assert_eq!(map.len(), 100);
+}
+
+#[test]
+fn test_retain_collecting() {
+    let map = ShardMap::new();
+    for i in 0..100u32 {
+        map.insert(i, i);
+    }
+    let mut removed: Vec<u32> = map
+        .retain_collecting(|k, _| k % 2 == 0)
+        .into_iter()
+        .map(|(k, v)| {
+            assert_eq!(k, *v);
+            k
+        })
+        .collect();
+    removed.sort();
+    assert_eq!(removed, (0..100).filter(|i| i % 2 == 1).collect::<Vec<_>>());
+    assert_eq!(map.len(), 50);
+    for i in 0..100u32 {
+        assert_eq!(map.contains_key(&i), i % 2 == 0);
+    }
+}
+
+#[test]
+fn test_with_many_mut_transfer() {
+    let map = ShardMap::new();
+    map.insert("alice".to_string(), 100i64);
+    map.insert("bob".to_string(), 50i64);
+    let alice = "alice".to_string();
+    let bob = "bob".to_string();
+    let missing = "carol".to_string();
+
+    let moved = map.with_many_mut(&[&alice, &bob, &missing], |slots| {
+        assert!(slots[2].is_none());
+        let amount = 40;
+        **slots[0].as_mut().unwrap() -= amount;
+        **slots[1].as_mut().unwrap() += amount;
+        amount
+    });
+
+    assert_eq!(moved, 40);
+    assert_eq!(*map.get(&alice).unwrap(), 60);
+    assert_eq!(*map.get(&bob).unwrap(), 90);
+}
+
+#[test]
+#[should_panic(expected = "duplicate key")]
+fn test_with_many_mut_rejects_duplicates() {
+    let map = ShardMap::new();
+    map.insert("k", 1);
+    map.with_many_mut(&[&"k", &"k"], |_| ());
+}
+
+#[test]
+fn test_fixed_seed() {
+    let build = |seed| {
+        ShardMapBuilder::new()
+            .fixed_seed(seed)
+            .build::<&str, i32>()
+            .unwrap()
+    };
+    let a = build(42);
+    let b = build(42);
+    let c = build(43);
+    assert_eq!(a.hash_for_key(&"user:1"), b.hash_for_key(&"user:1"));
+    assert_eq!(a.shard_for_key(&"user:1"), b.shard_for_key(&"user:1"));
+    assert_ne!(a.hash_for_key(&"user:1"), c.hash_for_key(&"user:1"));
+
+    // Golden value: must not change across runs or machines for the same seed.
+    #[cfg(all(target_pointer_width = "64", not(target_feature = "aes")))]
+    assert_eq!(a.hash_for_key(&"user:1"), 5236213121823107973);
+}
+
+#[cfg(feature = "op-trace")]
+#[test]
+fn test_recent_ops_trace() {
+    use shardmap::OpKind;
+
+    let map = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .build::<&str, i32>()
+        .unwrap();
+    map.insert("a", 1);
+    map.get(&"a");
+    map.update(&"a", |v| *v += 1);
+    map.remove(&"a");
+
+    let h = map.hash_for_key(&"a");
+    assert_eq!(
+        map.recent_ops(0, 10),
+        vec![
+            (OpKind::Insert, h),
+            (OpKind::Get, h),
+            (OpKind::Update, h),
+            (OpKind::Remove, h),
+        ]
+    );
+    assert_eq!(map.recent_ops(0, 1), vec![(OpKind::Remove, h)]);
+
+    for i in 0..(shardmap::trace::TRACE_CAPACITY as i32 + 10) {
+        map.insert("b", i);
+    }
+    assert_eq!(
+        map.recent_ops(0, usize::MAX).len(),
+        shardmap::trace::TRACE_CAPACITY
+    );
+}
+
+#[test]
+fn test_contains_value() {
+    let map = ShardMap::new();
+    for i in 0..20 {
+        map.insert(i, format!("v{}", i));
+    }
+    assert!(map.contains_value(&"v7".to_string()));
+    assert!(!map.contains_value(&"v20".to_string()));
+}
+
+#[test]
+fn test_find_keys_by_value() {
+    let map = ShardMap::new();
+    map.insert("a", 1);
+    map.insert("b", 2);
+    map.insert("c", 2);
+    map.insert("d", 2);
+
+    assert_eq!(map.find_key_by_value(&1), Some("a"));
+    assert_eq!(map.find_keys_by_value(&1), vec!["a"]);
+
+    let mut twos = map.find_keys_by_value(&2);
+    twos.sort();
+    assert_eq!(twos, vec!["b", "c", "d"]);
+    assert!(twos.contains(&map.find_key_by_value(&2).unwrap()));
+
+    assert_eq!(map.find_key_by_value(&3), None);
+    assert!(map.find_keys_by_value(&3).is_empty());
+}
+
+#[test]
+fn test_bounded_reject() {
+    let map = ShardMapBuilder::new()
+        .shard_count(2)
+        .unwrap()
+        .max_entries_per_shard(3)
+        .on_full(OnFull::Reject)
+        .build::<u32, u32>()
+        .unwrap();
+
+    let mut accepted = 0;
+    let mut rejected = 0;
+    for i in 0..20 {
+        match map.checked_insert(i, i) {
+            Ok(None) => accepted += 1,
+            Err(Error::ShardFull) => rejected += 1,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert_eq!(accepted, 6);
+    assert_eq!(rejected, 14);
+    assert_eq!(map.shard_loads(), vec![3, 3]);
+
+    // Overwriting an existing key is always allowed.
+    let kept = (0..20).find(|k| map.contains_key(k)).unwrap();
+    assert_eq!(*map.checked_insert(kept, 100).unwrap().unwrap(), kept);
+
+    assert!(ShardMapBuilder::new()
+        .max_entries_per_shard(0)
+        .build::<u32, u32>()
+        .is_err());
+}
+
+#[test]
+fn test_bounded_reject_only_refuses_checked_inserts() {
+    let map = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(1)
+        .on_full(OnFull::Reject)
+        .build::<u32, u32>()
+        .unwrap();
+    map.insert(1, 1);
+    assert_eq!(map.checked_insert(2, 2), Err(Error::ShardFull));
+    // Inserts without a `Result` evict the oldest entry instead of panicking.
+    map.insert(2, 2);
+    assert_eq!(*map.get_or_insert(3, 3), 3);
+    assert!(map.try_insert(4, 4).is_ok());
+    let keys: Vec<u32> = map.iter_snapshot().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![4]);
+}
+
+#[test]
+fn test_bounded_defaults_to_evict_oldest() {
+    let map = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(2)
+        .build::<u32, u32>()
+        .unwrap();
+    for i in 0..3 {
+        map.insert(i, i);
+    }
+    assert_eq!(map.checked_insert(3, 3), Ok(None));
+    let mut keys: Vec<u32> = map.iter_snapshot().map(|(k, _)| k).collect();
+    keys.sort();
+    assert_eq!(keys, vec![2, 3]);
+}
+
+#[test]
+fn test_bounded_evict_oldest() {
+    let map = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(3)
+        .on_full(OnFull::EvictOldest)
+        .build::<u32, u32>()
+        .unwrap();
+
+    for i in 0..3 {
+        assert_eq!(map.insert_evicting(i, i), (None, None));
+    }
+    // Overwrites and reads don't change insertion order.
+    map.insert(0, 100);
+    map.get(&0);
+
+    let (_, evicted) = map.insert_evicting(3, 3);
+    let (k, v) = evicted.unwrap();
+    assert_eq!((k, *v), (0, 100));
+
+    map.insert(4, 4);
+    map.insert(5, 5);
+    assert_eq!(map.len(), 3);
+    let mut keys: Vec<u32> = map.iter_snapshot().map(|(k, _)| k).collect();
+    keys.sort();
+    assert_eq!(keys, vec![3, 4, 5]);
+
+    // A removed key frees its slot and is no longer in the eviction order.
+    map.remove(&3);
+    assert_eq!(map.insert_evicting(6, 6), (None, None));
+    let (_, evicted) = map.insert_evicting(7, 7);
+    assert_eq!(evicted.unwrap().0, 4);
+}
+
+#[test]
+fn test_bounded_evict_oldest_many_shards() {
+    let map = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .max_entries_per_shard(10)
+        .on_full(OnFull::EvictOldest)
+        .build::<u32, u32>()
+        .unwrap();
+    for i in 0..1000 {
+        map.insert(i, i);
+        assert!(map.shard_loads().iter().all(|&n| n <= 10));
+    }
+    assert_eq!(map.len(), 40);
+    // Survivors are the newest keys of each shard.
+    for (k, _) in map.iter_snapshot() {
+        let shard = map.shard_for_key(&k);
+        let newer = (k + 1..1000)
+            .filter(|n| map.shard_for_key(n) == shard)
+            .count();
+        assert!(newer < 10);
+    }
+}
+
+#[test]
+fn test_bounded_evict_oldest_colliding_hashes() {
+    // Every key hashes the same, so eviction order must tell them apart by entry.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Colliding(u32);
+    impl std::hash::Hash for Colliding {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            0u8.hash(state);
+        }
+    }
+
+    let map = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(3)
+        .on_full(OnFull::EvictOldest)
+        .build::<Colliding, u32>()
+        .unwrap();
+    for i in 0..3 {
+        map.insert(Colliding(i), i);
+    }
+    let (_, evicted) = map.insert_evicting(Colliding(3), 3);
+    assert_eq!(evicted.unwrap().0, Colliding(0));
+
+    // Removing one key leaves the others it collides with in the order.
+    map.remove(&Colliding(2));
+    assert_eq!(map.insert_evicting(Colliding(4), 4), (None, None));
+    let (_, evicted) = map.insert_evicting(Colliding(5), 5);
+    assert_eq!(evicted.unwrap().0, Colliding(1));
+    let (_, evicted) = map.insert_evicting(Colliding(6), 6);
+    assert_eq!(evicted.unwrap().0, Colliding(3));
+    assert_eq!(map.len(), 3);
+}
+
+#[cfg(feature = "lru")]
+#[test]
+fn test_bounded_lru() {
+    let map = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(3)
+        .on_full(OnFull::EvictLeastRecent)
+        .build::<u32, u32>()
+        .unwrap();
+    map.insert(0, 0);
+    map.insert(1, 1);
+    map.insert(2, 2);
+
+    // Touch 0 so 1 becomes least recently used.
+    map.get(&0);
+    let (_, evicted) = map.insert_evicting(3, 3);
+    assert_eq!(evicted.unwrap().0, 1);
+
+    // Keep reading 0 while inserting; it survives, unaccessed entries go first.
+    for i in 4..10 {
+        map.get(&0);
+        map.insert(i, i);
+        assert!(map.contains_key(&0));
+    }
+    let mut keys: Vec<u32> = map.iter_snapshot().map(|(k, _)| k).collect();
+    keys.sort();
+    assert_eq!(keys, vec![0, 8, 9]);
+
+    // Recency is now 8, 0, 9. Overwriting 8 counts as use, so 0 goes next.
+    map.insert(8, 80);
+    let (_, evicted) = map.insert_evicting(10, 10);
+    assert_eq!(evicted.unwrap().0, 0);
+}
+
+#[cfg(feature = "watch")]
+#[tokio::test]
+async fn test_watch_loads() {
+    use std::time::Duration;
+
+    let map = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .build::<u32, u32>()
+        .unwrap();
+    let mut rx = map.watch_loads(Duration::from_millis(5));
+    assert_eq!(rx.borrow_and_update().iter().sum::<usize>(), 0);
+
+    map.insert(1, 1);
+    rx.changed().await.unwrap();
+    assert_eq!(rx.borrow_and_update().iter().sum::<usize>(), 1);
+
+    map.insert(2, 2);
+    map.insert(3, 3);
+    rx.changed().await.unwrap();
+    let loads = rx.borrow_and_update().clone();
+    assert_eq!(loads.len(), 4);
+    assert_eq!(loads, map.shard_loads());
+
+    // Dropping the map stops the sampler and closes the channel.
+    drop(map);
+    assert!(rx.changed().await.is_err());
+}
+
+#[cfg(feature = "watch")]
+#[tokio::test]
+async fn test_watch_key() {
+    let map: ShardMap<String, u32> = ShardMap::with_shard_count(4).unwrap();
+    let key = "balance".to_string();
+    map.insert(key.clone(), 10);
+
+    let mut rx = map.watch_key(&key);
+    assert_eq!(rx.borrow_and_update().as_deref(), Some(&10));
+
+    map.insert(key.clone(), 20);
+    rx.changed().await.unwrap();
+    assert_eq!(rx.borrow_and_update().as_deref(), Some(&20));
+
+    // Writes to other keys, even on the same shard, don't wake the watcher.
+    for i in 0..50 {
+        map.insert(format!("other-{i}"), i);
+    }
+    assert!(!rx.has_changed().unwrap());
+
+    map.update(&key, |v| *v += 1);
+    rx.changed().await.unwrap();
+    assert_eq!(rx.borrow_and_update().as_deref(), Some(&21));
+
+    map.remove(&key);
+    rx.changed().await.unwrap();
+    assert!(rx.borrow_and_update().is_none());
+
+    // Bulk writes notify too.
+    map.overwrite_many([(key.clone(), 5)]);
+    rx.changed().await.unwrap();
+    assert_eq!(rx.borrow_and_update().as_deref(), Some(&5));
+    map.clear();
+    rx.changed().await.unwrap();
+    assert!(rx.borrow_and_update().is_none());
+}
+
+#[test]
+fn test_spawn_sibling() {
+    struct Reverse;
+    impl ShardRouter for Reverse {
+        fn route(&self, hash: u64, shard_count: usize) -> usize {
+            shard_count - 1 - (hash as usize % shard_count)
+        }
+    }
+
+    let map: ShardMap<String, u32> = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .fixed_seed(7)
+        .routing(RoutingConfig::Custom(Box::new(Reverse)))
+        .build()
+        .unwrap();
+    map.insert("a".to_string(), 1);
+
+    let sibling = map.spawn_sibling();
+    assert_eq!(sibling.shard_count(), map.shard_count());
+    assert!(sibling.is_empty());
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        assert_eq!(sibling.shard_for_key(&key), map.shard_for_key(&key));
+    }
+}
+
+#[test]
+fn test_get_cloned_or() {
+    let map: ShardMap<&str, String> = ShardMap::new();
+    map.insert("name", "shardmap".to_string());
+
+    assert_eq!(
+        map.get_cloned_or(&"name", "default".to_string()),
+        "shardmap"
+    );
+    assert_eq!(
+        map.get_cloned_or(&"missing", "default".to_string()),
+        "default"
+    );
+
+    let mut calls = 0;
+    let present = map.get_cloned_or_else(&"name", || {
+        calls += 1;
+        String::new()
+    });
+    assert_eq!(present, "shardmap");
+    assert_eq!(calls, 0);
+    let absent = map.get_cloned_or_else(&"missing", || {
+        calls += 1;
+        "lazy".to_string()
+    });
+    assert_eq!(absent, "lazy");
+    assert_eq!(calls, 1);
+    assert!(!map.contains_key(&"missing"));
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_access_distribution_reveals_hot_shard() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    for i in 0..64 {
+        map.insert(i, i);
+    }
+    let hot = map.shard_for_key(&0);
+    for _ in 0..1000 {
+        map.get(&0);
+    }
+    map.get(&9999); // misses count too
+    map.remove(&0);
+
+    let touches = map.access_distribution();
+    assert_eq!(touches.len(), map.shard_count());
+    assert_eq!(touches.iter().sum::<u64>(), 64 + 1000 + 1 + 1);
+    let hottest = (0..touches.len()).max_by_key(|&i| touches[i]).unwrap();
+    assert_eq!(hottest, hot);
+    let others: u64 = touches
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != hot)
+        .map(|(_, &t)| t)
+        .sum();
+    assert!(touches[hot] > others);
+}
+
+#[test]
+fn test_feed_loads_to_router() {
+    use shardmap::Rebalancer;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct LoadAware {
+        last_loads: Mutex<Vec<usize>>,
+    }
+    impl ShardRouter for LoadAware {
+        fn route(&self, key_hash: u64, shard_count: usize) -> usize {
+            (key_hash as usize) % shard_count
+        }
+        fn as_rebalancer(&self) -> Option<&dyn Rebalancer> {
+            Some(self)
+        }
+    }
+    impl Rebalancer for LoadAware {
+        fn advise(&self, loads: &[usize]) {
+            *self.last_loads.lock().unwrap() = loads.to_vec();
+        }
+    }
+
+    struct Shared(Arc<LoadAware>);
+    impl ShardRouter for Shared {
+        fn route(&self, key_hash: u64, shard_count: usize) -> usize {
+            self.0.route(key_hash, shard_count)
+        }
+        fn as_rebalancer(&self) -> Option<&dyn Rebalancer> {
+            self.0.as_rebalancer()
+        }
+    }
+
+    let router = Arc::new(LoadAware::default());
+    let map: ShardMap<u32, u32> = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .routing(RoutingConfig::Custom(Box::new(Shared(router.clone()))))
+        .build()
+        .unwrap();
+    for i in 0..20 {
+        map.insert(i, i);
+    }
+    assert!(router.last_loads.lock().unwrap().is_empty());
+    assert!(map.feed_loads_to_router());
+    assert_eq!(*router.last_loads.lock().unwrap(), map.shard_loads());
+    assert_eq!(map.len(), 20);
+
+    // The default router has no rebalancer hook.
+    let plain: ShardMap<u32, u32> = ShardMap::new();
+    assert!(!plain.feed_loads_to_router());
+
+    // Neither do the halves of a split map.
+    let (low, high) = map.split_shards(2);
+    assert!(!low.feed_loads_to_router());
+    assert!(!high.feed_loads_to_router());
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_get_hit_ratio() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    assert_eq!(map.diagnostics().hit_ratio(), None);
+    for i in 0..10 {
+        map.insert(i, i);
+    }
+    for i in 0..30 {
+        map.get(&i);
+    }
+    let diag = map.diagnostics();
+    let hits: u64 = diag.shards.iter().map(|s| s.get_hits).sum();
+    let misses: u64 = diag.shards.iter().map(|s| s.get_misses).sum();
+    assert_eq!((hits, misses), (10, 20));
+    let ratio = diag.hit_ratio().unwrap();
+    assert!((ratio - 1.0 / 3.0).abs() < 1e-9);
+
+    let ops = &map.stats().operations[map.shard_for_key(&0)];
+    assert!(ops.get_hits >= 1);
+}
+
+#[test]
+fn test_insert_full_returns_replaced_key() {
+    use std::hash::{Hash, Hasher};
+
+    #[derive(Debug)]
+    struct Interned {
+        name: &'static str,
+        generation: u32,
+    }
+    impl PartialEq for Interned {
+        fn eq(&self, other: &Self) -> bool {
+            self.name == other.name
+        }
+    }
+    impl Eq for Interned {}
+    impl Hash for Interned {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.name.hash(state);
+        }
+    }
+
+    let map = ShardMap::new();
+    let first = Interned {
+        name: "k",
+        generation: 1,
+    };
+    let (old_key, old_value) = map.insert_full(first, "a");
+    assert!(old_key.is_none());
+    assert!(old_value.is_none());
+
+    let second = Interned {
+        name: "k",
+        generation: 2,
+    };
+    let (old_key, old_value) = map.insert_full(second, "b");
+    assert_eq!(old_key.unwrap().generation, 1);
+    assert_eq!(*old_value.unwrap(), "a");
+    assert_eq!(map.len(), 1);
+
+    // The newly inserted key object is now the stored one.
+    let probe = Interned {
+        name: "k",
+        generation: 0,
+    };
+    let (old_key, _) = map.insert_full(probe, "c");
+    assert_eq!(old_key.unwrap().generation, 2);
+}
+
+#[test]
+fn test_clear_with_capacity_control() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    for i in 0..10_000 {
+        map.insert(i, i);
+    }
+    let full_capacity = map.capacity();
+    assert!(full_capacity >= 10_000);
+
+    map.clear_with(true);
+    assert!(map.is_empty());
+    assert_eq!(map.capacity(), full_capacity);
+
+    for i in 0..10_000 {
+        map.insert(i, i);
+    }
+    map.clear_with(false);
+    assert!(map.is_empty());
+    assert!(map.capacity() < full_capacity);
+    assert_eq!(map.capacity(), 0);
+
+    map.insert(1, 1);
+    assert_eq!(*map.get(&1).unwrap(), 1);
+}
+
+#[test]
+fn test_update_many() {
+    let map: ShardMap<u32, u64> = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .build()
+        .unwrap();
+    for i in 0..100 {
+        map.insert(i, 0);
+    }
+    let keys: Vec<u32> = (0..100).chain(1000..1010).collect();
+    let refs: Vec<&u32> = keys.iter().collect();
+
+    #[cfg(feature = "metrics")]
+    let locks_before: u64 = map
+        .stats()
+        .operations
+        .iter()
+        .map(|o| o.lock_acquisitions)
+        .sum();
+
+    let updated = map.update_many(&refs, |_, v| *v += 1);
+
+    #[cfg(feature = "metrics")]
+    {
+        let locks_after: u64 = map
+            .stats()
+            .operations
+            .iter()
+            .map(|o| o.lock_acquisitions)
+            .sum();
+        // One write lock per shard, not one per key (`stats()` itself read-locks each
+        // shard once to count entries).
+        assert_eq!(locks_after - locks_before, 4 + 4);
+    }
+
+    assert_eq!(updated, 100);
+    assert!((0..100).all(|i| *map.get(&i).unwrap() == 1));
+    assert!(!map.contains_key(&1000));
+}
+
+#[test]
+fn test_key_normalizer() {
+    let map: ShardMap<String, u32> = ShardMapBuilder::new()
+        .key_normalizer(Arc::new(|k: &String| k.trim().to_lowercase()))
+        .build()
+        .unwrap();
+    map.insert("foo".to_string(), 1);
+
+    assert_eq!(*map.get(&"FOO".to_string()).unwrap(), 1);
+    assert!(map.contains_key(&"  Foo ".to_string()));
+    assert_eq!(*map.insert("Foo".to_string(), 2).unwrap(), 1);
+    assert_eq!(map.len(), 1);
+
+    map.update(&"FOO".to_string(), |v| *v += 1);
+    assert_eq!(map.get_cloned_or(&"foo".to_string(), 0), 3);
+
+    map.rename(&"FOO".to_string(), "BAR".to_string()).unwrap();
+    let stored: Vec<String> = map.iter_snapshot().map(|(k, _)| k).collect();
+    assert_eq!(stored, vec!["bar".to_string()]);
+
+    let sibling = map.spawn_sibling();
+    sibling.insert("Baz".to_string(), 9);
+    assert!(sibling.contains_key(&"baz".to_string()));
+
+    assert_eq!(*map.remove(&"Bar".to_string()).unwrap(), 3);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_key_normalizer_batch_paths() {
+    use shardmap::Diff;
+
+    let map: ShardMap<String, u32> = ShardMapBuilder::new()
+        .key_normalizer(Arc::new(|k: &String| k.to_lowercase()))
+        .build()
+        .unwrap();
+    let key = |s: &str| s.to_string();
+
+    map.overwrite_many([(key("A"), 1), (key("b"), 2)]);
+    map.merge_from([(key("B"), 5)], |_, old, new| old + new);
+    map.apply_diff(Diff {
+        upserts: vec![(key("C"), 3)],
+        removals: vec![key("A")],
+    });
+    let c_shard = map.routed_index(map.routing_value(&key("C")));
+    assert_eq!(c_shard, map.routed_index(map.routing_value(&key("c"))));
+    map.insert_presharded(vec![(c_shard, vec![(key("C"), 30)])])
+        .unwrap();
+    assert_eq!(map.update_many(&[&key("B")], |_, v| *v += 1), 1);
+    assert_eq!(
+        map.try_insert_all(vec![(key("D"), 4), (key("d"), 4)]),
+        Err(shardmap::Error::KeyAlreadyExists)
+    );
+
+    let mut stored: Vec<(String, u32)> = map.iter_snapshot().map(|(k, v)| (k, *v)).collect();
+    stored.sort();
+    assert_eq!(stored, vec![(key("b"), 8), (key("c"), 30)]);
+}
+
+#[test]
+#[should_panic(expected = "shard_for_key: not supported on a map with a key normalizer")]
+fn test_key_normalizer_rejects_borrowed_routing() {
+    let map: ShardMap<String, u32> = ShardMapBuilder::new()
+        .key_normalizer(Arc::new(|k: &String| k.to_lowercase()))
+        .build()
+        .unwrap();
+    map.shard_for_key("Foo");
+}
+
+#[test]
+fn test_write_through_store() {
+    use shardmap::{WriteThrough, WriteThroughMode};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockStore {
+        calls: Mutex<Vec<String>>,
+    }
+    impl WriteThrough<u32, u32> for MockStore {
+        fn on_insert(&self, key: &u32, value: &u32) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("insert {}={}", key, value));
+        }
+        fn on_remove(&self, key: &u32) {
+            self.calls.lock().unwrap().push(format!("remove {}", key));
+        }
+    }
+
+    for mode in [WriteThroughMode::AfterLock, WriteThroughMode::InLock] {
+        let store = Arc::new(MockStore::default());
+        let map: ShardMap<u32, u32> = ShardMapBuilder::new()
+            .shard_count(1)
+            .unwrap()
+            .max_entries_per_shard(2)
+            .on_full(OnFull::EvictOldest)
+            .write_through(store.clone(), mode)
+            .build()
+            .unwrap();
+
+        map.insert(1, 10);
+        map.insert(1, 11);
+        map.checked_insert(2, 20).unwrap();
+        map.update(&2, |v| *v += 1);
+        map.insert_evicting(3, 30); // evicts 1
+        map.remove(&2);
+        map.remove(&99); // absent: no call
+        assert_eq!(*map.get(&3).unwrap(), 30);
+
+        assert_eq!(
+            *store.calls.lock().unwrap(),
+            [
+                "insert 1=10",
+                "insert 1=11",
+                "insert 2=20",
+                "insert 2=21",
+                "remove 1",
+                "insert 3=30",
+                "remove 2",
+            ],
+            "mode {:?}",
+            mode
+        );
+    }
+}
+
+#[test]
+fn test_write_through_sees_every_write() {
+    use shardmap::{Diff, WriteThrough, WriteThroughMode};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Applies the reported writes to its own map, which must end up equal to the cache.
+    #[derive(Default)]
+    struct MirrorStore {
+        rows: Mutex<HashMap<u32, u32>>,
+    }
+    impl WriteThrough<u32, u32> for MirrorStore {
+        fn on_insert(&self, key: &u32, value: &u32) {
+            self.rows.lock().unwrap().insert(*key, *value);
+        }
+        fn on_remove(&self, key: &u32) {
+            self.rows.lock().unwrap().remove(key);
+        }
+    }
+
+    for mode in [WriteThroughMode::AfterLock, WriteThroughMode::InLock] {
+        let store = Arc::new(MirrorStore::default());
+        let mut map: ShardMap<u32, u32> = ShardMapBuilder::new()
+            .shard_count(4)
+            .unwrap()
+            .write_through(store.clone(), mode)
+            .build()
+            .unwrap();
+        let check = |map: &ShardMap<u32, u32>, step: &str| {
+            let cached: HashMap<u32, u32> = map.iter().map(|(k, v)| (k, *v)).collect();
+            assert_eq!(
+                *store.rows.lock().unwrap(),
+                cached,
+                "{:?} after {}",
+                mode,
+                step
+            );
+        };
+
+        map.get_or_insert(1, 10);
+        map.get_or_insert_with(2, || 20);
+        map.get_or_insert_with_status(3, || 30);
+        map.get_or_insert_then(3, 0, |v| *v += 1);
+        map.try_insert(4, 40).unwrap();
+        map.insert_full(4, 41);
+        map.get_or_try_insert_single_flight(5, || Ok::<_, String>(50))
+            .unwrap();
+        check(&map, "single inserts");
+
+        map.rename(&1, 100).unwrap();
+        map.rename(&2, 101).unwrap();
+        map.migrate_key(&3, (map.shard_for_key(&3) + 1) % 4)
+            .unwrap();
+        check(&map, "renames");
+
+        map.overwrite_many([(6, 60), (7, 70), (4, 42)]);
+        map.merge_from([(6, 1), (8, 80)], |_, old, new| old + new);
+        map.get_or_insert_many(vec![9, 10], |k| k * 10);
+        map.try_insert_all(vec![(11, 110), (12, 120)]).unwrap();
+        map.update_many(&[&9, &10], |_, v| *v += 1);
+        map.apply_diff(Diff {
+            upserts: vec![(13, 130), (7, 71)],
+            removals: vec![8],
+        });
+        check(&map, "batches");
+
+        match map.entry(14) {
+            shardmap::Entry::Vacant(entry) => {
+                entry.insert(140);
+            }
+            shardmap::Entry::Occupied(_) => unreachable!(),
+        }
+        if let shardmap::Entry::Occupied(mut entry) = map.entry(14) {
+            *entry.get_mut() += 1;
+        }
+        if let shardmap::Entry::Occupied(entry) = map.entry(13) {
+            entry.remove();
+        }
+        map.with_many_mut(&[&11, &12], |slots| {
+            for value in slots.iter_mut().flatten() {
+                **value += 1;
+            }
+        });
+        check(&map, "entry and with_many_mut");
+
+        map.for_each_mut(|_, v| *v += 1);
+        map.retain(|k, _| k % 2 == 0);
+        map.drain_filter(|k, _| *k > 100);
+        map.rebuild(|_, table| {
+            for (_, value) in table.iter_mut() {
+                *value = Arc::new(**value * 2);
+            }
+        });
+        check(&map, "bulk writes");
+
+        let next = map.spawn_sibling();
+        next.insert(1, 1);
+        map.swap_contents(&next).unwrap();
+        check(&map, "swap_contents");
+
+        map.clear();
+        check(&map, "clear");
+    }
+}
+
+#[test]
+fn test_read_through_backfills_once() {
+    use shardmap::ReadThrough;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct MockSource {
+        loads: AtomicUsize,
+    }
+    impl ReadThrough<u32, String> for MockSource {
+        fn load(&self, key: &u32) -> Option<String> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            (*key < 100).then(|| format!("row{}", key))
+        }
+    }
+
+    let source = Arc::new(MockSource::default());
+    let map: ShardMap<u32, String> = ShardMap::new().with_read_through(source.clone());
+
+    assert!(map.get(&7).is_none());
+    assert_eq!(source.loads.load(Ordering::SeqCst), 0);
+
+    assert_eq!(*map.get_through(&7).unwrap(), "row7");
+    assert_eq!(source.loads.load(Ordering::SeqCst), 1);
+    assert_eq!(*map.get(&7).unwrap(), "row7");
+
+    // Cached now: no second load.
+    assert_eq!(*map.get_through(&7).unwrap(), "row7");
+    assert_eq!(source.loads.load(Ordering::SeqCst), 1);
+
+    // Missing from the source too: nothing cached.
+    assert!(map.get_through(&500).is_none());
+    assert!(!map.contains_key(&500));
+    assert_eq!(map.len(), 1);
+
+    // A value already present wins over the source.
+    map.insert(8, "local".to_string());
+    assert_eq!(*map.get_through(&8).unwrap(), "local");
+}
+
+#[test]
+fn test_keys_difference_and_intersection() {
+    let old: ShardMap<u32, u32> = ShardMap::new();
+    let new: ShardMap<u32, u32> = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .build()
+        .unwrap();
+    for i in 0..10 {
+        old.insert(i, i);
+    }
+    for i in 5..15 {
+        new.insert(i, i);
+    }
+
+    let mut removed = old.keys_difference(&new);
+    removed.sort();
+    assert_eq!(removed, (0..5).collect::<Vec<_>>());
+    let mut added = new.keys_difference(&old);
+    added.sort();
+    assert_eq!(added, (10..15).collect::<Vec<_>>());
+    let mut kept = old.keys_intersection(&new);
+    kept.sort();
+    assert_eq!(kept, (5..10).collect::<Vec<_>>());
+
+    // Disjoint key sets.
+    let other: ShardMap<u32, u32> = ShardMap::new();
+    for i in 100..105 {
+        other.insert(i, i);
+    }
+    assert!(old.keys_intersection(&other).is_empty());
+    assert_eq!(old.keys_difference(&other).len(), 10);
+
+    // Comparing a map with itself.
+    assert!(old.keys_difference(&old).is_empty());
+    assert_eq!(old.keys_intersection(&old).len(), 10);
+}
+
+#[test]
+fn test_apply_diff() {
+    use shardmap::Diff;
+
+    let map: ShardMap<u32, &str> = ShardMap::new();
+    for i in 0..10 {
+        map.insert(i, "old");
+    }
+    map.apply_diff(Diff {
+        upserts: vec![(1, "new"), (5, "new"), (20, "added")],
+        removals: vec![0, 5, 99],
+    });
+
+    assert!(!map.contains_key(&0));
+    assert_eq!(*map.get(&1).unwrap(), "new");
+    // Removed then upserted within the same shard lock: the upsert wins.
+    assert_eq!(*map.get(&5).unwrap(), "new");
+    assert_eq!(*map.get(&20).unwrap(), "added");
+    assert_eq!(*map.get(&2).unwrap(), "old");
+    assert_eq!(map.len(), 10);
+
+    map.apply_diff(Diff::default());
+    assert_eq!(map.len(), 10);
+}
+
+#[cfg(feature = "lock-introspect")]
+#[test]
+fn test_locked_shard_count() {
+    use std::sync::Barrier;
+
+    let map: Arc<ShardMap<u32, u32>> = Arc::new(ShardMap::new());
+    map.insert(1, 1);
+    assert_eq!(map.locked_shard_count(), 0);
+
+    let locked = Arc::new(Barrier::new(2));
+    let release = Arc::new(Barrier::new(2));
+    let holder = {
+        let map = map.clone();
+        let locked = locked.clone();
+        let release = release.clone();
+        std::thread::spawn(move || {
+            map.with_many_mut(&[&1], |_| {
+                locked.wait();
+                release.wait();
+            });
+        })
+    };
+
+    locked.wait();
+    assert!(map.locked_shard_count() >= 1);
+    release.wait();
+    holder.join().unwrap();
+    assert_eq!(map.locked_shard_count(), 0);
+}
+
+#[test]
+fn test_with_capacity_and_shards() {
+    let map: ShardMap<u32, u32> = ShardMap::with_capacity_and_shards(1000, 8).unwrap();
+    assert_eq!(map.shard_count(), 8);
+    assert!(map.capacity() >= 1000);
+
+    // Rounds the per-shard share up.
+    let map: ShardMap<u32, u32> = ShardMap::with_capacity_and_shards(9, 8).unwrap();
+    assert!(map.capacity() >= 9);
+
+    assert!(matches!(
+        ShardMap::<u32, u32>::with_capacity_and_shards(100, 6),
+        Err(Error::InvalidShardCount)
+    ));
+    assert!(matches!(
+        ShardMap::<u32, u32>::with_capacity_and_shards(100, 0),
+        Err(Error::InvalidShardCount)
+    ));
+}
+
+#[test]
+fn test_route_batch_matches_shard_for_key() {
+    let map: ShardMap<u32, ()> = ShardMap::new();
+    let keys: Vec<u32> = (0..1000).map(|i| i * 7919).collect();
+    let mut out = vec![usize::MAX; keys.len()];
+    map.route_u32_batch(&keys, &mut out);
+    for (key, &shard) in keys.iter().zip(&out) {
+        assert_eq!(shard, map.shard_for_key(key));
+    }
+
+    struct Modulo;
+    impl ShardRouter for Modulo {
+        fn route(&self, key_hash: u64, shard_count: usize) -> usize {
+            (key_hash % shard_count as u64) as usize
+        }
+    }
+    let map: ShardMap<u64, ()> = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .fixed_seed(3)
+        .routing(RoutingConfig::Custom(Box::new(Modulo)))
+        .build()
+        .unwrap();
+    let keys: Vec<u64> = (0..1000).map(|i| i << 20).collect();
+    let mut out = vec![0; keys.len()];
+    map.route_u64_batch(&keys, &mut out);
+    for (key, &shard) in keys.iter().zip(&out) {
+        assert_eq!(shard, map.shard_for_key(key));
+    }
+}
+
+#[test]
+#[should_panic(expected = "must be as long as")]
+fn test_route_batch_length_mismatch_panics() {
+    let map: ShardMap<u32, ()> = ShardMap::new();
+    let mut out = [0; 2];
+    map.route_u32_batch(&[1, 2, 3], &mut out);
+}
+
+#[test]
+fn test_set_routing_rehashes_entries() {
+    struct AllToZero;
+    impl ShardRouter for AllToZero {
+        fn route(&self, _key_hash: u64, _shard_count: usize) -> usize {
+            0
+        }
+    }
+    struct OutOfRange;
+    impl ShardRouter for OutOfRange {
+        fn route(&self, _key_hash: u64, shard_count: usize) -> usize {
+            shard_count
+        }
+    }
+
+    let mut map: ShardMap<u32, u32> = ShardMap::new();
+    for i in 0..100 {
+        map.insert(i, i * 2);
+    }
+    assert!(map.shard_loads()[0] < 100);
+
+    assert_eq!(
+        map.set_routing(RoutingConfig::Custom(Box::new(OutOfRange))),
+        Err(Error::InvalidShardIndex)
+    );
+    assert_eq!(*map.get(&5).unwrap(), 10);
+
+    map.set_routing(RoutingConfig::Custom(Box::new(AllToZero)))
+        .unwrap();
+    assert_eq!(map.shard_loads()[0], 100);
+    assert_eq!(map.len(), 100);
+    assert!((0..100).all(|i| map.shard_for_key(&i) == 0 && *map.get(&i).unwrap() == i * 2));
+
+    map.set_routing(RoutingConfig::Default).unwrap();
+    assert!(map.shard_loads()[0] < 100);
+    assert!((0..100).all(|i| *map.get(&i).unwrap() == i * 2));
+
+    // A bounded map refuses a routing that would overflow a shard.
+    let mut bounded: ShardMap<u32, u32> = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .max_entries_per_shard(10)
+        .build()
+        .unwrap();
+    for i in 0..20 {
+        bounded.checked_insert(i, i).ok();
+    }
+    assert_eq!(
+        bounded.set_routing(RoutingConfig::Custom(Box::new(AllToZero))),
+        Err(Error::ShardFull)
+    );
+}
+
+#[test]
+fn test_count_by_prefix() {
+    let map: ShardMap<String, u32> = ShardMap::new();
+    for i in 0..30 {
+        map.insert(format!("acme:{}", i), i);
+    }
+    for i in 0..12 {
+        map.insert(format!("globex:{}", i), i);
+    }
+    map.insert("initech:0".to_string(), 0);
+
+    let counts = map.count_by(|k| k.split(':').next().unwrap().to_string());
+    assert_eq!(counts.len(), 3);
+    assert_eq!(counts["acme"], 30);
+    assert_eq!(counts["globex"], 12);
+    assert_eq!(counts["initech"], 1);
+    assert_eq!(counts.values().sum::<usize>(), map.len());
+
+    let empty: ShardMap<String, u32> = ShardMap::new();
+    assert!(empty.count_by(|k| k.len()).is_empty());
+}
+
+#[test]
+fn test_swap_contents() {
+    let a: ShardMap<u32, &str> = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .fixed_seed(11)
+        .build()
+        .unwrap();
+    let b = a.spawn_sibling();
+    for i in 0..50 {
+        a.insert(i, "a");
+    }
+    for i in 100..120 {
+        b.insert(i, "b");
+    }
+
+    a.swap_contents(&b).unwrap();
+    assert_eq!(a.len(), 20);
+    assert_eq!(b.len(), 50);
+    assert!((100..120).all(|i| *a.get(&i).unwrap() == "b"));
+    assert!((0..50).all(|i| *b.get(&i).unwrap() == "a"));
+    assert!(!a.contains_key(&0));
+
+    // Swapping with itself is a no-op.
+    a.swap_contents(&a).unwrap();
+    assert_eq!(a.len(), 20);
+
+    // Different shard count or seed: rejected, nothing changes.
+    let other_count: ShardMap<u32, &str> = ShardMap::new();
+    assert_eq!(a.swap_contents(&other_count), Err(Error::IncompatibleMaps));
+    let other_seed: ShardMap<u32, &str> = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .fixed_seed(12)
+        .build()
+        .unwrap();
+    assert_eq!(a.swap_contents(&other_seed), Err(Error::IncompatibleMaps));
+    assert_eq!(a.len(), 20);
+}
+
+#[test]
+fn test_freeze_for_read_blocks_writers() {
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    let map: Arc<ShardMap<u32, u32>> = Arc::new(ShardMap::new());
+    for i in 0..100 {
+        map.insert(i, i);
+    }
+
+    let started = Arc::new(Barrier::new(2));
+    let writer = {
+        let map = map.clone();
+        let started = started.clone();
+        std::thread::spawn(move || {
+            started.wait();
+            for i in 100..200 {
+                map.insert(i, i);
+            }
+        })
+    };
+
+    let (before, after) = map.freeze_for_read(|m| {
+        let before = m.len();
+        started.wait();
+        // Give the writer time to queue up behind the read locks.
+        std::thread::sleep(Duration::from_millis(50));
+        let after = m.len();
+        assert_eq!(m.iter_snapshot().count(), after);
+        (before, after)
+    });
+    assert_eq!(before, 100);
+    assert_eq!(after, 100);
+
+    writer.join().unwrap();
+    assert_eq!(map.len(), 200);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_suggest_shard_count() {
+    let map: ShardMap<u32, u32> = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(map.suggest_shard_count(), 8);
+
+    for i in 0..800 {
+        map.insert(i, i);
+    }
+    // Hammer a single shard.
+    for _ in 0..10_000 {
+        map.get(&0);
+    }
+    assert!(map.suggest_shard_count() > 8);
+}
+
+#[test]
+fn test_try_insert_all_is_all_or_nothing() {
+    let map: ShardMap<u32, &str> = ShardMap::new();
+    map.insert(7, "existing");
+
+    let batch: Vec<(u32, &str)> = (0..10).map(|i| (i, "claimed")).collect();
+    assert_eq!(map.try_insert_all(batch), Err(Error::KeyAlreadyExists));
+    assert_eq!(map.len(), 1);
+    assert_eq!(*map.get(&7).unwrap(), "existing");
+
+    // Duplicates within the batch are rejected too.
+    assert_eq!(
+        map.try_insert_all(vec![(20, "a"), (20, "b")]),
+        Err(Error::KeyAlreadyExists)
+    );
+    assert!(!map.contains_key(&20));
+
+    let batch: Vec<(u32, &str)> = (100..120).map(|i| (i, "claimed")).collect();
+    map.try_insert_all(batch).unwrap();
+    assert_eq!(map.len(), 21);
+    assert!((100..120).all(|i| *map.get(&i).unwrap() == "claimed"));
+
+    // A bounded map that can't fit the batch inserts nothing.
+    let bounded: ShardMap<u32, u32> = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(3)
+        .on_full(OnFull::Reject)
+        .build()
+        .unwrap();
+    bounded.insert(0, 0);
+    assert_eq!(
+        bounded.try_insert_all(vec![(1, 1), (2, 2), (3, 3)]),
+        Err(Error::ShardFull)
+    );
+    assert_eq!(bounded.len(), 1);
+    bounded.try_insert_all(vec![(1, 1), (2, 2)]).unwrap();
+    assert_eq!(bounded.len(), 3);
+}
+
+#[test]
+fn test_snapshot_chunks_match_full_snapshot() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    for i in 0..1000 {
+        map.insert(i, i * 3);
+    }
+
+    let chunks: Vec<Vec<(u32, Arc<u32>)>> = map.snapshot_chunks(64).collect();
+    assert!(chunks.iter().all(|c| !c.is_empty() && c.len() <= 64));
+    assert!(chunks[..chunks.len() - 1].iter().all(|c| c.len() == 64));
+
+    let mut chunked: Vec<(u32, u32)> = chunks.into_iter().flatten().map(|(k, v)| (k, *v)).collect();
+    let mut full: Vec<(u32, u32)> = map.iter_snapshot().map(|(k, v)| (k, *v)).collect();
+    chunked.sort();
+    full.sort();
+    assert_eq!(chunked, full);
+
+    let empty: ShardMap<u32, u32> = ShardMap::new();
+    assert_eq!(empty.snapshot_chunks(8).count(), 0);
+}
+
+#[test]
+fn test_overwrite_many_counts_existing_keys() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    for i in 0..50 {
+        map.insert(i, 0);
+    }
+    let items = (25..75).map(|i| (i, 1));
+    assert_eq!(map.overwrite_many(items), 25);
+    assert_eq!(map.len(), 75);
+    assert!((0..25).all(|i| *map.get(&i).unwrap() == 0));
+    assert!((25..75).all(|i| *map.get(&i).unwrap() == 1));
+
+    assert_eq!(map.overwrite_many(Vec::new()), 0);
+}
+
+#[test]
+fn test_holds_same() {
+    let map: ShardMap<&str, String> = ShardMap::new();
+    map.insert("k", "v1".to_string());
+    let cached = map.get(&"k").unwrap();
+    let clone = Arc::clone(&cached);
+    assert!(map.holds_same(&"k", &clone));
+    assert!(!map.holds_same(&"other", &clone));
+
+    // Equal value, different allocation.
+    assert!(!map.holds_same(&"k", &Arc::new("v1".to_string())));
+
+    map.insert("k", "v1".to_string());
+    assert!(!map.holds_same(&"k", &cached));
+
+    let current = map.get(&"k").unwrap();
+    map.remove(&"k");
+    assert!(!map.holds_same(&"k", &current));
+}
+
+#[test]
+fn test_routing_fn() {
+    let map = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .routing_fn(|h, n| (h as usize) % n)
+        .build::<u64, u64>()
+        .unwrap();
+    for k in 0..100u64 {
+        map.insert(k, k);
+    }
+    for k in 0..100u64 {
+        let hash = map.hash_for_key(&k);
+        assert_eq!(map.shard_for_key(&k), (hash as usize) % 8);
+        assert_eq!(*map.get(&k).unwrap(), k);
+    }
+    assert_eq!(map.shard_loads().iter().sum::<usize>(), 100);
+
+    let all_last = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .routing(RoutingConfig::from_fn(|_, n| n - 1))
+        .build::<&str, i32>()
+        .unwrap();
+    all_last.insert("a", 1);
+    all_last.insert("b", 2);
+    assert_eq!(all_last.shard_loads(), vec![0, 0, 0, 2]);
+}
+
+#[test]
+fn test_count_where() {
+    let map = ShardMap::new();
+    for i in 0..200u32 {
+        map.insert(i, i * 3);
+    }
+    let from_snapshot = map.iter_snapshot().filter(|(_, v)| **v % 2 == 0).count();
+    assert_eq!(map.count_where(|_, v| v % 2 == 0), from_snapshot);
+    assert_eq!(map.count_where(|_, v| v % 2 == 0), 100);
+    assert_eq!(map.count_where(|k, _| *k >= 150), 50);
+    assert_eq!(map.count_where(|_, _| false), 0);
+}
+
+#[test]
+fn test_into_sorted_vec() {
+    let map = ShardMap::new();
+    for i in (0..500u32).rev() {
+        map.insert(i, i.to_string());
+    }
+    let entries = map.into_sorted_vec();
+    assert_eq!(entries.len(), 500);
+    assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+    for (i, (k, v)) in entries.iter().enumerate() {
+        assert_eq!(*k, i as u32);
+        assert_eq!(**v, i.to_string());
+    }
+
+    let empty: ShardMap<u32, u32> = ShardMap::new();
+    assert!(empty.into_sorted_vec().is_empty());
+}
+
+#[test]
+fn test_routing_descriptor() {
+    use shardmap::{HashFunction, RoutingKind};
+    use std::hash::Hash;
+
+    // Reimplements routing from the descriptor's fields alone.
+    fn route_externally<Q: Hash>(d: &shardmap::RoutingDescriptor, key: &Q) -> usize {
+        assert!(d.is_reproducible());
+        assert_eq!(d.hash_function, HashFunction::AHash);
+        let seed = d.seed.unwrap();
+        let (lo, hi) = (seed as u64, (seed >> 64) as u64);
+        let hash = ahash::RandomState::with_seeds(lo, hi, !lo, !hi).hash_one(key);
+        (hash as usize) & (d.shard_count - 1)
+    }
+
+    // Unseeded ahash keys are per process.
+    let map: ShardMap<String, i32> = ShardMap::with_shard_count(32).unwrap();
+    let d = map.routing_descriptor();
+    assert_eq!(d.shard_count, 32);
+    assert_eq!(d.seed, None);
+    assert_eq!(d.routing_kind, RoutingKind::Mask);
+    assert!(!d.is_reproducible());
+
+    let seeded = ShardMapBuilder::new()
+        .fixed_seed(0xdead_beef_0123_4567_89ab_cdef_f00d_cafe)
+        .build::<u64, u64>()
+        .unwrap();
+    let d = seeded.routing_descriptor();
+    assert_eq!(d.seed, Some(0xdead_beef_0123_4567_89ab_cdef_f00d_cafe));
+    for k in 0..200u64 {
+        assert_eq!(route_externally(&d, &k), seeded.shard_for_key(&k));
+    }
+
+    let custom = ShardMapBuilder::new()
+        .routing_fn(|_, _| 0)
+        .build::<u64, u64>()
+        .unwrap();
+    let d = custom.routing_descriptor();
+    assert_eq!(d.routing_kind, RoutingKind::Custom);
+    assert!(!d.is_reproducible());
+
+    // FxHash has no per-process keys, so it is reproducible without a seed.
+    #[cfg(feature = "fxhash")]
+    {
+        let fx = ShardMapBuilder::new()
+            .hash_function(HashFunction::FxHash)
+            .build::<u64, u64>()
+            .unwrap();
+        let d = fx.routing_descriptor();
+        assert_eq!((d.hash_function, d.seed), (HashFunction::FxHash, None));
+        assert!(d.is_reproducible());
+    }
+}
+
+#[test]
+fn test_into_inner_hashmap() {
+    let map = ShardMap::with_shard_count(8).unwrap();
+    for i in 0..300u32 {
+        map.insert(i, i * 2);
+    }
+    assert!(map.shard_loads().iter().filter(|&&n| n > 0).count() > 1);
+    let shared = map.get(&7).unwrap();
+
+    let inner = map.into_inner_hashmap();
+    assert_eq!(inner.len(), 300);
+    for i in 0..300u32 {
+        assert_eq!(*inner[&i], i * 2);
+    }
+    assert!(Arc::ptr_eq(&inner[&7], &shared));
+}
+
+#[test]
+fn test_peek() {
+    let map = ShardMap::new();
+    map.insert("a", 1);
+    assert_eq!(*map.peek(&"a").unwrap(), 1);
+    assert!(map.peek(&"missing").is_none());
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_peek_does_not_count_reads() {
+    let map = ShardMap::new();
+    map.insert("a", 1);
+    let shard = map.shard_for_key(&"a");
+    let reads = |map: &ShardMap<&str, i32>| map.stats().operations[shard].reads;
+    let touches = |map: &ShardMap<&str, i32>| map.access_distribution()[shard];
+
+    let (reads_before, touches_before) = (reads(&map), touches(&map));
+    for _ in 0..5 {
+        assert_eq!(*map.peek(&"a").unwrap(), 1);
+    }
+    assert_eq!(reads(&map), reads_before);
+    assert_eq!(touches(&map), touches_before);
+
+    map.get(&"a");
+    assert_eq!(reads(&map), reads_before + 1);
+}
+
+#[test]
+fn test_contains_many_by_hash() {
+    let map: ShardMap<String, u32> = ShardMap::with_shard_count(8).unwrap();
+    for i in (0..100u32).step_by(3) {
+        map.insert(format!("k{i}"), i);
+    }
+    let keys: Vec<String> = (0..100u32).map(|i| format!("k{i}")).collect();
+    let batch: Vec<(&str, u64)> = keys
+        .iter()
+        .map(|k| (k.as_str(), map.hash_for_key(k.as_str())))
+        .collect();
+
+    let found = map.contains_many_by_hash(&batch);
+    assert_eq!(found.len(), keys.len());
+    for (key, present) in keys.iter().zip(&found) {
+        assert_eq!(*present, map.contains_key(key));
+    }
+    assert_eq!(found.iter().filter(|&&p| p).count(), 34);
+    assert!(map.contains_many_by_hash::<str>(&[]).is_empty());
+}
+
+#[test]
+fn test_max_by_min_by() {
+    let map = ShardMap::with_shard_count(8).unwrap();
+    assert!(map.max_by(|a: &u64, b| a.cmp(b)).is_none());
+    assert!(map.min_by(|a: &u64, b| a.cmp(b)).is_none());
+
+    for i in 0..500u64 {
+        // Values peak at key 321 and bottom out at key 123.
+        let value = match i {
+            321 => 10_000,
+            123 => 0,
+            _ => 1 + (i * 37) % 997,
+        };
+        map.insert(format!("counter-{i}"), value);
+    }
+    let (key, value) = map.max_by(|a, b| a.cmp(b)).unwrap();
+    assert_eq!((key.as_str(), *value), ("counter-321", 10_000));
+    let (key, value) = map.min_by(|a, b| a.cmp(b)).unwrap();
+    assert_eq!((key.as_str(), *value), ("counter-123", 0));
+
+    // A reversed comparator swaps the two.
+    let (key, _) = map.max_by(|a, b| b.cmp(a)).unwrap();
+    assert_eq!(key, "counter-123");
+}
+
+#[test]
+fn test_sum_by() {
+    struct Gauge {
+        level: i64,
+        samples: u32,
+    }
+
+    let map = ShardMap::with_shard_count(8).unwrap();
+    for i in 0..250i64 {
+        map.insert(
+            i,
+            Gauge {
+                level: i - 100,
+                samples: 2,
+            },
+        );
+    }
+    let from_snapshot: i64 = map.iter_snapshot().map(|(_, g)| g.level).sum();
+    assert_eq!(map.sum_i64(|g| g.level), from_snapshot);
+    assert_eq!(map.sum_i64(|g| g.level), (0..250).map(|i| i - 100).sum());
+    assert_eq!(map.sum_by(|g| g.samples as u64, 0, |a, b| a + b), 500);
+    assert_eq!(map.sum_by(|g| g.level, i64::MIN, i64::max), 149);
+
+    let empty: ShardMap<u8, Gauge> = ShardMap::new();
+    assert_eq!(empty.sum_i64(|g| g.level), 0);
+}
+
+#[test]
+fn test_retain_with_progress() {
+    let map = ShardMap::with_shard_count(16).unwrap();
+    for i in 0..400u32 {
+        map.insert(i, i);
+    }
+    let mut calls = Vec::new();
+    map.retain_with_progress(|_, v| *v % 4 == 0, |done, total| calls.push((done, total)));
+
+    assert_eq!(calls.len(), map.shard_count());
+    assert!(calls.iter().all(|&(_, total)| total == 16));
+    assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(calls.last(), Some(&(16, 16)));
+    assert_eq!(map.len(), 100);
+    assert_eq!(map.count_where(|_, v| v % 4 != 0), 0);
+}
+
+#[test]
+fn test_compute_shard_matches_live_map() {
+    use shardmap::HashFunction;
+
+    for shards in [1, 2, 16, 64] {
+        let map: ShardMap<String, ()> = ShardMap::with_shard_count(shards).unwrap();
+        let seeded = ShardMapBuilder::new()
+            .shard_count(shards)
+            .unwrap()
+            .fixed_seed(42)
+            .build::<String, ()>()
+            .unwrap();
+        for i in 0..100 {
+            let key = format!("user:{i}");
+            let offline =
+                ShardMap::<String, ()>::compute_shard(&key, shards, HashFunction::AHash, None)
+                    .unwrap();
+            assert_eq!(offline, map.shard_for_key(&key));
+            let offline =
+                ShardMap::<String, ()>::compute_shard(&key, shards, HashFunction::AHash, Some(42))
+                    .unwrap();
+            assert_eq!(offline, seeded.canonical_shard(&key));
+            assert_eq!(seeded.canonical_shard(&key), seeded.shard_for_key(&key));
+        }
+    }
+    assert_eq!(
+        ShardMap::<u32, ()>::compute_shard(&1u32, 12, HashFunction::AHash, None),
+        Err(Error::InvalidShardCount)
+    );
+}
+
+#[test]
+fn test_entry_api() {
+    use shardmap::Entry;
+
+    let map = ShardMap::new();
+    map.insert("a", 1);
+
+    // Occupied: inspect, then replace.
+    match map.entry("a") {
+        Entry::Occupied(mut entry) => {
+            assert_eq!(*entry.key(), "a");
+            assert_eq!(**entry.get(), 1);
+            let previous = entry.insert(10);
+            assert_eq!(*previous, 1);
+            assert_eq!(**entry.get(), 10);
+        }
+        Entry::Vacant(_) => panic!("expected occupied"),
+    }
+    assert_eq!(*map.get(&"a").unwrap(), 10);
+
+    // Occupied: inspect, then remove.
+    match map.entry("a") {
+        Entry::Occupied(entry) => assert_eq!(*entry.remove(), 10),
+        Entry::Vacant(_) => panic!("expected occupied"),
+    }
+    assert!(map.get(&"a").is_none());
+    assert_eq!(map.len(), 0);
+
+    // Vacant: insert.
+    match map.entry("b") {
+        Entry::Vacant(entry) => {
+            assert_eq!(*entry.key(), "b");
+            assert_eq!(*entry.insert(2), 2);
+        }
+        Entry::Occupied(_) => panic!("expected vacant"),
+    }
+    assert_eq!(*map.get(&"b").unwrap(), 2);
+
+    // Dropping a vacant entry inserts nothing.
+    assert_eq!(map.entry("c").key(), &"c");
+    assert!(!map.contains_key(&"c"));
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn test_entry_respects_bound() {
+    use shardmap::Entry;
+
+    let map = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(2)
+        .on_full(OnFull::EvictOldest)
+        .build::<u32, u32>()
+        .unwrap();
+    for k in 0..3 {
+        if let Entry::Vacant(entry) = map.entry(k) {
+            entry.insert(k);
+        }
+    }
+    assert_eq!(map.len(), 2);
+    assert!(!map.contains_key(&0));
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_shrink_idle_shards() {
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(2).unwrap();
+    let mut keys: [Vec<u32>; 2] = [Vec::new(), Vec::new()];
+    for k in 0..4000u32 {
+        keys[map.shard_for_key(&k)].push(k);
+        map.insert(k, k);
+    }
+    for k in 0..4000u32 {
+        map.remove(&k);
+    }
+    let full_capacity = map.capacity();
+
+    // The first call only records the baseline.
+    assert!(map.shrink_idle_shards(0).is_empty());
+    assert_eq!(map.capacity(), full_capacity);
+
+    // Shard 1 stays active; shard 0 goes idle.
+    map.insert(keys[1][0], 1);
+    assert_eq!(map.shrink_idle_shards(0), vec![0]);
+    let after = map.capacity();
+    assert!(after < full_capacity);
+    assert!(after >= keys[1].len());
+
+    // With no writes in the next interval, both shards are idle.
+    assert_eq!(map.shrink_idle_shards(0), vec![0, 1]);
+    assert!(map.capacity() < after);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_into_par_iter() {
+    use rayon::iter::ParallelIterator;
+    use std::collections::HashSet;
+
+    let map = ShardMap::with_shard_count(16).unwrap();
+    for i in 0..1000u32 {
+        map.insert(i, i + 1);
+    }
+    let collected: HashSet<(u32, u32)> = map.into_par_iter().map(|(k, v)| (k, *v)).collect();
+    let expected: HashSet<(u32, u32)> = (0..1000u32).map(|i| (i, i + 1)).collect();
+    assert_eq!(collected, expected);
+}
+
+#[test]
+fn test_as_read_only() {
+    use shardmap::ReadOnly;
+
+    fn total(view: ReadOnly<'_, String, u32>) -> u32 {
+        view.iter_snapshot().map(|(_, v)| *v).sum()
+    }
+
+    let map: ShardMap<String, u32> = ShardMap::with_shard_count(4).unwrap();
+    map.insert("a".to_string(), 1);
+    map.insert("b".to_string(), 2);
+
+    let view = map.as_read_only();
+    let copy = view;
+    assert_eq!(*view.get(&"a".to_string()).unwrap(), 1);
+    assert!(copy.contains_key(&"b".to_string()));
+    assert!(!view.contains_key(&"c".to_string()));
+    assert_eq!(view.len(), 2);
+    assert!(!view.is_empty());
+    assert_eq!(view.shard_count(), 4);
+    assert_eq!(view.shard_for_key("a"), map.shard_for_key("a"));
+    assert_eq!(view.shard_loads(), map.shard_loads());
+    assert_eq!(view.diagnostics().shards.len(), 4);
+    assert_eq!(total(view), 3);
+
+    // The view tracks later writes to the map.
+    map.insert("c".to_string(), 3);
+    assert_eq!(view.len(), 3);
+}
+
+#[test]
+fn test_get_or_insert_with_status() {
+    let map = ShardMap::new();
+    let mut fills = 0;
+
+    let (value, inserted) = map.get_or_insert_with_status("k", || {
+        fills += 1;
+        10
+    });
+    assert_eq!((*value, inserted), (10, true));
+
+    let (value, inserted) = map.get_or_insert_with_status("k", || {
+        fills += 1;
+        20
+    });
+    assert_eq!((*value, inserted), (10, false));
+    assert_eq!(fills, 1);
+
+    // Racing callers on one missing key: exactly one fill.
+    let map = Arc::new(ShardMap::new());
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let map = Arc::clone(&map);
+            std::thread::spawn(move || map.get_or_insert_with_status("shared", move || i).1)
+        })
+        .collect();
+    let filled = handles
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .filter(|&inserted| inserted)
+        .count();
+    assert_eq!(filled, 1);
+}
+
+#[test]
+fn test_reserve_to_total() {
+    let map: ShardMap<u64, u64> = ShardMap::with_shard_count(8).unwrap();
+    map.insert(1, 1);
+    map.reserve_to_total(10_000);
+    assert!(map.capacity() >= 10_000);
+
+    // Only grows: a smaller target leaves capacity as is.
+    let capacity = map.capacity();
+    map.reserve_to_total(100);
+    assert_eq!(map.capacity(), capacity);
+    assert_eq!(*map.get(&1).unwrap(), 1);
+}
+
+#[test]
+fn test_error_kind_and_io_conversion() {
+    use shardmap::ErrorKind;
+    use std::io;
+
+    let cases = [
+        (
+            Error::KeyNotFound,
+            ErrorKind::NotFound,
+            io::ErrorKind::NotFound,
+        ),
+        (
+            Error::KeyAlreadyExists,
+            ErrorKind::AlreadyExists,
+            io::ErrorKind::AlreadyExists,
+        ),
+        (Error::ShardFull, ErrorKind::Full, io::ErrorKind::Other),
+        (
+            Error::InvalidShardCount,
+            ErrorKind::InvalidConfig,
+            io::ErrorKind::InvalidInput,
+        ),
+        (
+            Error::IncompatibleMaps,
+            ErrorKind::InvalidInput,
+            io::ErrorKind::InvalidInput,
+        ),
+        (
+            Error::ShardCountMismatch,
+            ErrorKind::InvalidInput,
+            io::ErrorKind::InvalidInput,
+        ),
+        (
+            Error::BoundNotSupported,
+            ErrorKind::InvalidConfig,
+            io::ErrorKind::InvalidInput,
+        ),
+    ];
+    for (err, kind, io_kind) in cases {
+        assert_eq!(err.kind(), kind);
+        let io_err: io::Error = err.clone().into();
+        assert_eq!(io_err.kind(), io_kind);
+        assert_eq!(io_err.to_string(), err.to_string());
+        let inner = io_err.get_ref().unwrap().downcast_ref::<Error>();
+        assert_eq!(inner, Some(&err));
+    }
+
+    // `?` composes with io::Result callers.
+    fn open_map() -> io::Result<ShardMap<u32, u32>> {
+        Ok(ShardMap::with_shard_count(3)?)
+    }
+    let err = open_map().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_coalesce_values() {
+    let map = ShardMap::with_shard_count(8).unwrap();
+    for i in 0..300u32 {
+        map.insert(i, format!("region-{}", i % 3));
+    }
+
+    // 300 allocations become 3.
+    assert_eq!(map.coalesce_values(), 297);
+    for i in 0..300u32 {
+        let shared = map.get(&(i % 3)).unwrap();
+        assert!(Arc::ptr_eq(&map.get(&i).unwrap(), &shared));
+    }
+    assert_eq!(map.len(), 300);
+    assert_eq!(map.coalesce_values(), 0);
+
+    // Allocations still held outside the map are shared but not freed.
+    let map = ShardMap::new();
+    map.insert("a", 7u64);
+    map.insert("b", 7u64);
+    let held = (map.get(&"a").unwrap(), map.get(&"b").unwrap());
+    assert_eq!(map.coalesce_values(), 0);
+    assert!(Arc::ptr_eq(
+        &map.get(&"a").unwrap(),
+        &map.get(&"b").unwrap()
+    ));
+    assert_eq!((*held.0, *held.1), (7, 7));
+}
+
+#[test]
+fn test_retain_controlled() {
+    use std::ops::ControlFlow;
+
+    let map = ShardMap::with_shard_count(8).unwrap();
+    for i in 0..200u32 {
+        map.insert(i, i);
+    }
+    let mut decided = Vec::new();
+    map.retain_controlled(|k, v| {
+        if decided.len() == 50 {
+            return ControlFlow::Break(());
+        }
+        decided.push(*k);
+        *v += 1000;
+        ControlFlow::Continue(k % 2 == 0)
+    });
+
+    assert_eq!(decided.len(), 50);
+    let removed = decided.iter().filter(|k| *k % 2 == 1).count();
+    assert_eq!(map.len(), 200 - removed);
+    for i in 0..200u32 {
+        match map.get(&i) {
+            Some(v) if decided.contains(&i) => assert_eq!(*v, i + 1000),
+            Some(v) => assert_eq!(*v, i, "undecided entry {i} must be untouched"),
+            None => assert!(decided.contains(&i) && i % 2 == 1),
+        }
+    }
+
+    // Shared values are only replaced on `Continue(true)`; the entry `f` breaks on keeps
+    // its `Arc`.
+    let map = ShardMap::with_shard_count(1).unwrap();
+    for i in 0..4u32 {
+        map.insert(i, i);
+    }
+    let held: Vec<_> = (0..4u32).map(|i| map.get(&i).unwrap()).collect();
+    let mut calls = 0;
+    map.retain_controlled(|_, v| {
+        calls += 1;
+        *v += 100;
+        if calls == 2 {
+            return ControlFlow::Break(());
+        }
+        ControlFlow::Continue(true)
+    });
+    let replaced = (0..4u32)
+        .filter(|&i| !Arc::ptr_eq(&map.get(&i).unwrap(), &held[i as usize]))
+        .count();
+    assert_eq!(replaced, 1);
+    for i in 0..4u32 {
+        let v = map.get(&i).unwrap();
+        assert!(*v == i || *v == i + 100);
+        assert_eq!(*held[i as usize], i);
+    }
+}
+
+#[test]
+fn test_insert_presharded() {
+    let items: Vec<(u32, u32)> = (0..500).map(|i| (i, i * 7)).collect();
+
+    let expected: ShardMap<u32, u32> = ShardMap::with_shard_count(8).unwrap();
+    expected.overwrite_many(items.clone());
+
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(8).unwrap();
+    let mut groups: Vec<(usize, Vec<(u32, u32)>)> = (0..8).map(|idx| (idx, Vec::new())).collect();
+    for (k, v) in items {
+        groups[map.shard_for_key(&k)].1.push((k, v));
+    }
+    map.insert_presharded(groups).unwrap();
+
+    assert_eq!(map.shard_loads(), expected.shard_loads());
+    for k in 0..500u32 {
+        assert_eq!(map.get(&k), expected.get(&k));
+    }
+
+    assert_eq!(
+        map.insert_presharded(vec![(0, vec![(1000, 1)]), (8, vec![(1001, 1)])]),
+        Err(Error::InvalidShardIndex)
+    );
+    assert!(!map.contains_key(&1000));
+}
+
+#[test]
+fn test_hasher_for_key() {
+    let map = ShardMapBuilder::new()
+        .fixed_seed(7)
+        .build::<String, u32>()
+        .unwrap();
+    let hash = map.hasher_for_key::<str>();
+    for i in 0..100 {
+        let key = format!("item-{i}");
+        assert_eq!(hash(&key), map.hash_for_key(key.as_str()));
+        assert_eq!(map.shard_for_hash(hash(&key)), map.shard_for_key(&key));
+    }
+
+    // The closure outlives the map and works across threads.
+    let expected = map.hash_for_key("x");
+    drop(map);
+    let from_thread = std::thread::spawn(move || hash("x")).join().unwrap();
+    assert_eq!(from_thread, expected);
+}
+
+#[test]
+fn test_migrate_key() {
+    let map: ShardMap<String, u32> = ShardMap::with_shard_count(4).unwrap();
+    let key = "tenant:7".to_string();
+    map.insert(key.clone(), 7);
+    let home = map.shard_for_key(&key);
+    let target = (home + 1) % 4;
+
+    map.migrate_key(&key, target).unwrap();
+    let mut loads = vec![0; 4];
+    loads[target] = 1;
+    assert_eq!(map.shard_loads(), loads);
+    // Default routing no longer finds it.
+    assert!(map.get(&key).is_none());
+    assert_eq!(map.migrate_key(&key, home), Err(Error::KeyNotFound));
+
+    assert_eq!(map.migrate_key(&key, 4), Err(Error::InvalidShardIndex));
+    assert_eq!(
+        map.migrate_key(&"missing".to_string(), target),
+        Err(Error::KeyNotFound)
+    );
+
+    // Routing that follows the placement finds it again.
+    let other = "tenant:8".to_string();
+    map.insert(other.clone(), 8);
+    let other_home = map.shard_for_key(&other);
+    map.migrate_key(&other, other_home).unwrap();
+    assert_eq!(*map.get(&other).unwrap(), 8);
+}
+
+#[test]
+fn test_migrate_key_into_full_shard() {
+    let map = ShardMapBuilder::new()
+        .shard_count(2)
+        .unwrap()
+        .max_entries_per_shard(1)
+        .on_full(OnFull::Reject)
+        .build::<u32, u32>()
+        .unwrap();
+    let a = (0..).find(|k| map.shard_for_key(k) == 0).unwrap();
+    let b = (0..).find(|k| map.shard_for_key(k) == 1).unwrap();
+    map.insert(a, 1);
+    map.insert(b, 2);
+    assert_eq!(map.migrate_key(&a, 1), Err(Error::ShardFull));
+    assert_eq!(map.shard_loads(), vec![1, 1]);
+    assert_eq!(*map.get(&a).unwrap(), 1);
+}
+
+#[test]
+fn test_load_rates() {
+    use std::time::Duration;
+
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(4).unwrap();
+    for k in 0..400 {
+        map.insert(k, k);
+    }
+    let loads = map.shard_loads();
+    let prev: Vec<usize> = loads.iter().map(|&l| l - 10).collect();
+    let rates = map.load_rates(&prev, Duration::from_secs(2)).unwrap();
+    assert_eq!(rates, vec![5.0; 4]);
+
+    // Shrunk shards report a negative rate.
+    let grown: Vec<usize> = loads.iter().map(|&l| l + 20).collect();
+    let rates = map.load_rates(&grown, Duration::from_millis(500)).unwrap();
+    assert_eq!(rates, vec![-40.0; 4]);
+
+    assert_eq!(
+        map.load_rates(&[0, 0], Duration::from_secs(1)),
+        Err(Error::ShardCountMismatch)
+    );
+}
+
+#[test]
+fn test_set_api_dedup_across_threads() {
+    let set: Arc<ShardMap<u32, ()>> = Arc::new(ShardMap::new());
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let set = Arc::clone(&set);
+            std::thread::spawn(move || (0..1000).filter(|&k| set.set_insert(k)).count())
+        })
+        .collect();
+    let added: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    // Every key is reported as new exactly once across all threads.
+    assert_eq!(added, 1000);
+    assert_eq!(set.len(), 1000);
+
+    assert!(set.set_contains(&999));
+    assert!(!set.set_contains(&1000));
+    assert!(set.set_remove(&999));
+    assert!(!set.set_remove(&999));
+    assert!(!set.set_contains(&999));
+    assert_eq!(set.len(), 999);
+}
+
+#[cfg(feature = "deterministic")]
+#[test]
+fn test_deterministic_iteration_order() {
+    let build = || {
+        let map = ShardMapBuilder::new()
+            .shard_count(4)
+            .unwrap()
+            .deterministic()
+            .build::<String, u32>()
+            .unwrap();
+        for i in 0..500 {
+            map.insert(format!("key-{i}"), i);
+        }
+        for i in (0..500).step_by(7) {
+            map.remove(&format!("key-{i}"));
+        }
+        map
+    };
+    let order = |map: &ShardMap<String, u32>| -> Vec<String> {
+        map.iter_snapshot().map(|(k, _)| k).collect()
+    };
+    let (a, b) = (build(), build());
+    assert_eq!(order(&a), order(&b));
+    assert_eq!(a.shard_loads(), b.shard_loads());
+    assert!(a.routing_descriptor().is_reproducible());
+}
+
+#[test]
+fn test_split_shards() {
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(8).unwrap();
+    for k in 0..1000 {
+        map.insert(k, k * 2);
+    }
+    let loads = map.shard_loads();
+    let (low, high) = map.split_shards(3);
+    assert_eq!(low.shard_loads(), loads[..3]);
+    assert_eq!(high.shard_loads(), loads[3..]);
+    assert_eq!(low.len() + high.len(), 1000);
+
+    for half in [&low, &high] {
+        for (key, value) in half.iter_snapshot() {
+            assert!(half.shard_for_key(&key) < half.shard_count());
+            assert_eq!(half.get(&key), Some(value));
+        }
+    }
+
+    // A foreign key still lands in range, and the halves can be split again.
+    let foreign = high.iter_snapshot().next().unwrap().0;
+    low.insert(foreign, 0);
+    assert!(low.shard_for_key(&foreign) < 3);
+    let (a, b) = high.split_shards(2);
+    assert_eq!((a.shard_count(), b.shard_count()), (2, 3));
+    assert!(b.iter_snapshot().all(|(k, v)| b.get(&k) == Some(v)));
+}
+
+#[test]
+fn test_split_halves_refuse_default_routing() {
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(16).unwrap();
+    for k in 0..300 {
+        map.insert(k, k);
+    }
+    let (mut low, high) = map.split_shards(5);
+    let loads = low.shard_loads();
+    assert_eq!(
+        low.set_routing(RoutingConfig::Default),
+        Err(Error::InvalidShardCount)
+    );
+    assert_eq!(low.shard_loads(), loads);
+    assert!(low.iter_snapshot().all(|(k, v)| low.get(&k) == Some(v)));
+
+    // Modulo routing is fine on any count; a power-of-two half can go back to the default.
+    low.set_routing(RoutingConfig::from_fn(|hash, n| (hash % n as u64) as usize))
+        .unwrap();
+    assert!(low.iter_snapshot().all(|(k, v)| low.get(&k) == Some(v)));
+    let (mut eight, _) = high.split_shards(8);
+    eight.set_routing(RoutingConfig::Default).unwrap();
+    assert!(eight.iter_snapshot().all(|(k, v)| eight.get(&k) == Some(v)));
+}
+
+#[test]
+#[should_panic(expected = "split pivot")]
+fn test_split_shards_rejects_edge_pivot() {
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(4).unwrap();
+    let _ = map.split_shards(4);
+}
+
+#[test]
+fn test_for_each_mut_unique_and_shared() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    for k in 0..100 {
+        map.insert(k, k);
+    }
+    // All unique: nothing skipped.
+    assert_eq!(map.try_for_each_mut(|_, v| *v += 1), 0);
+    assert!((0..100).all(|k| *map.get(&k).unwrap() == k + 1));
+
+    // Shared values are skipped by try_for_each_mut...
+    let held: Vec<_> = (0..10).map(|k| map.get(&k).unwrap()).collect();
+    assert_eq!(map.try_for_each_mut(|_, v| *v *= 10), 10);
+    assert_eq!(*map.get(&5).unwrap(), 6);
+    assert_eq!(*map.get(&50).unwrap(), 510);
+
+    // ...and cloned by for_each_mut, leaving outside holders untouched.
+    map.for_each_mut(|_, v| *v += 1);
+    assert_eq!(*map.get(&5).unwrap(), 7);
+    assert_eq!(*held[5], 6);
+    assert_eq!(*map.get(&50).unwrap(), 511);
+}
+
+#[test]
+fn test_diagnostics_delta() {
+    use std::time::Duration;
+
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(4).unwrap();
+    for k in 0..100 {
+        map.insert(k, k);
+    }
+    let earlier = map.diagnostics_snapshot();
+    for k in 100..150 {
+        map.insert(k, k);
+    }
+    for k in 0..20 {
+        map.remove(&k);
+    }
+    for k in 0..10 {
+        map.get(&k);
+    }
+    let mut later = map.diagnostics_snapshot();
+    later.taken_at = earlier.taken_at + Duration::from_secs(2);
+
+    let delta = later.delta(&earlier);
+    assert_eq!(delta.elapsed, Duration::from_secs(2));
+    assert_eq!(delta.entry_change(), 30);
+    let expected: Vec<i64> = later
+        .loads
+        .iter()
+        .zip(&earlier.loads)
+        .map(|(&a, &b)| a as i64 - b as i64)
+        .collect();
+    assert_eq!(delta.load_changes, expected);
+    let rates = delta.load_rates();
+    assert_eq!(rates.iter().sum::<f64>(), 15.0);
+
+    let sum = |f: fn(&shardmap::ShardOps) -> u64| delta.operations.iter().map(f).sum::<u64>();
+    if cfg!(feature = "metrics") {
+        assert_eq!(sum(|o| o.writes), 50);
+        assert_eq!(sum(|o| o.removes), 20);
+        assert_eq!(sum(|o| o.get_misses), 10);
+        assert_eq!(delta.ops_per_sec(), (sum(|o| o.reads) + 70) as f64 / 2.0);
+    } else {
+        assert_eq!(delta.ops_per_sec(), 0.0);
+    }
+}
+
+#[test]
+fn test_get_or_insert_then() {
+    let map: ShardMap<&str, Vec<u32>> = ShardMap::new();
+    let v = map.get_or_insert_then("k", vec![1], |v| v.push(2));
+    assert_eq!(*v, [1, 2]);
+    assert_eq!(*map.get(&"k").unwrap(), [1, 2]);
+
+    // Present: the default is ignored and the stored value is mutated copy-on-write.
+    let held = map.get(&"k").unwrap();
+    let v = map.get_or_insert_then("k", vec![9], |v| v.push(3));
+    assert_eq!(*v, [1, 2, 3]);
+    assert_eq!(*held, [1, 2]);
+
+    // Entry guard: insert then edit under the still-held lock.
+    {
+        let mut entry = map.entry("e").or_insert(vec![]);
+        entry.get_mut().push(7);
+    }
+    assert_eq!(*map.get(&"e").unwrap(), [7]);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_get_or_insert_then_single_lock() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    let shard = map.shard_for_key(&1);
+    let locks = |map: &ShardMap<u32, u32>| map.stats().operations[shard].lock_acquisitions;
+    // `stats()` itself locks each shard once to read its length.
+    let before = locks(&map);
+    map.get_or_insert_then(1, 10, |v| *v += 1);
+    assert_eq!(locks(&map) - before, 2);
+    let before = locks(&map);
+    assert_eq!(*map.get_or_insert_then(1, 10, |v| *v += 1), 12);
+    assert_eq!(locks(&map) - before, 2);
+}
+
+#[test]
+fn test_shard_count_helpers() {
+    use shardmap::{round_shard_count, valid_shard_count};
+
+    for (n, rounded) in [(0, 1), (1, 1), (2, 2), (7, 8), (8, 8), (9, 16)] {
+        assert_eq!(round_shard_count(n), rounded, "round {n}");
+        assert_eq!(valid_shard_count(n), n == rounded, "valid {n}");
+        assert!(ShardMap::<u32, u32>::with_shard_count(round_shard_count(n)).is_ok());
+    }
+    assert_eq!(round_shard_count(usize::MAX), 1 << (usize::BITS - 1));
+}
+
+#[test]
+fn test_retain_shuffled_matches_retain() {
+    let build = || {
+        let map: ShardMap<u32, u32> = ShardMap::with_shard_count(16).unwrap();
+        for k in 0..2000 {
+            map.insert(k, k);
+        }
+        map
+    };
+    let ordered = build();
+    let shuffled = build();
+    ordered.retain(|k, v| {
+        *v += 1;
+        k % 3 != 0
+    });
+    let mut calls = 0;
+    shuffled.retain_shuffled(|k, v| {
+        calls += 1;
+        *v += 1;
+        k % 3 != 0
+    });
+    assert_eq!(calls, 2000);
+
+    let sorted = |map: &ShardMap<u32, u32>| {
+        let mut entries: Vec<(u32, u32)> = map.iter_snapshot().map(|(k, v)| (k, *v)).collect();
+        entries.sort_unstable();
+        entries
+    };
+    assert_eq!(sorted(&shuffled), sorted(&ordered));
+    assert_eq!(shuffled.len(), 2000 - 667);
+}
+
+#[test]
+fn test_get_and_insert_timeout() {
+    use shardmap::{Entry, TimedOut};
+    use std::time::{Duration, Instant};
+
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(1).unwrap();
+    map.insert(1, 10);
+
+    // Fast path: uncontended.
+    let timeout = Duration::from_millis(20);
+    assert_eq!(map.get_timeout(&1, timeout).unwrap().as_deref(), Some(&10));
+    assert_eq!(map.insert_timeout(2, 20, timeout), Ok(None));
+
+    // An entry holds the only shard's write lock.
+    let entry = map.entry(3);
+    assert!(matches!(entry, Entry::Vacant(_)));
+    let started = Instant::now();
+    assert_eq!(map.get_timeout(&1, timeout), Err(TimedOut));
+    assert_eq!(map.insert_timeout(4, 40, timeout), Err(TimedOut));
+    assert!(started.elapsed() >= timeout * 2);
+    drop(entry);
+
+    assert!(!map.contains_key(&4));
+    assert_eq!(*map.insert_timeout(2, 21, timeout).unwrap().unwrap(), 20);
+}
+
+#[test]
+fn test_top_values_by() {
+    let scores: ShardMap<u32, i64> = ShardMap::with_shard_count(8).unwrap();
+    for player in 0..500u32 {
+        // A permutation of 0..500 so scores are distinct.
+        scores.insert(player, ((player * 7919) % 500) as i64);
+    }
+    let top = scores.top_values_by(3, |s| *s);
+    let ranked: Vec<i64> = top.iter().map(|(_, v)| **v).collect();
+    assert_eq!(ranked, vec![499, 498, 497]);
+    for (player, score) in &top {
+        assert_eq!(*scores.get(player).unwrap(), **score);
+    }
+
+    // Negated score ranks the lowest; n larger than the map returns everything.
+    let bottom = scores.top_values_by(2, |s| -s);
+    assert_eq!(bottom.iter().map(|(_, v)| **v).collect::<Vec<_>>(), [0, 1]);
+    assert_eq!(scores.top_values_by(1000, |s| *s).len(), 500);
+    assert!(scores.top_values_by(0, |s| *s).is_empty());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_drain_filter_matches_serial() {
+    let build = || {
+        let map: ShardMap<u32, u32> = ShardMap::with_shard_count(16).unwrap();
+        for k in 0..5000 {
+            map.insert(k, k % 97);
+        }
+        map
+    };
+    let serial = build();
+    let parallel = build();
+    let pred = |k: &u32, v: &Arc<u32>| **v < 40 || k % 5 == 1;
+
+    let mut expected = serial.drain_filter(pred);
+    let mut removed = parallel.par_drain_filter(pred);
+    expected.sort_unstable_by_key(|(k, _)| *k);
+    removed.sort_unstable_by_key(|(k, _)| *k);
+    assert_eq!(removed, expected);
+    assert_eq!(parallel.len(), serial.len());
+    assert!(parallel.iter_snapshot().all(|(k, v)| !pred(&k, &v)));
+}
+
+#[test]
+fn test_shared_hasher_across_maps() {
+    let values = ShardMapBuilder::new()
+        .fixed_seed(0xfeed)
+        .build::<String, u64>()
+        .unwrap();
+    let hasher = values.shared_hasher();
+    let index = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .shared_hasher(Arc::clone(&hasher))
+        .build::<String, u64>()
+        .unwrap();
+    assert!(Arc::ptr_eq(&index.shared_hasher(), &hasher));
+    for i in 0..100 {
+        let key = format!("user:{i}");
+        assert_eq!(values.hash_for_key(&key), index.hash_for_key(&key));
+    }
+    // The shared hasher wins over a conflicting seed.
+    let other = ShardMapBuilder::new()
+        .fixed_seed(1)
+        .shared_hasher(hasher)
+        .build::<String, u64>()
+        .unwrap();
+    assert_eq!(other.hash_for_key("k"), values.hash_for_key("k"));
+    assert_eq!(other.routing_descriptor().seed, Some(0xfeed));
+}
+
+#[test]
+fn test_iter_like_std_map() {
+    let map: ShardMap<String, usize> = ShardMap::new();
+    for word in ["a", "bb", "ccc"] {
+        map.insert(word.to_string(), word.len());
+    }
+    let mut seen: Vec<(String, usize)> = map.iter().map(|(k, v)| (k, *v)).collect();
+    seen.sort();
+    assert_eq!(seen, [("a".into(), 1), ("bb".into(), 2), ("ccc".into(), 3)]);
+
+    // No lock is held while iterating, so the body can write to the map.
+    for (key, value) in map.iter() {
+        map.insert(key, *value * 10);
+    }
+    assert_eq!(map.iter().map(|(_, v)| *v).sum::<usize>(), 60);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_retain_counts_removes() {
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    for k in 0..1000 {
+        map.insert(k, k);
+    }
+    let removes = |map: &ShardMap<u32, u32>| -> u64 {
+        map.stats().operations.iter().map(|o| o.removes).sum()
+    };
+    let ops = |map: &ShardMap<u32, u32>| map.diagnostics().total_operations;
+
+    let (before, ops_before) = (removes(&map), ops(&map));
+    map.retain(|k, _| k % 4 != 0);
+    assert_eq!(removes(&map) - before, 250);
+    assert_eq!(ops(&map) - ops_before, 250);
+
+    let before = removes(&map);
+    let dropped = map.retain_collecting(|k, _| *k < 500);
+    assert_eq!(removes(&map) - before, dropped.len() as u64);
+    let before = removes(&map);
+    let drained = map.drain_filter(|k, _| k % 3 == 1);
+    assert_eq!(removes(&map) - before, drained.len() as u64);
+}
+
+#[test]
+fn test_is_degenerate() {
+    // Zero-sized keys all route to one shard.
+    let unit: ShardMap<(), u32> = ShardMap::new();
+    assert!(unit.is_degenerate());
+    unit.insert((), 1);
+    assert!(unit.is_degenerate());
+
+    #[derive(Hash, PartialEq, Eq)]
+    struct Marker;
+    let marker: ShardMap<Marker, u32> = ShardMap::with_shard_count(4).unwrap();
+    assert!(marker.is_degenerate());
+
+    // Healthy map.
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(4).unwrap();
+    assert!(!map.is_degenerate());
+    for k in 0..100 {
+        map.insert(k, k);
+    }
+    assert!(!map.is_degenerate());
+
+    // A router that sends everything to one shard is caught by load concentration.
+    let skewed = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .routing_fn(|_, _| 2)
+        .build::<u32, u32>()
+        .unwrap();
+    skewed.insert(1, 1);
+    assert!(!skewed.is_degenerate());
+    for k in 0..10 {
+        skewed.insert(k, k);
+    }
+    assert!(skewed.is_degenerate());
+}
+
+#[test]
+fn test_iter_concurrent_outlives_map() {
+    let map: ShardMap<u32, String> = ShardMap::with_shard_count(4).unwrap();
+    for k in 0..100 {
+        map.insert(k, k.to_string());
+    }
+    let mut iter = map.iter_concurrent();
+    let first = iter.next().unwrap();
+    drop(map);
+    let mut keys: Vec<u32> = std::iter::once(first).chain(iter).map(|(k, _)| k).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, (0..100).collect::<Vec<_>>());
+
+    // 'static: the iterator can move to another thread on its own.
+    let map: ShardMap<u32, u32> = ShardMap::new();
+    map.insert(1, 1);
+    let iter = map.iter_concurrent();
+    let handle = std::thread::spawn(move || iter.count());
+    assert_eq!(handle.join().unwrap(), 1);
+
+    // Consuming the map while an iterator is alive moves the entries out from under it.
+    let map: ShardMap<u32, u32> = ShardMap::with_shard_count(2).unwrap();
+    for k in 0..10 {
+        map.insert(k, k);
+    }
+    let iter = map.iter_concurrent();
+    assert_eq!(map.into_sorted_vec().len(), 10);
+    assert_eq!(iter.count(), 0);
+}
+
+#[test]
+fn test_probe_stats() {
+    let map = ShardMap::with_shard_count(8).unwrap();
+    for i in 0..1000 {
+        map.insert(i, i);
+    }
+    let probes = map.probe_stats();
+    assert_eq!(probes.len(), map.shard_count());
+    assert_eq!(probes.iter().map(|p| p.len).sum::<usize>(), 1000);
+    for p in &probes {
+        assert!((0.0..=1.0).contains(&p.load_factor));
+        assert!(p.estimated_probe_length >= 1.0);
+        assert!(p.len <= p.capacity);
+    }
+    let empty: ShardMap<u32, u32> = ShardMap::with_shard_count(4).unwrap();
+    for p in empty.probe_stats() {
+        assert_eq!(p.load_factor, 0.0);
+        assert_eq!(p.estimated_probe_length, 1.0);
+    }
+}
+
+#[test]
+fn test_rebuild_shard() {
+    let map = ShardMap::with_shard_count(4).unwrap();
+    let target = map.shard_for_key(&0u64);
+    for round in 0..20u64 {
+        for i in 0..500u64 {
+            map.insert(round * 500 + i, i);
+        }
+        for i in 0..500u64 {
+            if round < 19 || !i.is_multiple_of(10) {
+                map.remove(&(round * 500 + i));
+            }
+        }
+    }
+    let before: Vec<(u64, u64)> = {
+        let mut v: Vec<_> = map.iter().map(|(k, v)| (k, *v)).collect();
+        v.sort_unstable();
+        v
+    };
+    let capacity_before = map.probe_stats()[target].capacity;
+
+    map.rebuild_shard(target).unwrap();
+
+    let mut after: Vec<_> = map.iter().map(|(k, v)| (k, *v)).collect();
+    after.sort_unstable();
+    assert_eq!(after, before);
+    let probe = map.probe_stats()[target];
+    assert!(probe.capacity <= capacity_before);
+    assert!(probe.capacity >= probe.len);
+    for (k, v) in &before {
+        assert_eq!(*map.get(k).unwrap(), *v);
+    }
+    assert!(matches!(
+        map.rebuild_shard(4),
+        Err(Error::InvalidShardIndex)
+    ));
+}
+
+#[test]
+fn test_get_or_insert_with_panicking_factory() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let map: ShardMap<u32, u32> = ShardMapBuilder::new()
+        .shard_count(1)
+        .unwrap()
+        .max_entries_per_shard(2)
+        .on_full(OnFull::EvictOldest)
+        .build()
+        .unwrap();
+    map.insert(1, 10);
+    map.insert(2, 20);
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        map.get_or_insert_with(3, || panic!("factory failed"))
+    }));
+    assert!(result.is_err());
+    assert!(map.get(&3).is_none());
+    // Nothing was evicted for the value that never arrived.
+    assert_eq!(map.len(), 2);
+    assert_eq!(*map.get(&1).unwrap(), 10);
+
+    // The shard lock was released and the shard still works.
+    assert_eq!(*map.get_or_insert_with(3, || 30), 30);
+    map.insert(4, 40);
+    assert_eq!(map.len(), 2);
+    assert_eq!(*map.get(&4).unwrap(), 40);
+}
+
+#[test]
+fn test_len_hint_and_concurrent_size_hint() {
+    let map = ShardMap::with_shard_count(8).unwrap();
+    assert_eq!(map.len_hint(), 0);
+    for i in 0..500 {
+        map.insert(i, i);
+    }
+    for i in 0..100 {
+        map.remove(&i);
+    }
+    assert_eq!(map.len_hint(), map.len());
+
+    let mut iter = map.iter_concurrent();
+    assert_eq!(iter.size_hint(), (0, None));
+    iter.next().unwrap();
+    let (lower, upper) = iter.size_hint();
+    assert!((1..=399).contains(&lower));
+    assert_eq!(upper, None);
+    let rest: Vec<_> = iter.collect();
+    assert_eq!(rest.len(), 399);
+
+    let mut map = map;
+    map.rebuild(|_, entries| entries.clear());
+    assert_eq!(map.len_hint(), 0);
+}
+
+#[test]
+fn test_copy_shard_map_inline_values() {
+    let map = ShardMapBuilder::new()
+        .shard_count(8)
+        .unwrap()
+        .inline_values()
+        .build::<u64, u64>()
+        .unwrap();
+    assert_eq!(map.shard_count(), 8);
+    for i in 0..200 {
+        assert_eq!(map.insert(i, i * 2), None);
+    }
+    assert_eq!(map.insert(7, 70), Some(14));
+    assert_eq!(map.get(&7), Some(70));
+    assert_eq!(map.get(&1000), None);
+    assert!(map.contains_key(&199));
+
+    assert_eq!(map.update(&7, |v| *v += 1), Some(71));
+    assert_eq!(map.update(&1000, |v| *v += 1), None);
+    assert_eq!(map.get_or_insert(7, 0), 71);
+    assert_eq!(map.get_or_insert(1000, 5), 5);
+
+    assert_eq!(map.remove(&1000), Some(5));
+    assert_eq!(map.len(), 200);
+    assert_eq!(map.shard_loads().iter().sum::<usize>(), 200);
+    assert_eq!(map.iter_snapshot().count(), 200);
+
+    // Routing matches an Arc-based map from the same config.
+    let seeded = || {
+        ShardMapBuilder::new()
+            .shard_count(8)
+            .unwrap()
+            .fixed_seed(42)
+    };
+    let inline = seeded().inline_values().build::<u64, u64>().unwrap();
+    let boxed = seeded().build::<u64, u64>().unwrap();
+    for k in 0..100 {
+        assert_eq!(inline.shard_for_key(&k), boxed.shard_for_key(&k));
+    }
+
+    map.clear();
+    assert!(map.is_empty());
+    assert!(matches!(
+        ShardMapBuilder::new()
+            .max_entries_per_shard(4)
+            .inline_values()
+            .build::<u64, u64>(),
+        Err(Error::BoundNotSupported)
+    ));
+}
+
+#[test]
+fn test_get_map_returns_only_found() {
+    let map = ShardMap::with_shard_count(8).unwrap();
+    for i in 0..50u32 {
+        map.insert(i.to_string(), i);
+    }
+    let wanted = ["3", "17", "49", "50", "nope", "3"];
+    let found = map.get_map(wanted.iter().copied());
+    assert_eq!(found.len(), 3);
+    assert_eq!(*found["3"], 3);
+    assert_eq!(*found["17"], 17);
+    assert_eq!(*found["49"], 49);
+    assert!(!found.contains_key("50"));
+    assert!(!found.contains_key("nope"));
+    assert!(map.get_map(std::iter::empty::<&str>()).is_empty());
+}
+
+#[test]
+fn test_try_reserve() {
+    use shardmap::TryReserveError;
+
+    let map: ShardMap<u64, u64> = ShardMap::with_shard_count(4).unwrap();
+    map.try_reserve(1000).unwrap();
+    assert!(map.capacity() >= 1000);
+    for shard in map.probe_stats() {
+        assert!(shard.capacity >= 250);
+    }
+    assert!(matches!(
+        map.try_reserve(usize::MAX),
+        Err(TryReserveError::CapacityOverflow)
+    ));
+    map.insert(1, 1);
+    assert_eq!(*map.get(&1).unwrap(), 1);
+}
+
+#[test]
+fn test_operations_snapshot_matches_stats() {
+    let map = ShardMap::with_shard_count(4).unwrap();
+    for i in 0..100 {
+        map.insert(i, i);
+    }
+    for i in 0..50 {
+        map.get(&i);
+    }
+    map.remove(&3);
+    let stats = map.stats();
+    let ops = map.operations_snapshot();
+    assert_eq!(ops.len(), map.shard_count());
+    assert_eq!(ops, stats.operations);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_operations_snapshot_takes_no_locks() {
+    let map = ShardMap::with_shard_count(4).unwrap();
+    map.insert(1, 1);
+    let before = map.operations_snapshot();
+    let after = map.operations_snapshot();
+    assert_eq!(before, after);
+    assert_eq!(after.iter().map(|o| o.writes).sum::<u64>(), 1);
+}
+
+#[test]
+fn test_from_pairs_presized() {
+    let pairs: Vec<(u64, u64)> = (0..10_000).map(|i| (i, i * 3)).collect();
+    let map = ShardMap::from_pairs(pairs, 8).unwrap();
+    assert_eq!(map.len(), 10_000);
+    assert_eq!(map.shard_count(), 8);
+    for i in (0..10_000).step_by(97) {
+        assert_eq!(*map.get(&i).unwrap(), i * 3);
+    }
+    // A table that grew during the load would hold at least twice its presized target.
+    let target = 10_000usize.div_ceil(8);
+    for shard in map.probe_stats() {
+        assert!(shard.capacity >= shard.len);
+        assert!(shard.capacity < 2 * shard.len.max(target));
+    }
+
+    let dup = ShardMap::from_pairs(vec![("k", 1), ("k", 2)], 2).unwrap();
+    assert_eq!(dup.len(), 1);
+    assert_eq!(*dup.get(&"k").unwrap(), 2);
+    assert!(matches!(
+        ShardMap::<u64, u64>::from_pairs(Vec::new(), 3),
+        Err(Error::InvalidShardCount)
+    ));
+}
+
+#[cfg(feature = "bloom")]
+#[test]
+fn test_build_bloom() {
+    let map = ShardMap::new();
+    for i in 0..10_000u64 {
+        map.insert(i, i);
+    }
+    let bloom = map.build_bloom(0.01);
+    for i in 0..10_000u64 {
+        assert!(bloom.might_contain(&i), "false negative for {}", i);
+    }
+    let false_positives = (10_000..110_000u64)
+        .filter(|i| bloom.might_contain(i))
+        .count();
+    let rate = false_positives as f64 / 100_000.0;
+    assert!(rate < 0.02, "false-positive rate {} too high", rate);
+
+    // Point in time: later inserts aren't reflected.
+    map.insert(u64::MAX, 0);
+    let fresh = map.build_bloom(0.01);
+    assert!(fresh.might_contain(&u64::MAX));
+}
+
+#[cfg(feature = "bloom")]
+#[test]
+fn test_build_bloom_normalizes_lookups() {
+    let map = ShardMapBuilder::new()
+        .key_normalizer(Arc::new(|k: &String| k.to_lowercase()))
+        .build()
+        .unwrap();
+    for i in 0..1_000 {
+        map.insert(format!("Key{}", i), i);
+    }
+    let bloom = map.build_bloom(0.01);
+    for i in 0..1_000 {
+        assert!(
+            bloom.might_contain(&format!("KEY{}", i)),
+            "false negative for {}",
+            i
+        );
+        assert!(bloom.might_contain(&format!("key{}", i)));
+    }
+}
+
+#[test]
+fn test_merge_from_keep_max() {
+    let map = ShardMap::with_shard_count(4).unwrap();
+    for i in 0..20u32 {
+        map.insert(i, i * 10);
+    }
+    let updates = (10..30u32).map(|i| (i, 150)).chain([(29, 400), (29, 100)]);
+    let resolved = map.merge_from(updates, |_, old, new| new.max(*old));
+    // Keys 10..20 existed, and key 29 was resolved twice against its own earlier value.
+    assert_eq!(resolved, 12);
+    assert_eq!(map.len(), 30);
+    for i in 0..10 {
+        assert_eq!(*map.get(&i).unwrap(), i * 10);
+    }
+    for i in 10..20 {
+        assert_eq!(*map.get(&i).unwrap(), (i * 10).max(150));
+    }
+    for i in 20..29 {
+        assert_eq!(*map.get(&i).unwrap(), 150);
+    }
+    assert_eq!(*map.get(&29).unwrap(), 400);
+}
+
+#[test]
+fn test_router_uniformity() {
+    let map: ShardMap<u64, ()> = ShardMap::with_shard_count(16).unwrap();
+    let score = map.router_uniformity(|i| (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15), 50_000);
+    assert!(score > 0.99, "default router scored {}", score);
+
+    struct AllToZero;
+    impl ShardRouter for AllToZero {
+        fn route(&self, _key_hash: u64, _shard_count: usize) -> usize {
+            0
+        }
+    }
+    let skewed = ShardMapBuilder::new()
+        .shard_count(16)
+        .unwrap()
+        .routing(RoutingConfig::Custom(Box::new(AllToZero)))
+        .build::<u64, ()>()
+        .unwrap();
+    let score = skewed.router_uniformity(|i| i as u64, 50_000);
+    assert!(score < 0.01, "AllToZero scored {}", score);
+    assert!(skewed.is_empty());
+
+    let single: ShardMap<u64, ()> = ShardMap::with_shard_count(1).unwrap();
+    assert_eq!(single.router_uniformity(|i| i as u64, 100), 1.0);
+}
+
+#[test]
+fn test_insert_classified() {
+    use shardmap::InsertOutcome;
+
+    let map = ShardMap::new();
+    let first = match map.insert_classified("k", 1) {
+        InsertOutcome::Inserted(new) => new,
+        other => panic!("expected Inserted, got {:?}", other),
+    };
+    assert!(map.holds_same(&"k", &first));
+
+    match map.insert_classified("k", 2) {
+        InsertOutcome::Updated { old, new } => {
+            assert!(Arc::ptr_eq(&old, &first));
+            assert_eq!(*new, 2);
+            assert!(map.holds_same(&"k", &new));
+        }
+        other => panic!("expected Updated, got {:?}", other),
+    }
+    map.remove(&"k");
+    assert_eq!(
+        map.insert_classified("k", 3),
+        InsertOutcome::Inserted(Arc::new(3))
+    );
+}
+
+#[test]
+fn test_get_or_try_insert_single_flight() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    // Concurrent callers sharing one fill are covered by the unit tests, which can hold the
+    // leader until every follower has joined.
+    let map: ShardMap<&str, String> = ShardMap::new();
+
+    // An error is returned but not cached; the next call fills again.
+    let err = map.get_or_try_insert_single_flight("key", || Err("backend down".to_string()));
+    assert_eq!(err, Err("backend down".to_string()));
+    assert!(!map.contains_key(&"key"));
+    let value = map.get_or_try_insert_single_flight("key", || Ok::<_, String>("value".into()));
+    let stored = map.get(&"key").unwrap();
+    assert!(Arc::ptr_eq(&value.unwrap(), &stored));
+    let again =
+        map.get_or_try_insert_single_flight("key", || -> Result<String, String> { unreachable!() });
+    assert!(Arc::ptr_eq(&again.unwrap(), &stored));
+
+    // A panicking leader doesn't wedge the key.
+    let panicked = catch_unwind(AssertUnwindSafe(|| {
+        map.get_or_try_insert_single_flight("other", || -> Result<String, String> {
+            panic!("factory panicked")
+        })
+    }));
+    assert!(panicked.is_err());
+    let value = map.get_or_try_insert_single_flight("other", || Ok::<_, String>("ok".into()));
+    assert_eq!(value.unwrap().as_str(), "ok");
+}
+
+#[test]
+fn test_pick_keys_in_distinct_shards() {
+    let map: ShardMap<u64, u64> = ShardMap::with_shard_count(8).unwrap();
+    let keys = map.pick_keys_in_distinct_shards(8, |i| i as u64).unwrap();
+    assert_eq!(keys.len(), 8);
+    let mut shards: Vec<usize> = keys.iter().map(|k| map.shard_for_key(k)).collect();
+    shards.sort_unstable();
+    assert_eq!(shards, (0..8).collect::<Vec<_>>());
+    assert!(map.is_empty());
+    assert!(map.pick_keys_in_distinct_shards(9, |i| i as u64).is_none());
+
+    struct AllToZero;
+    impl ShardRouter for AllToZero {
+        fn route(&self, _key_hash: u64, _shard_count: usize) -> usize {
+            0
+        }
+    }
+    let skewed = ShardMapBuilder::new()
+        .shard_count(4)
+        .unwrap()
+        .routing(RoutingConfig::Custom(Box::new(AllToZero)))
+        .build::<u64, u64>()
+        .unwrap();
+    assert_eq!(
+        skewed.pick_keys_in_distinct_shards(1, |i| i as u64),
+        Some(vec![0])
+    );
+    assert!(skewed
+        .pick_keys_in_distinct_shards(2, |i| i as u64)
+        .is_none());
+}